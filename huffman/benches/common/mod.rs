@@ -0,0 +1,29 @@
+// What the benches share, pulled into each of them with `mod common;`
+
+use std::time::Duration;
+
+pub fn throughput(len: usize, time: Duration) -> f64 { len as f64 / time.as_secs_f64() / 1e6 }
+
+// stitch together random pieces of the text with the odd random byte, so every byte value shows up
+// and some codes end up long
+pub fn generate_corpus(text: &[u8], len: usize) -> Vec<u8>
+{
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut corpus = Vec::with_capacity(len);
+    while corpus.len() < len
+    {
+        let piece = 16 + next() as usize % 240;
+        let start = next() as usize % (text.len() - piece);
+        corpus.extend_from_slice(&text[start..start + piece]);
+        corpus.push(next() as u8);
+    }
+    corpus.truncate(len);
+    corpus
+}
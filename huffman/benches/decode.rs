@@ -0,0 +1,82 @@
+// Compares decoding with the lookup table against walking the tree a bit at a time
+//
+// run with `cargo bench --bench decode`
+mod common;
+
+use common::generate_corpus;
+use common::throughput;
+use huffman::file_lib::bit_reader::BitReader;
+use huffman::file_lib::bit_writer::BitWriter;
+use huffman::huffman_tree::canonical_codes;
+use huffman::huffman_tree::write_code;
+use huffman::huffman_tree::FrequencyTable;
+use huffman::huffman_tree::HuffmanTree;
+use huffman::huffman_tree::TableDecoder;
+use std::time::Duration;
+use std::time::Instant;
+
+const CORPUS_LEN: usize = 8 * 1024 * 1024;
+
+fn main()
+{
+    let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+    let corpus = generate_corpus(&text, CORPUS_LEN);
+
+    println!("{:<14} {:>10} {:>12} {:>12} {:>8}",
+             "input", "bytes", "tree MB/s", "table MB/s", "speedup");
+    bench("asyoulik.txt", &text, 20);
+    bench("generated", &corpus, 2);
+}
+
+fn bench(name: &str, text: &[u8], rounds: usize)
+{
+    let mut frequencies = FrequencyTable::new();
+    text.iter().for_each(|byte| frequencies.add(*byte as u16));
+    let lengths = HuffmanTree::new(&frequencies).expect("input is not empty")
+                                                .code_lengths();
+    // the decoder rebuilds the tree from the canonical codes
+    let tree = HuffmanTree::from_lengths(&lengths).unwrap();
+    let table = TableDecoder::new(&lengths).expect("codes fit in a table");
+
+    let codes = canonical_codes(&lengths);
+    let mut writer = BitWriter::new(Vec::new());
+    for byte in text
+    {
+        write_code(&mut writer, &codes[*byte as usize].unwrap()).unwrap();
+    }
+    let encoded = writer.into_inner().unwrap();
+
+    let tree_time = time(rounds, || {
+        let mut reader = BitReader::new(&encoded[..]);
+        for byte in text
+        {
+            assert_eq!(tree.decode(&mut reader).unwrap(), *byte as u16);
+        }
+    });
+    let table_time = time(rounds, || {
+        let mut reader = BitReader::new(&encoded[..]);
+        for byte in text
+        {
+            assert_eq!(table.decode(&mut reader).unwrap(), *byte as u16);
+        }
+    });
+
+    println!("{:<14} {:>10} {:>12.1} {:>12.1} {:>7.2}x",
+             name,
+             text.len(),
+             throughput(text.len(), tree_time),
+             throughput(text.len(), table_time),
+             tree_time.as_secs_f64() / table_time.as_secs_f64());
+}
+
+// best of a few rounds
+fn time(rounds: usize, mut f: impl FnMut()) -> Duration
+{
+    (0..rounds).map(|_| {
+                   let start = Instant::now();
+                   f();
+                   start.elapsed()
+               })
+               .min()
+               .unwrap()
+}
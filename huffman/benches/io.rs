@@ -0,0 +1,139 @@
+// Compares reading, counting, encoding and writing a byte at a time against doing it a slice at a
+// time, then times compress from one file to another
+//
+// run with `cargo bench --bench io`
+mod common;
+
+use common::generate_corpus;
+use common::throughput;
+use huffman::compress::compress_with;
+use huffman::compress::CompressOptions;
+use huffman::file_lib::bit_writer::BitWriter;
+use huffman::file_lib::file_bit_writer::FileBitWriter;
+use huffman::file_lib::file_byte_reader::FileByteReader;
+use huffman::huffman_tree::canonical_codes;
+use huffman::huffman_tree::write_code;
+use huffman::huffman_tree::write_codes;
+use huffman::huffman_tree::FrequencyTable;
+use huffman::huffman_tree::HuffmanTree;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+const CORPUS_LEN: usize = 32 * 1024 * 1024;
+const CHUNK_LEN: usize = 64 * 1024;
+const ROUNDS: usize = 3;
+
+fn main()
+{
+    let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+    let corpus = generate_corpus(&text, CORPUS_LEN);
+    let path = Path::new("kmd_BenchIo.bin");
+    std::fs::write(path, &corpus).expect("failed writing the corpus");
+
+    println!("{:<14} {:>10} {:>14} {:>12} {:>8}",
+             "stage", "bytes", "per byte MB/s", "bulk MB/s", "speedup");
+
+    let per_byte = time(|| {
+        let mut frequencies = FrequencyTable::new();
+        for byte in FileByteReader::new(path).expect("failed opening the corpus")
+        {
+            frequencies.add(byte.unwrap() as u16);
+        }
+        frequencies
+    });
+    let bulk = time(|| {
+        let mut frequencies = FrequencyTable::new();
+        let mut reader = FileByteReader::new(path).expect("failed opening the corpus");
+        let mut chunk = vec![0u8; CHUNK_LEN];
+        loop
+        {
+            let read = reader.read_chunk(&mut chunk).unwrap();
+            if read == 0
+            {
+                break;
+            }
+            frequencies.add_bytes(&chunk[..read]);
+        }
+        frequencies
+    });
+    assert_eq!(per_byte.1, bulk.1);
+    report("read and count", corpus.len(), per_byte.0, bulk.0);
+
+    let lengths = HuffmanTree::new(&bulk.1).expect("input is not empty")
+                                           .code_lengths();
+    let codes = canonical_codes(&lengths);
+    let per_byte = time(|| {
+        let mut writer = BitWriter::new(Vec::with_capacity(corpus.len()));
+        for byte in &corpus
+        {
+            write_code(&mut writer, &codes[*byte as usize].unwrap()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    });
+    let bulk = time(|| {
+        let mut writer = BitWriter::new(Vec::with_capacity(corpus.len()));
+        write_codes(&mut writer, &codes, &corpus).unwrap();
+        writer.into_inner().unwrap()
+    });
+    assert!(per_byte.1 == bulk.1);
+    report("encode", corpus.len(), per_byte.0, bulk.0);
+    let encoded = bulk.1;
+
+    // the same bytes into a real file, which is where an unbuffered writer costs a syscall a byte
+    let output = Path::new("kmd_BenchIo.compressed");
+    let per_byte = time(|| {
+        let _ = std::fs::remove_file(output);
+        let mut writer = FileBitWriter::new(output).expect("failed creating the output");
+        for byte in &encoded
+        {
+            writer.write_bits(*byte as u64, 8).unwrap();
+        }
+        writer.finish().unwrap();
+    });
+    let bulk = time(|| {
+        let _ = std::fs::remove_file(output);
+        let mut writer = FileBitWriter::new(output).expect("failed creating the output");
+        writer.write_bytes(&encoded).unwrap();
+        writer.finish().unwrap();
+    });
+    report("write file", encoded.len(), per_byte.0, bulk.0);
+
+    // and all of compress, file to file
+    let compressed = time(|| {
+        let _ = std::fs::remove_file(output);
+        compress_with(path, output, &CompressOptions::default()).expect("failed compressing");
+    });
+    println!("{:<14} {:>10} {:>14} {:>12.1}",
+             "compress file",
+             corpus.len(),
+             "",
+             throughput(corpus.len(), compressed.0));
+
+    let _ = std::fs::remove_file(output);
+    let _ = std::fs::remove_file(path);
+}
+
+fn report(stage: &str, len: usize, per_byte: Duration, bulk: Duration)
+{
+    println!("{:<14} {:>10} {:>14.1} {:>12.1} {:>7.2}x",
+             stage,
+             len,
+             throughput(len, per_byte),
+             throughput(len, bulk),
+             per_byte.as_secs_f64() / bulk.as_secs_f64());
+}
+
+// best of a few rounds, along with what the last round gave
+fn time<T>(mut f: impl FnMut() -> T) -> (Duration, T)
+{
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..ROUNDS
+    {
+        let start = Instant::now();
+        result = Some(f());
+        best = best.min(start.elapsed());
+    }
+    (best, result.unwrap())
+}
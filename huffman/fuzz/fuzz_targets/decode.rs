@@ -0,0 +1,9 @@
+// Header parsing and every way of decoding, fed whatever libfuzzer comes up with
+//
+// run from huffman/ with `cargo +nightly fuzz run decode`, which starts from the seeds in
+// fuzz/corpus/decode, and turn anything it finds into a test in src/fuzz.rs
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| huffman::fuzz::decode(data));
@@ -0,0 +1,280 @@
+use crate::file_lib::bit::Bit;
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::read_value;
+use std::io::Error;
+use std::io::Read;
+use std::io::Write;
+
+// a symbol seen for the first time is sent as the escape code followed by the symbol in this many
+// bits
+const SYMBOL_BITS: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+enum AdaptiveKind
+{
+    // the escape for a symbol not seen yet, always weight 0
+    Nyt,
+    Leaf(u16),
+    // children for a 0 bit and a 1 bit
+    Internal(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AdaptiveNode
+{
+    weight: u64,
+    parent: Option<usize>,
+    kind: AdaptiveKind,
+}
+
+// An adaptive huffman tree, updated with the FGK algorithm
+//
+// Nothing about the code is sent up front, the encoder and decoder both start with a tree that
+// only has the escape for a new symbol and update it the same way after every symbol, so the code
+// follows the counts seen so far. The nodes are kept in the order of the sibling property, weights
+// never increase along the vec and siblings sit next to each other, the root is always first.
+#[derive(Debug)]
+pub struct AdaptiveTree
+{
+    nodes: Vec<AdaptiveNode>,
+    // the leaf for every symbol seen so far, indexed by symbol
+    leaves: Vec<Option<usize>>,
+    nyt: usize,
+}
+
+impl AdaptiveTree
+{
+    pub fn new() -> Self
+    {
+        Self { nodes: vec![AdaptiveNode { weight: 0, parent: None, kind: AdaptiveKind::Nyt }],
+               leaves: vec![None; 1 << SYMBOL_BITS],
+               nyt: 0 }
+    }
+
+    // write the code for the symbol and update the tree
+    pub fn encode<W: Write>(&mut self, writer: &mut BitWriter<W>, symbol: u8) -> Result<(), Error>
+    {
+        match self.leaves[symbol as usize]
+        {
+            Some(leaf) => self.write_path(writer, leaf)?,
+            None =>
+            {
+                self.write_path(writer, self.nyt)?;
+                writer.write_bits(symbol as u64, SYMBOL_BITS)?;
+            }
+        }
+
+        self.update(symbol);
+        Ok(())
+    }
+
+    // read a code, walking down from the root, and update the tree
+    pub fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<u8, Error>
+    {
+        let mut index = 0;
+        let symbol = loop
+        {
+            match self.nodes[index].kind
+            {
+                AdaptiveKind::Leaf(symbol) => break symbol as u8,
+                AdaptiveKind::Nyt => break read_value(reader, SYMBOL_BITS)? as u8,
+                AdaptiveKind::Internal(zero, one) =>
+                {
+                    index = match read_value(reader, 1)?
+                    {
+                        0 => zero,
+                        _ => one,
+                    };
+                }
+            }
+        };
+
+        self.update(symbol);
+        Ok(symbol)
+    }
+
+    // the path from the root to a node, collected from the node up
+    fn write_path<W: Write>(&self, writer: &mut BitWriter<W>, mut index: usize)
+                            -> Result<(), Error>
+    {
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[index].parent
+        {
+            let AdaptiveKind::Internal(zero, _) = self.nodes[parent].kind
+            else
+            {
+                unreachable!("parents are internal nodes")
+            };
+            path.push(if index == zero { Bit::Zero } else { Bit::One });
+            index = parent;
+        }
+
+        for bit in path.iter().rev()
+        {
+            writer.write(bit)?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, symbol: u8)
+    {
+        let mut index = match self.leaves[symbol as usize]
+        {
+            Some(leaf) => leaf,
+            None => self.add_symbol(symbol),
+        };
+
+        loop
+        {
+            // swap with the first node of the same weight, unless that's the parent, so the
+            // weight can go up without breaking the order
+            let weight = self.nodes[index].weight;
+            let mut leader = index;
+            while leader > 0 && self.nodes[leader - 1].weight == weight
+            {
+                leader -= 1;
+            }
+            if leader != index && Some(leader) != self.nodes[index].parent
+            {
+                self.swap(index, leader);
+                index = leader;
+            }
+
+            self.nodes[index].weight += 1;
+            match self.nodes[index].parent
+            {
+                Some(parent) => index = parent,
+                None => break,
+            }
+        }
+    }
+
+    // split the escape into a new escape and a leaf for the symbol, returning the leaf
+    fn add_symbol(&mut self, symbol: u8) -> usize
+    {
+        let parent = self.nyt;
+        let leaf = self.nodes.len();
+        let nyt = leaf + 1;
+
+        self.nodes[parent].kind = AdaptiveKind::Internal(nyt, leaf);
+        self.nodes.push(AdaptiveNode { weight: 0,
+                                       parent: Some(parent),
+                                       kind: AdaptiveKind::Leaf(symbol as u16) });
+        self.nodes
+            .push(AdaptiveNode { weight: 0, parent: Some(parent), kind: AdaptiveKind::Nyt });
+        self.leaves[symbol as usize] = Some(leaf);
+        self.nyt = nyt;
+        leaf
+    }
+
+    // swap the subtrees at two positions, the parents stay where they are
+    fn swap(&mut self, a: usize, b: usize)
+    {
+        self.nodes.swap(a, b);
+        let parent = self.nodes[a].parent;
+        self.nodes[a].parent = self.nodes[b].parent;
+        self.nodes[b].parent = parent;
+
+        for index in [a, b]
+        {
+            match self.nodes[index].kind
+            {
+                AdaptiveKind::Nyt => self.nyt = index,
+                AdaptiveKind::Leaf(symbol) => self.leaves[symbol as usize] = Some(index),
+                AdaptiveKind::Internal(zero, one) =>
+                {
+                    self.nodes[zero].parent = Some(index);
+                    self.nodes[one].parent = Some(index);
+                }
+            }
+        }
+    }
+}
+
+impl Default for AdaptiveTree
+{
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn encode(text: &[u8]) -> Vec<u8>
+    {
+        let mut tree = AdaptiveTree::new();
+        let mut writer = BitWriter::new(Vec::new());
+        for byte in text
+        {
+            tree.encode(&mut writer, *byte).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    fn decode(encoded: &[u8], len: usize) -> Vec<u8>
+    {
+        let mut tree = AdaptiveTree::new();
+        let mut reader = BitReader::new(encoded);
+        (0..len).map(|_| tree.decode(&mut reader).unwrap())
+                .collect()
+    }
+
+    // weights never go up along the vec, and every internal node weighs as much as its children
+    fn check_sibling_property(tree: &AdaptiveTree)
+    {
+        for (index, node) in tree.nodes.iter().enumerate()
+        {
+            if index > 0
+            {
+                assert!(tree.nodes[index - 1].weight >= node.weight);
+            }
+            if let AdaptiveKind::Internal(zero, one) = node.kind
+            {
+                assert_eq!(node.weight,
+                           tree.nodes[zero].weight + tree.nodes[one].weight);
+                assert_eq!(zero.abs_diff(one), 1);
+                assert_eq!(tree.nodes[zero].parent, Some(index));
+                assert_eq!(tree.nodes[one].parent, Some(index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_symbol()
+    {
+        // the escape has no bits while it's the whole tree, so it's just the symbol
+        assert_eq!(encode(b"a"), [b'a']);
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let encoded = encode(&text);
+        assert!(encoded.len() < text.len() * 2 / 3);
+        assert_eq!(decode(&encoded, text.len()), text);
+    }
+
+    #[test]
+    fn test_every_symbol()
+    {
+        let text = (0..=255u8).chain((0..=255u8).rev()).collect::<Vec<_>>();
+        let encoded = encode(&text);
+        assert_eq!(decode(&encoded, text.len()), text);
+    }
+
+    #[test]
+    fn test_sibling_property()
+    {
+        let mut tree = AdaptiveTree::new();
+        let mut writer = BitWriter::new(Vec::new());
+        for byte in b"abracadabra, mississippi"
+        {
+            tree.encode(&mut writer, *byte).unwrap();
+            check_sibling_property(&tree);
+        }
+        assert_eq!(tree.nodes[0].weight, 24);
+    }
+}
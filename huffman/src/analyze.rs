@@ -0,0 +1,345 @@
+// What a file's bytes look like to the huffman coder, for seeing why it compresses the way it does
+//
+// The bytes are counted and given the codes compress would give them as a single block, and the
+// average code length is set against the entropy, which is the best any code for single bytes
+// could do. A compressed file is uncompressed first and its own header is reported alongside.
+
+use crate::container::Header;
+use crate::container::HEADER_LEN;
+use crate::container::MAGIC;
+use crate::file_lib::bit::Bit;
+use crate::file_lib::file_byte_reader::FileByteReader;
+use crate::huffman_tree::canonical_codes;
+use crate::huffman_tree::code_lengths_size_bits;
+use crate::huffman_tree::symbol_name;
+use crate::huffman_tree::Code;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::huffman_tree::ALPHABET_SIZE;
+use crate::uncompress::HuffmanDecoder;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Error;
+use std::io::Read;
+use std::path::Path;
+
+// bytes counted at a time
+const CHUNK_LEN: usize = 64 * 1024;
+
+// a byte that comes up in the data, with the code it gets
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolCode
+{
+    pub symbol: u8,
+    pub count: u64,
+    pub code: Code,
+}
+
+// where the data came from when it was read out of a compressed file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedSource
+{
+    pub header: Header,
+    pub size_bits: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis
+{
+    pub len: u64,
+    // in symbol order, only the ones that come up
+    pub symbols: Vec<SymbolCode>,
+    // both in bits per byte
+    pub entropy: f64,
+    pub average_code_len: f64,
+    // the codes for every byte together
+    pub coded_bits: u64,
+    // the container header and the code lengths that are stored ahead of the codes
+    pub header_bits: u64,
+    pub compressed: Option<CompressedSource>,
+    pub frequencies: FrequencyTable,
+}
+
+impl Analysis
+{
+    pub fn new(frequencies: &FrequencyTable) -> Self
+    {
+        let len = frequencies.symbols().map(|(_, count)| count).sum::<u64>();
+        let lengths = match HuffmanTree::new(frequencies)
+        {
+            Some(tree) => tree.code_lengths(),
+            None => vec![0; ALPHABET_SIZE],
+        };
+        let codes = canonical_codes(&lengths);
+
+        let symbols = frequencies.symbols()
+                                 .map(|(symbol, count)| {
+                                     SymbolCode { symbol: symbol as u8,
+                                                                     count,
+                                                                     code: codes[symbol as usize]
+                                                                         .expect("every counted \
+                                                                                  symbol has a \
+                                                                                  code") }
+                                 })
+                                 .collect::<Vec<_>>();
+        let coded_bits = symbols.iter()
+                                .map(|symbol| symbol.count * symbol.code.len as u64)
+                                .sum::<u64>();
+        let entropy = symbols.iter()
+                             .map(|symbol| {
+                                 let probability = symbol.count as f64 / len as f64;
+                                 -probability * probability.log2()
+                             })
+                             .sum::<f64>();
+        let header_bits = (HEADER_LEN * 8) as u64
+                          + match len
+                          {
+                              0 => 0,
+                              _ => code_lengths_size_bits(&lengths) as u64,
+                          };
+
+        Self { len,
+               symbols,
+               entropy,
+               average_code_len: match len
+               {
+                   0 => 0.0,
+                   _ => coded_bits as f64 / len as f64,
+               },
+               coded_bits,
+               header_bits,
+               compressed: None,
+               frequencies: frequencies.clone() }
+    }
+
+    pub fn to_json(&self) -> String
+    {
+        let mut json = String::new();
+        write!(json,
+               "{{\"len\":{},\"distinct_symbols\":{},\"entropy\":{},\"average_code_len\":{},\
+                \"coded_bits\":{},\"header_bits\":{},\"compressed\":",
+               self.len,
+               self.symbols.len(),
+               self.entropy,
+               self.average_code_len,
+               self.coded_bits,
+               self.header_bits).expect("writing to a string can't fail");
+        match &self.compressed
+        {
+            Some(compressed) => write!(json,
+                                       "{{\"version\":{},\"flags\":{},\"codec\":\"{:?}\",\
+                                        \"size_bits\":{}}}",
+                                       compressed.header.version,
+                                       compressed.header.flags,
+                                       compressed.header.codec,
+                                       compressed.size_bits),
+            None => write!(json, "null"),
+        }.expect("writing to a string can't fail");
+
+        json.push_str(",\"symbols\":[");
+        for (index, symbol) in self.symbols.iter().enumerate()
+        {
+            if index > 0
+            {
+                json.push(',');
+            }
+            write!(json,
+                   "{{\"symbol\":{},\"count\":{},\"probability\":{},\"code\":\"{}\",\"len\":{}}}",
+                   symbol.symbol,
+                   symbol.count,
+                   symbol.count as f64 / self.len as f64,
+                   code_string(&symbol.code),
+                   symbol.code.len).expect("writing to a string can't fail");
+        }
+        json.push_str("]}");
+        json
+    }
+
+    // the tree the codes come from as a graphviz digraph, with no nodes when there were no bytes
+    pub fn to_dot(&self) -> String
+    {
+        match HuffmanTree::new(&self.frequencies)
+        {
+            Some(tree) => tree.to_dot(&self.frequencies),
+            None => "digraph huffman {\n}\n".to_string(),
+        }
+    }
+}
+
+impl Display for Analysis
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        if let Some(compressed) = &self.compressed
+        {
+            writeln!(f,
+                     "compressed: version {}, flags {:#04x}, {:?} codec, {} bits",
+                     compressed.header.version,
+                     compressed.header.flags,
+                     compressed.header.codec,
+                     compressed.size_bits)?;
+        }
+        writeln!(f, "bytes: {}, distinct: {}", self.len, self.symbols.len())?;
+        writeln!(f,
+                 "entropy: {:.4} bits per byte, average code length: {:.4} bits per byte",
+                 self.entropy, self.average_code_len)?;
+        writeln!(f,
+                 "coded: {} bits, header overhead: {} bits",
+                 self.coded_bits, self.header_bits)?;
+        writeln!(f,
+                 "{:<8} {:>10} {:>9} {:>4}  code",
+                 "symbol", "count", "freq", "len")?;
+        for symbol in &self.symbols
+        {
+            writeln!(f,
+                     "{:<8} {:>10} {:>8.4}% {:>4}  {}",
+                     symbol_name(symbol.symbol as u16),
+                     symbol.count,
+                     100.0 * symbol.count as f64 / self.len as f64,
+                     symbol.code.len,
+                     code_string(&symbol.code))?;
+        }
+        Ok(())
+    }
+}
+
+fn code_string(code: &Code) -> String
+{
+    code.bits()
+        .map(|bit| match bit
+        {
+            Bit::Zero => '0',
+            Bit::One => '1',
+        })
+        .collect()
+}
+
+// count the bytes of a file, or of what a compressed file uncompresses to
+pub fn analyze_file(in_file: &Path) -> Result<Analysis, Error>
+{
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    File::open(in_file)?.take(MAGIC.len() as u64)
+                        .read_to_end(&mut magic)?;
+
+    let mut frequencies = FrequencyTable::new();
+    if magic != MAGIC
+    {
+        let mut reader = FileByteReader::new(in_file)?;
+        let mut chunk = vec![0u8; CHUNK_LEN];
+        loop
+        {
+            let read = reader.read_chunk(&mut chunk)?;
+            if read == 0
+            {
+                break;
+            }
+            frequencies.add_bytes(&chunk[..read]);
+        }
+        return Ok(Analysis::new(&frequencies));
+    }
+
+    let mut decoder = HuffmanDecoder::new(BufReader::new(File::open(in_file)?))?;
+    let header = *decoder.header();
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    loop
+    {
+        let read = decoder.read(&mut chunk)?;
+        if read == 0
+        {
+            break;
+        }
+        frequencies.add_bytes(&chunk[..read]);
+    }
+    let size_bits = 8 * std::fs::metadata(in_file)?.len();
+    Ok(Analysis { compressed: Some(CompressedSource { header, size_bits }),
+                  ..Analysis::new(&frequencies) })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::compress::compress;
+
+    struct TestFile
+    {
+        path: PathBuf,
+    }
+
+    impl TestFile
+    {
+        pub fn create(path: PathBuf) -> Self { Self { path } }
+    }
+
+    impl Drop for TestFile
+    {
+        fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+    }
+
+    #[test]
+    fn test_analysis()
+    {
+        // counts of 4, 2, 1 and 1 have an entropy of 1.75 bits, which huffman codes hit exactly
+        let mut frequencies = FrequencyTable::new();
+        for (symbol, count) in [(b'a', 4), (b'b', 2), (b'c', 1), (b'd', 1)]
+        {
+            frequencies.add_count(symbol as u16, count);
+        }
+        let analysis = Analysis::new(&frequencies);
+        assert_eq!(analysis.len, 8);
+        assert_eq!(analysis.entropy, 1.75);
+        assert_eq!(analysis.average_code_len, 1.75);
+        assert_eq!(analysis.coded_bits, 14);
+        assert_eq!(analysis.symbols
+                           .iter()
+                           .map(|symbol| code_string(&symbol.code))
+                           .collect::<Vec<_>>(),
+                   ["0", "10", "110", "111"]);
+
+        let json = analysis.to_json();
+        assert!(json.starts_with("{\"len\":8,\"distinct_symbols\":4,\"entropy\":1.75,"));
+        assert!(json.contains("\"compressed\":null"));
+        assert!(analysis.to_dot().contains("[shape=box, label=\"d\\n1\"]"));
+        assert!(json.ends_with(
+                               "{\"symbol\":100,\"count\":1,\"probability\":0.125,\"code\":\"111\",\
+                                \"len\":3}]}"
+        ));
+    }
+
+    #[test]
+    fn test_analysis_empty()
+    {
+        let analysis = Analysis::new(&FrequencyTable::new());
+        assert_eq!((analysis.len, analysis.entropy, analysis.average_code_len),
+                   (0, 0.0, 0.0));
+        assert_eq!(analysis.header_bits, (HEADER_LEN * 8) as u64);
+        assert!(analysis.to_json().ends_with("\"symbols\":[]}"));
+        assert_eq!(analysis.to_dot(), "digraph huffman {\n}\n");
+    }
+
+    #[test]
+    fn test_analyze_file()
+    {
+        let plain = analyze_file(Path::new("asyoulik.txt")).expect("failed analyzing");
+        assert_eq!(plain.len, 125179);
+        assert!(plain.entropy <= plain.average_code_len);
+        assert!(plain.average_code_len < plain.entropy + 0.1);
+        assert_eq!(plain.compressed, None);
+
+        // the same bytes once they've been through compress
+        let compressed = TestFile::create(PathBuf::from("kmd_AnalyzeFile.compressed"));
+        let _ = std::fs::remove_file(&compressed.path);
+        compress(Path::new("asyoulik.txt"), &compressed.path).expect("failed compressing");
+        let analysis = analyze_file(&compressed.path).expect("failed analyzing compressed");
+        assert_eq!(analysis.symbols, plain.symbols);
+        let source = analysis.compressed
+                             .expect("the compressed header is reported");
+        assert_eq!(source.header.original_len, 125179);
+        assert!(source.size_bits >= plain.coded_bits);
+    }
+}
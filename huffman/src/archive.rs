@@ -0,0 +1,698 @@
+// Many files and directories packed into one archive
+//
+// Each file's contents are compressed on their own into the same format compress writes, one after
+// the other after a short header. A central directory at the end lists every entry with its path,
+// size, permissions and modification time, and where its compressed data is, so an archive can be
+// listed without reading the data and entries can be pulled out one at a time.
+//
+// header:    ARCHIVE_MAGIC, ARCHIVE_VERSION
+// entries:   compressed data for each file
+// directory: for each entry its kind, path length u16, path, mode u32, mtime i64, size u64,
+//            crc32 u32, offset u64, compressed length u64
+// footer:    directory offset u64, entry count u32, DIRECTORY_MAGIC
+//
+// Paths are relative, with / between components, and a directory comes before what's in it.
+
+use crate::compress::CompressOptions;
+use crate::compress::HuffmanEncoder;
+use crate::container::FormatError;
+use crate::crc32::crc32;
+use crate::uncompress::HuffmanDecoder;
+use std::fs;
+use std::fs::File;
+use std::fs::Metadata;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"HUFA";
+pub const ARCHIVE_VERSION: u8 = 1;
+const ARCHIVE_HEADER_LEN: u64 = 5;
+pub const DIRECTORY_MAGIC: [u8; 4] = *b"HDIR";
+// the directory offset, entry count and magic at the very end of the archive
+pub const DIRECTORY_FOOTER_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind
+{
+    File,
+    Directory,
+}
+
+impl EntryKind
+{
+    fn id(self) -> u8
+    {
+        match self
+        {
+            EntryKind::File => 0,
+            EntryKind::Directory => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, Error>
+    {
+        match id
+        {
+            0 => Ok(EntryKind::File),
+            1 => Ok(EntryKind::Directory),
+            _ => Err(Error::new(ErrorKind::InvalidData,
+                                format!("unknown archive entry kind {}", id))),
+        }
+    }
+}
+
+// an entry in the central directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry
+{
+    pub kind: EntryKind,
+    pub path: String,
+    // unix permission bits
+    pub mode: u32,
+    // seconds since the unix epoch
+    pub mtime: i64,
+    // the size and checksum before compression, both 0 for a directory
+    pub size: u64,
+    pub crc32: u32,
+    // where the compressed data starts in the archive and how long it is
+    pub offset: u64,
+    pub compressed_len: u64,
+}
+
+impl ArchiveEntry
+{
+    fn write(&self, bytes: &mut Vec<u8>) -> Result<(), Error>
+    {
+        let too_long = || {
+            Error::new(ErrorKind::InvalidInput,
+                       format!("path is too long to archive: {}", self.path))
+        };
+        let path_len = u16::try_from(self.path.len()).map_err(|_| too_long())?;
+        bytes.push(self.kind.id());
+        bytes.extend_from_slice(&path_len.to_le_bytes());
+        bytes.extend_from_slice(self.path.as_bytes());
+        bytes.extend_from_slice(&self.mode.to_le_bytes());
+        bytes.extend_from_slice(&self.mtime.to_le_bytes());
+        bytes.extend_from_slice(&self.size.to_le_bytes());
+        bytes.extend_from_slice(&self.crc32.to_le_bytes());
+        bytes.extend_from_slice(&self.offset.to_le_bytes());
+        bytes.extend_from_slice(&self.compressed_len.to_le_bytes());
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Self, Error>
+    {
+        let kind = EntryKind::from_id(read_bytes::<1, _>(reader)?[0])?;
+        let path_len = u16::from_le_bytes(read_bytes(reader)?);
+        let mut path = vec![0u8; path_len as usize];
+        reader.read_exact(&mut path).map_err(truncated)?;
+        let path = String::from_utf8(path).map_err(|_| {
+                                              Error::new(ErrorKind::InvalidData,
+                                                         "archive path isn't valid UTF-8")
+                                          })?;
+
+        Ok(Self { kind,
+                  path,
+                  mode: u32::from_le_bytes(read_bytes(reader)?),
+                  mtime: i64::from_le_bytes(read_bytes(reader)?),
+                  size: u64::from_le_bytes(read_bytes(reader)?),
+                  crc32: u32::from_le_bytes(read_bytes(reader)?),
+                  offset: u64::from_le_bytes(read_bytes(reader)?),
+                  compressed_len: u64::from_le_bytes(read_bytes(reader)?) })
+    }
+}
+
+fn read_bytes<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], Error>
+{
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(bytes)
+}
+
+fn truncated(error: Error) -> Error
+{
+    match error.kind()
+    {
+        ErrorKind::UnexpectedEof => FormatError::Truncated.into(),
+        _ => error,
+    }
+}
+
+// Pack the files and directories, and everything under the directories, into a new archive
+//
+// Each input goes in under its own name, without whatever directories lead up to it. Symbolic
+// links and other special files can't be archived.
+pub fn create_archive(out_file: &Path,
+                      inputs: &[PathBuf],
+                      options: &CompressOptions)
+                      -> Result<Vec<ArchiveEntry>, Error>
+{
+    let mut sources = Vec::new();
+    for input in inputs
+    {
+        let name = input.file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or_else(|| {
+                            Error::new(ErrorKind::InvalidInput,
+                                       format!("can't archive {} under its name", input.display()))
+                        })?;
+        walk(input, name.to_string(), &mut sources)?;
+    }
+
+    // an archive that fails part way shouldn't be left behind looking like a whole one
+    let file = File::create_new(out_file)?;
+    let result = write_archive(&mut BufWriter::new(file), sources, options);
+    if result.is_err()
+    {
+        let _ = fs::remove_file(out_file);
+    }
+    result
+}
+
+fn write_archive<W: Write>(writer: &mut W,
+                           sources: Vec<(PathBuf, String, Metadata)>,
+                           options: &CompressOptions)
+                           -> Result<Vec<ArchiveEntry>, Error>
+{
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+
+    let mut offset = ARCHIVE_HEADER_LEN;
+    let mut entries = Vec::with_capacity(sources.len());
+    for (source, path, metadata) in sources
+    {
+        let mut entry = ArchiveEntry { kind: EntryKind::Directory,
+                                       path,
+                                       mode: mode(&metadata),
+                                       mtime: mtime(&metadata),
+                                       size: 0,
+                                       crc32: 0,
+                                       offset,
+                                       compressed_len: 0 };
+        if metadata.is_file()
+        {
+            let contents = fs::read(&source)?;
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), options)?;
+            encoder.write_all(&contents)?;
+            let compressed = encoder.finish()?;
+            writer.write_all(&compressed)?;
+
+            entry = ArchiveEntry { kind: EntryKind::File,
+                                   size: contents.len() as u64,
+                                   crc32: crc32(&contents),
+                                   compressed_len: compressed.len() as u64,
+                                   ..entry };
+            offset += compressed.len() as u64;
+        }
+        entries.push(entry);
+    }
+
+    let mut directory = Vec::new();
+    for entry in &entries
+    {
+        entry.write(&mut directory)?;
+    }
+    writer.write_all(&directory)?;
+    writer.write_all(&offset.to_le_bytes())?;
+    writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+    writer.write_all(&DIRECTORY_MAGIC)?;
+    writer.flush()?;
+
+    Ok(entries)
+}
+
+// the file or directory and everything under it, with the path each goes in under
+fn walk(source: &Path,
+        path: String,
+        sources: &mut Vec<(PathBuf, String, Metadata)>)
+        -> Result<(), Error>
+{
+    let metadata = fs::symlink_metadata(source)?;
+    if metadata.is_file()
+    {
+        sources.push((source.to_path_buf(), path, metadata));
+        return Ok(());
+    }
+    if !metadata.is_dir()
+    {
+        return Err(Error::new(ErrorKind::InvalidInput,
+                              format!("{} isn't a regular file or directory",
+                                      source.display())));
+    }
+
+    sources.push((source.to_path_buf(), path.clone(), metadata));
+    // sorted so the same tree always gives the same archive
+    let mut children = fs::read_dir(source)?.map(|child| child.map(|child| child.path()))
+                                            .collect::<Result<Vec<_>, _>>()?;
+    children.sort();
+    for child in children
+    {
+        let name = child.file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or_else(|| {
+                            Error::new(ErrorKind::InvalidInput,
+                                       format!("{} isn't a valid UTF-8 name", child.display()))
+                        })?;
+        walk(&child, format!("{}/{}", path, name), sources)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32
+{
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32
+{
+    match (metadata.is_dir(), metadata.permissions().readonly())
+    {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+fn mtime(metadata: &Metadata) -> i64
+{
+    match metadata.modified()
+                  .map(|modified| modified.duration_since(UNIX_EPOCH))
+    {
+        Ok(Ok(since)) => since.as_secs() as i64,
+        Ok(Err(before)) => -(before.duration().as_secs() as i64),
+        Err(_) => 0,
+    }
+}
+
+fn system_time(mtime: i64) -> SystemTime
+{
+    match mtime
+    {
+        0.. => UNIX_EPOCH + Duration::from_secs(mtime as u64),
+        _ => UNIX_EPOCH - Duration::from_secs(mtime.unsigned_abs()),
+    }
+}
+
+// every entry in the archive's central directory, nothing else is read
+pub fn read_directory<R: Read + Seek>(reader: &mut R) -> Result<Vec<ArchiveEntry>, Error>
+{
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(ARCHIVE_HEADER_LEN as usize);
+    reader.by_ref()
+          .take(ARCHIVE_HEADER_LEN)
+          .read_to_end(&mut header)?;
+    if !header.starts_with(&ARCHIVE_MAGIC)
+    {
+        return Err(Error::new(ErrorKind::InvalidData, "not a huffman archive"));
+    }
+    if header.len() < ARCHIVE_HEADER_LEN as usize
+    {
+        return Err(FormatError::Truncated.into());
+    }
+    if header[4] != ARCHIVE_VERSION
+    {
+        return Err(FormatError::UnsupportedVersion(header[4]).into());
+    }
+
+    let end = reader.seek(SeekFrom::End(0))?;
+    if end < ARCHIVE_HEADER_LEN + DIRECTORY_FOOTER_LEN as u64
+    {
+        return Err(FormatError::Truncated.into());
+    }
+    reader.seek(SeekFrom::End(-(DIRECTORY_FOOTER_LEN as i64)))?;
+    let footer = read_bytes::<DIRECTORY_FOOTER_LEN, _>(reader)?;
+    if footer[12..] != DIRECTORY_MAGIC
+    {
+        return Err(Error::new(ErrorKind::InvalidData, "the archive's directory is missing"));
+    }
+    let offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+    let count = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+    if offset < ARCHIVE_HEADER_LEN || offset > end - DIRECTORY_FOOTER_LEN as u64
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("the archive's directory offset {} is out of range",
+                                      offset)));
+    }
+
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut directory = BufReader::new(reader.take(end - DIRECTORY_FOOTER_LEN as u64 - offset));
+    let entries = (0..count).map(|_| ArchiveEntry::read(&mut directory))
+                            .collect::<Result<Vec<_>, _>>()?;
+    for entry in &entries
+    {
+        if entry.offset.saturating_add(entry.compressed_len) > offset
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("the data for {} runs into the directory", entry.path)));
+        }
+    }
+    Ok(entries)
+}
+
+pub fn list_archive(in_file: &Path) -> Result<Vec<ArchiveEntry>, Error>
+{
+    read_directory(&mut File::open(in_file)?)
+}
+
+// decode every file in the archive without writing it anywhere, which checks each one's length
+// and checksum
+pub fn test_archive(in_file: &Path) -> Result<Vec<ArchiveEntry>, Error>
+{
+    let mut reader = BufReader::new(File::open(in_file)?);
+    let entries = read_directory(&mut reader)?;
+    for entry in &entries
+    {
+        read_entry(&mut reader, entry, &mut io::sink())?;
+    }
+    Ok(entries)
+}
+
+// Unpack the archive under dest, either every entry or just the ones selected
+//
+// Selecting a directory selects everything in it. Each selection has to match something, and an
+// entry isn't extracted over an existing file unless overwrite is set. Returns the entries that
+// were extracted.
+pub fn extract_archive(in_file: &Path,
+                       dest: &Path,
+                       selected: &[String],
+                       overwrite: bool)
+                       -> Result<Vec<ArchiveEntry>, Error>
+{
+    let mut reader = BufReader::new(File::open(in_file)?);
+    let entries = read_directory(&mut reader)?;
+
+    let selected = selected.iter()
+                           .map(|selection| selection.trim_end_matches('/'))
+                           .collect::<Vec<_>>();
+    let is_selected = |path: &str, selection: &str| {
+        path == selection
+        || path.strip_prefix(selection)
+               .is_some_and(|rest| rest.starts_with('/'))
+    };
+    let found = |selection: &str| {
+        entries.iter()
+               .any(|entry| is_selected(&entry.path, selection))
+    };
+    if let Some(missing) = selected.iter().find(|selection| !found(selection))
+    {
+        return Err(Error::new(ErrorKind::NotFound,
+                              format!("{} isn't in the archive", missing)));
+    }
+    let extracting = entries.into_iter()
+                            .filter(|entry| {
+                                selected.is_empty()
+                                || selected.iter()
+                                           .any(|selection| is_selected(&entry.path, selection))
+                            })
+                            .collect::<Vec<_>>();
+
+    for entry in &extracting
+    {
+        let path = dest.join(entry_path(&entry.path)?);
+        match entry.kind
+        {
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::File =>
+            {
+                if let Some(parent) = path.parent()
+                {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = match overwrite
+                {
+                    true => File::create(&path)?,
+                    false => File::create_new(&path).map_err(|error| match error.kind()
+                                                    {
+                                                        ErrorKind::AlreadyExists =>
+                                                        {
+                                                            Error::new(ErrorKind::AlreadyExists,
+                                                                       format!("{} already exists",
+                                                                               path.display()))
+                                                        }
+                                                        _ => error,
+                                                    })?,
+                };
+                let mut writer = BufWriter::new(file);
+                let result =
+                    read_entry(&mut reader, entry, &mut writer).and_then(|_| writer.flush());
+                if result.is_err()
+                {
+                    let _ = fs::remove_file(&path);
+                }
+                result?;
+                let file = writer.into_inner().map_err(|error| error.into_error())?;
+                file.set_modified(system_time(entry.mtime))?;
+                set_mode(&path, entry.mode)?;
+            }
+        }
+    }
+
+    // a directory's time and permissions are set once everything is in it, deepest first
+    for entry in extracting.iter()
+                           .rev()
+                           .filter(|entry| entry.kind == EntryKind::Directory)
+    {
+        let path = dest.join(entry_path(&entry.path)?);
+        // not every platform can open a directory to set its time
+        if let Ok(directory) = File::open(&path)
+        {
+            let _ = directory.set_modified(system_time(entry.mtime));
+        }
+        set_mode(&path, entry.mode)?;
+    }
+
+    Ok(extracting)
+}
+
+// the path an entry extracts to, which has to stay under where it's being extracted
+fn entry_path(path: &str) -> Result<PathBuf, Error>
+{
+    let relative = Path::new(path);
+    if path.is_empty()
+       || !relative.components()
+                   .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("archive path {} would extract outside the destination",
+                                      path)));
+    }
+    Ok(relative.to_path_buf())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), Error>
+{
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<(), Error>
+{
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+// decode a file's data into the writer, the decoder checks the length and checksum stored with
+// the data and they're checked against the directory too
+fn read_entry<R: Read + Seek, W: Write>(reader: &mut R,
+                                        entry: &ArchiveEntry,
+                                        writer: &mut W)
+                                        -> Result<(), Error>
+{
+    if entry.kind == EntryKind::Directory
+    {
+        return Ok(());
+    }
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    let mut decoder = HuffmanDecoder::new(reader.take(entry.compressed_len))?;
+    let header = *decoder.header();
+    if header.original_len != entry.size
+    {
+        return Err(FormatError::LengthMismatch { expected: entry.size,
+                                                 actual: header.original_len }.into());
+    }
+    if header.crc32 != entry.crc32
+    {
+        return Err(FormatError::ChecksumMismatch { expected: entry.crc32,
+                                                   actual: header.crc32 }.into());
+    }
+    io::copy(&mut decoder, writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a directory that's removed along with everything in it when dropped
+    struct TestDir
+    {
+        path: PathBuf,
+    }
+
+    impl TestDir
+    {
+        pub fn create(path: PathBuf) -> Self
+        {
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).expect("failed creating test directory");
+            Self { path }
+        }
+    }
+
+    impl Drop for TestDir
+    {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(self.path.as_path()); }
+    }
+
+    // a small tree with an empty file, an empty directory and a nested one
+    fn make_tree(root: &Path)
+    {
+        let text = fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        fs::create_dir_all(root.join("docs/nested")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("asyoulik.txt"), &text).unwrap();
+        fs::write(root.join("docs/short.txt"), b"Hello World!").unwrap();
+        fs::write(root.join("docs/nested/empty.txt"), b"").unwrap();
+        fs::write(root.join("docs/nested/part.txt"), &text[..1000]).unwrap();
+    }
+
+    #[test]
+    fn test_archive_round_trip()
+    {
+        let dir = TestDir::create(PathBuf::from("kmd_ArchiveRoundTrip"));
+        let tree = dir.path.join("tree");
+        make_tree(&tree);
+        #[cfg(unix)]
+        set_mode(&tree.join("docs/short.txt"), 0o600).unwrap();
+        File::options().write(true)
+                       .open(tree.join("docs/short.txt"))
+                       .unwrap()
+                       .set_modified(system_time(1_000_000_000))
+                       .unwrap();
+
+        let archive = dir.path.join("tree.hufa");
+        let entries = create_archive(&archive, &[tree], &CompressOptions::default())
+            .expect("failed creating archive");
+        assert_eq!(entries.iter()
+                          .map(|entry| entry.path.as_str())
+                          .collect::<Vec<_>>(),
+                   ["tree",
+                    "tree/asyoulik.txt",
+                    "tree/docs",
+                    "tree/docs/nested",
+                    "tree/docs/nested/empty.txt",
+                    "tree/docs/nested/part.txt",
+                    "tree/docs/short.txt",
+                    "tree/empty"]);
+        assert_eq!(list_archive(&archive).unwrap(), entries);
+        assert_eq!(test_archive(&archive).unwrap(), entries);
+
+        let out = dir.path.join("out");
+        extract_archive(&archive, &out, &[], false).expect("failed extracting");
+        for entry in entries.iter().filter(|entry| entry.kind == EntryKind::File)
+        {
+            let original = dir.path.join(&entry.path);
+            let extracted = out.join(&entry.path);
+            assert_eq!(fs::read(&extracted).unwrap(), fs::read(&original).unwrap());
+            let metadata = fs::metadata(&extracted).unwrap();
+            assert_eq!(mode(&metadata), mode(&fs::metadata(&original).unwrap()));
+            assert_eq!(mtime(&metadata), entry.mtime);
+        }
+        assert_eq!(mtime(&fs::metadata(out.join("tree/docs/short.txt")).unwrap()),
+                   1_000_000_000);
+        assert!(out.join("tree/empty").is_dir());
+
+        // not over what's there unless asked
+        let error = extract_archive(&archive, &out, &[], false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        extract_archive(&archive, &out, &[], true).expect("failed overwriting");
+    }
+
+    #[test]
+    fn test_archive_selected()
+    {
+        let dir = TestDir::create(PathBuf::from("kmd_ArchiveSelected"));
+        let tree = dir.path.join("tree");
+        make_tree(&tree);
+        let archive = dir.path.join("tree.hufa");
+        create_archive(&archive,
+                       &[tree.join("docs"), tree.join("asyoulik.txt")],
+                       &CompressOptions::default()).expect("failed creating archive");
+
+        let out = dir.path.join("out");
+        let extracted = extract_archive(&archive, &out, &["docs/nested/".to_string()], false)
+            .expect("failed extracting");
+        assert_eq!(extracted.iter()
+                            .map(|entry| entry.path.as_str())
+                            .collect::<Vec<_>>(),
+                   ["docs/nested",
+                    "docs/nested/empty.txt",
+                    "docs/nested/part.txt"]);
+        assert!(out.join("docs/nested/part.txt").exists());
+        assert!(!out.join("docs/short.txt").exists());
+        assert!(!out.join("asyoulik.txt").exists());
+
+        // docs/nest is only the start of a name
+        let error = extract_archive(&archive, &out, &["docs/nest".to_string()], false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_archive_corrupt()
+    {
+        let dir = TestDir::create(PathBuf::from("kmd_ArchiveCorrupt"));
+        let tree = dir.path.join("tree");
+        make_tree(&tree);
+        let archive = dir.path.join("tree.hufa");
+        let entries = create_archive(&archive, &[tree], &CompressOptions::default())
+            .expect("failed creating archive");
+
+        // damage the middle of the biggest file's data, the directory still reads
+        let mut bytes = fs::read(&archive).unwrap();
+        let entry = entries.iter()
+                           .max_by_key(|entry| entry.compressed_len)
+                           .unwrap();
+        bytes[(entry.offset + entry.compressed_len / 2) as usize] ^= 0x55;
+        fs::write(&archive, &bytes).unwrap();
+        assert_eq!(list_archive(&archive).unwrap(), entries);
+        assert!(test_archive(&archive).is_err());
+
+        // and a cut off archive has no directory
+        fs::write(&archive, &bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(list_archive(&archive).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+        fs::write(&archive, b"HUF\x1a").unwrap();
+        assert_eq!(list_archive(&archive).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_entry_path()
+    {
+        assert_eq!(entry_path("a/b.txt").unwrap(), Path::new("a/b.txt"));
+        for path in ["", "/etc/passwd", "../up", "a/../../up", "./a"]
+        {
+            assert_eq!(entry_path(path).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,12 +1,917 @@
+use crate::adaptive_tree::AdaptiveTree;
+use crate::container::write_block_index;
+use crate::container::BlockHeader;
+use crate::container::BlockTable;
+use crate::container::Codec;
+use crate::container::Header;
+use crate::container::FLAG_ORDER1;
+use crate::context_model::ContextCounts;
+use crate::context_model::ContextTables;
+use crate::crc32::crc32;
+use crate::crc32::Crc32;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::file_lib::file_bit_writer::FileBitWriter;
+use crate::file_lib::file_byte_reader::FileByteReader;
+use crate::huffman_tree::canonical_codes;
+use crate::huffman_tree::code_lengths_size_bits;
+use crate::huffman_tree::coded_bits;
+use crate::huffman_tree::static_code_lengths;
+use crate::huffman_tree::validate_lengths;
+use crate::huffman_tree::write_code_lengths;
+use crate::huffman_tree::write_codes;
+use crate::huffman_tree::Code;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::lz77::Lz77Counts;
+use crate::lz77::Lz77Tables;
+use crate::lz77::MatchOptions;
+use crate::lz77::MAX_WINDOW;
+use crate::parallel;
+use crate::range_coder::RangeModel;
+use crate::transform::TransformedBlock;
+use crate::transform::Transforms;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 
+// a one-pass encoder writes out a frame each time this many bytes have been buffered
+pub const FRAME_LEN: usize = 64 * 1024;
+
+pub const DEFAULT_BLOCK_LEN: usize = 256 * 1024;
+pub use crate::container::MAX_BLOCK_LEN;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressOptions
+{
+    // bytes of input per block, every block gets a code to suit its own contents unless the
+    // previous block's code does the job in fewer bits
+    pub block_len: usize,
+    // blocks are counted and encoded on this many threads, the output doesn't depend on it
+    pub threads: usize,
+    // code each byte with code lengths picked by the byte before it, which suits text much better
+    // but takes longer and needs more code lengths stored with each block
+    pub order1: bool,
+    // what turns the bytes into bits, the range coder gets closer to the entropy than huffman
+    // codes but takes longer to decode
+    pub codec: Codec,
+    // run over every block before it's coded, BWT, move to front and zero run length encoding
+    // together get text down to around the size bzip2 does
+    pub transforms: Transforms,
+    // how hard the LZ77 codec looks for matches, see MatchOptions::level
+    pub matching: MatchOptions,
+}
+
+impl CompressOptions
+{
+    fn check(&self) -> Result<(), Error>
+    {
+        if self.block_len == 0 || self.block_len > MAX_BLOCK_LEN
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("block length must be between 1 and {}",
+                                          MAX_BLOCK_LEN)));
+        }
+        if self.threads == 0
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, "at least one thread is needed"));
+        }
+        if self.matching.window == 0 || self.matching.window > MAX_WINDOW
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("the LZ77 window must be between 1 and {}",
+                                          MAX_WINDOW)));
+        }
+        if self.order1 && self.codec != Codec::Huffman
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "order-1 coding is only done with huffman codes"));
+        }
+        Ok(())
+    }
+
+    fn header(&self, original_len: u64, crc32: u32) -> Header
+    {
+        let header = Header { codec: self.codec, ..Header::blocks(original_len, crc32) }
+            .with_transforms(self.transforms);
+        match self.order1
+        {
+            true => Header { flags: header.flags | FLAG_ORDER1, ..header },
+            false => header,
+        }
+    }
+}
+
+impl Default for CompressOptions
+{
+    fn default() -> Self
+    {
+        Self { block_len: DEFAULT_BLOCK_LEN,
+               threads: 1,
+               order1: false,
+               codec: Codec::Huffman,
+               transforms: Transforms::default(),
+               matching: MatchOptions::default() }
+    }
+}
+
 pub fn compress(in_file: &Path, out_file: &Path) -> Result<(), Error>
 {
-    // Build the frequency table
-    // Create a huffman tree from the frequency table
-    // Serialize the huffman tree to the out_file
-    // Open the in_file process it and write out the compressed version to the out_file
-    // Write out an eof pattern
-    todo!()
+    compress_with(in_file, out_file, &CompressOptions::default())
+}
+
+pub fn compress_with(in_file: &Path,
+                     out_file: &Path,
+                     options: &CompressOptions)
+                     -> Result<(), Error>
+{
+    options.check()?;
+
+    // The first pass gives the length and checksum for the header
+    let (len, crc) = scan_input(in_file)?;
+    let header = options.header(len, crc);
+
+    // Open the in_file a second time to compress it into the out_file a block at a time
+    let mut reader = BufReader::new(File::open(in_file)?);
+    let blocks = std::iter::from_fn(|| read_block(&mut reader, options.block_len).transpose());
+    let mut writer = FileBitWriter::new(out_file)?;
+    write_blocks(&mut writer, &header, blocks, options)?;
+    Ok(writer.finish()?)
+}
+
+// compress in a single pass with an adaptive code, for input that can't be read twice
+pub fn compress_adaptive(in_file: &Path, out_file: &Path) -> Result<(), Error>
+{
+    let mut reader = BufReader::new(File::open(in_file)?);
+    let mut encoder = HuffmanEncoder::adaptive(BufWriter::new(File::create_new(out_file)?))?;
+    io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?.flush()
+}
+
+// bytes read at a time by the first pass
+const SCAN_CHUNK_LEN: usize = 64 * 1024;
+
+fn scan_input(in_file: &Path) -> Result<(u64, u32), Error>
+{
+    let mut crc = Crc32::new();
+    let mut len = 0u64;
+
+    let mut reader = FileByteReader::new(in_file)?;
+    let mut chunk = vec![0u8; SCAN_CHUNK_LEN];
+    loop
+    {
+        let read = reader.read_chunk(&mut chunk)?;
+        if read == 0
+        {
+            break;
+        }
+        crc.update(&chunk[..read]);
+        len += read as u64;
+    }
+
+    Ok((len, crc.finish()))
+}
+
+// the next block_len bytes, None at the end of the input
+fn read_block(reader: &mut impl Read, block_len: usize) -> Result<Option<Vec<u8>>, Error>
+{
+    let mut block = Vec::with_capacity(block_len.min(DEFAULT_BLOCK_LEN));
+    reader.by_ref()
+          .take(block_len as u64)
+          .read_to_end(&mut block)?;
+    Ok((!block.is_empty()).then_some(block))
+}
+
+// Write the header, every block and then the block index
+//
+// Each block gets its own code lengths, or reuses the last lengths written when that comes out
+// smaller. Blocks are taken a batch at a time, one per thread. Transforming, counting and encoding
+// the blocks in a batch happens in parallel, only the choice of code lengths and the writing go in
+// order, so the output is the same however many threads there are.
+fn write_blocks<W: Write, B: AsRef<[u8]> + Sync>(writer: &mut BitWriter<W>,
+                                                 header: &Header,
+                                                 blocks: impl IntoIterator<Item = Result<B, Error>>,
+                                                 options: &CompressOptions)
+                                                 -> Result<(), Error>
+{
+    let threads = options.threads;
+    header.write(writer)?;
+
+    // the last code lengths written and the index of the block they're in
+    let mut table: Option<(u32, BlockModel)> = None;
+    let mut offsets = Vec::new();
+    let mut offset = header.size() as u64;
+
+    let mut blocks = blocks.into_iter();
+    loop
+    {
+        let batch = blocks.by_ref()
+                          .take(threads)
+                          .collect::<Result<Vec<_>, Error>>()?;
+        if batch.is_empty()
+        {
+            break;
+        }
+
+        // the transformed blocks borrow from the batch when there aren't any transforms
+        let batch = batch.iter().map(AsRef::as_ref).collect::<Vec<&[u8]>>();
+        let counted = parallel::map(&batch, threads, |block| {
+            let transformed = header.transforms().apply(block);
+            let counts = BlockCounts::new(&transformed.data, header, &options.matching);
+            let model = counts.model(header.codec);
+            (transformed, counts, model)
+        });
+
+        let mut planned = Vec::with_capacity(batch.len());
+        for (block, (transformed, counts, model)) in batch.iter().zip(counted)
+        {
+            let index =
+                u32::try_from(offsets.len() + planned.len()).map_err(|_| {
+                                                                Error::new(ErrorKind::InvalidInput,
+                                                                           "too many blocks")
+                                                            })?;
+
+            // reusing a table takes a 32 bit block index in the block header instead of the lengths
+            let own_bits = model.size_bits() + model.coded_bits(&counts).unwrap();
+            let reuse = table.as_ref().and_then(|(table_index, table_model)| {
+                                          let reuse_bits = table_model.coded_bits(&counts)?;
+                                          (reuse_bits + 32 < own_bits).then_some(*table_index)
+                                      });
+
+            let block_table = match reuse
+            {
+                Some(table_index) => BlockTable::Reuse(table_index),
+                None =>
+                {
+                    table = Some((index, model));
+                    BlockTable::Own
+                }
+            };
+            let (_, model) = table.as_ref().expect("the block has a table");
+            planned.push((*block, transformed, counts, block_table, model.clone()));
+        }
+
+        let encoded = parallel::map(&planned,
+                                    threads,
+                                    |(block, transformed, counts, table, model)| {
+                                        encode_block(block,
+                                                     transformed,
+                                                     header.transforms(),
+                                                     counts,
+                                                     *table,
+                                                     model)
+                                    });
+        for result in encoded
+        {
+            let (block_header, body) = result?;
+            block_header.write(writer)?;
+            writer.write_bytes(&body)?;
+
+            offsets.push(offset);
+            offset += (block_header.size() + body.len()) as u64;
+        }
+    }
+
+    // No eof pattern is needed, the decoder stops after the number of bytes in the header and
+    // ignores the bits padding out the last byte of each block
+    write_block_index(writer, &offsets)
+}
+
+// the block header and everything after it, the code lengths are only written for a block that
+// doesn't reuse an earlier block's, and the length of the codes and the BWT primary index only for
+// a transformed block
+fn encode_block(block: &[u8],
+                transformed: &TransformedBlock,
+                transforms: Transforms,
+                counts: &BlockCounts,
+                table: BlockTable,
+                model: &BlockModel)
+                -> Result<(BlockHeader, Vec<u8>), Error>
+{
+    let mut body = BitWriter::new(Vec::new());
+    if table == BlockTable::Own
+    {
+        model.write(&mut body)?;
+    }
+    if !transforms.is_empty()
+    {
+        body.write_bits(transformed.data.len() as u64, 32)?;
+    }
+    if transforms.bwt
+    {
+        body.write_bits(transformed.primary as u64, 32)?;
+    }
+    model.encode(&mut body, &transformed.data, counts)?;
+    let body = body.into_inner()?;
+
+    Ok((BlockHeader { len: block.len() as u32,
+                      crc32: crc32(block),
+                      compressed_len: body.len() as u32,
+                      table },
+        body))
+}
+
+// what a block's code lengths are built from
+enum BlockCounts
+{
+    Order0(FrequencyTable),
+    Order1(ContextCounts),
+    Lz77(Lz77Counts),
+}
+
+// the code lengths, or frequencies, a block is coded with
+#[derive(Clone)]
+enum BlockModel
+{
+    Order0(Vec<u8>),
+    Order1(ContextTables),
+    Range(RangeModel),
+    Lz77(Lz77Tables),
+}
+
+impl BlockCounts
+{
+    fn new(block: &[u8], header: &Header, matching: &MatchOptions) -> Self
+    {
+        if header.is_order1()
+        {
+            return BlockCounts::Order1(ContextCounts::new(block));
+        }
+        if header.codec == Codec::Lz77
+        {
+            return BlockCounts::Lz77(Lz77Counts::new(block, matching));
+        }
+
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add_bytes(block);
+        BlockCounts::Order0(frequencies)
+    }
+
+    fn model(&self, codec: Codec) -> BlockModel
+    {
+        match self
+        {
+            BlockCounts::Order0(frequencies) if codec == Codec::Range =>
+            {
+                BlockModel::Range(RangeModel::new(frequencies).expect("blocks are never empty"))
+            }
+            BlockCounts::Order0(frequencies) =>
+            {
+                BlockModel::Order0(HuffmanTree::new(frequencies).expect("blocks are never empty")
+                                                                .code_lengths())
+            }
+            BlockCounts::Order1(counts) => BlockModel::Order1(ContextTables::new(counts)),
+            BlockCounts::Lz77(counts) => BlockModel::Lz77(Lz77Tables::new(counts)),
+        }
+    }
+}
+
+impl BlockModel
+{
+    fn size_bits(&self) -> usize
+    {
+        match self
+        {
+            BlockModel::Order0(lengths) => code_lengths_size_bits(lengths),
+            BlockModel::Order1(tables) => tables.size_bits(),
+            BlockModel::Range(model) => model.size_bits(),
+            BlockModel::Lz77(tables) => tables.size_bits(),
+        }
+    }
+
+    // None if the counts have a byte without a code
+    fn coded_bits(&self, counts: &BlockCounts) -> Option<usize>
+    {
+        match (self, counts)
+        {
+            (BlockModel::Order0(lengths), BlockCounts::Order0(frequencies)) =>
+            {
+                coded_bits(frequencies, lengths)
+            }
+            (BlockModel::Order1(tables), BlockCounts::Order1(counts)) => tables.coded_bits(counts),
+            (BlockModel::Range(model), BlockCounts::Order0(frequencies)) =>
+            {
+                model.coded_bits(frequencies)
+            }
+            (BlockModel::Lz77(tables), BlockCounts::Lz77(counts)) => tables.coded_bits(counts),
+            _ => None,
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        match self
+        {
+            BlockModel::Order0(lengths) => write_code_lengths(writer, lengths),
+            BlockModel::Order1(tables) => tables.write(writer),
+            BlockModel::Range(model) => model.write(writer),
+            BlockModel::Lz77(tables) => tables.write(writer),
+        }
+    }
+
+    // the LZ77 tokens come from the counts, everything else codes the block itself
+    fn encode<W: Write>(&self,
+                        writer: &mut BitWriter<W>,
+                        block: &[u8],
+                        counts: &BlockCounts)
+                        -> Result<(), Error>
+    {
+        match (self, counts)
+        {
+            (BlockModel::Order0(lengths), _) =>
+            {
+                write_codes(writer, &canonical_codes(lengths), block)
+            }
+            (BlockModel::Order1(tables), _) => tables.encode(writer, block),
+            (BlockModel::Range(model), _) => model.encode(writer, block),
+            (BlockModel::Lz77(tables), BlockCounts::Lz77(counts)) =>
+            {
+                tables.encode(writer, &counts.tokens)
+            }
+            (BlockModel::Lz77(_), _) => unreachable!("LZ77 tables are only built from LZ77 counts"),
+        }
+    }
+}
+
+// Compresses everything written to it into the wrapped writer
+//
+// By default the input is buffered until finish, so it can be counted before it's encoded and the
+// output is the same as compress gives. When the input is too big to hold on to, or the output
+// needs to start flowing straight away, a fixed or adaptive model can be used instead and the input
+// is encoded in frames as it arrives.
+pub struct HuffmanEncoder<W: Write>
+{
+    // None once finished
+    writer: Option<BitWriter<W>>,
+    mode: Mode,
+}
+
+enum Mode
+{
+    TwoPass
+    {
+        buffer: Vec<u8>,
+        options: CompressOptions,
+    },
+    OnePass
+    {
+        model: Model,
+        frame: Vec<u8>,
+        crc: Crc32,
+        len: u64,
+    },
+}
+
+enum Model
+{
+    Fixed(Vec<Option<Code>>),
+    Adaptive(AdaptiveTree),
+}
+
+impl<W: Write> HuffmanEncoder<W>
+{
+    // count the input before encoding it, nothing is written until finish
+    pub fn new(writer: W) -> Self
+    {
+        Self::with_options(writer, &CompressOptions::default()).expect("the defaults are valid")
+    }
+
+    pub fn with_options(writer: W, options: &CompressOptions) -> Result<Self, Error>
+    {
+        options.check()?;
+        Ok(Self { writer: Some(BitWriter::new(writer)),
+                  mode: Mode::TwoPass { buffer: Vec::new(), options: options.clone() } })
+    }
+
+    // encode the input in one pass with the code described by the code lengths, every byte that
+    // gets written needs a code
+    pub fn with_model(writer: W, lengths: &[u8]) -> Result<Self, Error>
+    {
+        validate_lengths(lengths)?;
+
+        let mut writer = BitWriter::new(writer);
+        Header::streamed().write(&mut writer)?;
+        write_code_lengths(&mut writer, lengths)?;
+
+        Ok(Self::one_pass(writer, Model::Fixed(canonical_codes(lengths))))
+    }
+
+    // encode the input in one pass with a built in model that suits text but can code any byte
+    pub fn with_static_model(writer: W) -> Result<Self, Error>
+    {
+        Self::with_model(writer, &static_code_lengths())
+    }
+
+    // encode the input in one pass with a code that adapts to the bytes seen so far
+    pub fn adaptive(writer: W) -> Result<Self, Error>
+    {
+        let mut writer = BitWriter::new(writer);
+        Header::adaptive().write(&mut writer)?;
+
+        Ok(Self::one_pass(writer, Model::Adaptive(AdaptiveTree::new())))
+    }
+
+    fn one_pass(writer: BitWriter<W>, model: Model) -> Self
+    {
+        Self { writer: Some(writer),
+               mode: Mode::OnePass { model,
+                                     frame: Vec::with_capacity(FRAME_LEN),
+                                     crc: Crc32::new(),
+                                     len: 0 } }
+    }
+
+    // write out everything that's left and hand back the writer, the writer is taken first so
+    // that if this fails drop doesn't try again
+    pub fn finish(mut self) -> Result<W, Error>
+    {
+        let mut writer = self.writer.take().expect("writer is only taken here");
+        finish_encoding(&mut writer, &mut self.mode)?;
+        writer.into_inner()
+    }
+}
+
+// write whatever the encoder is still holding on to, and the end of the data
+fn finish_encoding<W: Write>(writer: &mut BitWriter<W>, mode: &mut Mode) -> Result<(), Error>
+{
+    match mode
+    {
+        Mode::TwoPass { buffer, options } =>
+        {
+            let header = options.header(buffer.len() as u64, crc32(buffer));
+            write_blocks(writer,
+                         &header,
+                         buffer.chunks(options.block_len).map(Ok),
+                         options)
+        }
+        Mode::OnePass { model, frame, crc, len } =>
+        {
+            if !frame.is_empty()
+            {
+                write_frame(writer, model, frame)?;
+            }
+            // the empty frame marks the end
+            write_frame(writer, model, frame)?;
+            writer.write_bits(*len, 64)?;
+            writer.write_bits(crc.finish() as u64, 32)
+        }
+    }
+}
+
+// a frame is a 32 bit count followed by the codes
+fn write_frame<W: Write>(writer: &mut BitWriter<W>,
+                         model: &mut Model,
+                         frame: &mut Vec<u8>)
+                         -> Result<(), Error>
+{
+    writer.write_bits(frame.len() as u64, 32)?;
+    match model
+    {
+        Model::Fixed(codes) => write_codes(writer, codes, frame)?,
+        Model::Adaptive(tree) =>
+        {
+            for byte in frame.iter()
+            {
+                tree.encode(writer, *byte)?;
+            }
+        }
+    }
+    frame.clear();
+    Ok(())
+}
+
+impl<W: Write> Write for HuffmanEncoder<W>
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error>
+    {
+        let writer = self.writer.as_mut().expect("encoder is not finished");
+        match &mut self.mode
+        {
+            Mode::TwoPass { buffer, .. } => buffer.extend_from_slice(buf),
+            Mode::OnePass { model, frame, crc, len } =>
+            {
+                if let Model::Fixed(codes) = model
+                {
+                    if let Some(byte) = buf.iter().find(|byte| codes[**byte as usize].is_none())
+                    {
+                        return Err(Error::new(ErrorKind::InvalidInput,
+                                              format!("byte {:#04x} has no code in the model",
+                                                      byte)));
+                    }
+                }
+
+                for chunk in buf.chunks(FRAME_LEN)
+                {
+                    let take = chunk.len().min(FRAME_LEN - frame.len());
+                    frame.extend_from_slice(&chunk[..take]);
+                    if frame.len() == FRAME_LEN
+                    {
+                        write_frame(writer, model, frame)?;
+                        frame.extend_from_slice(&chunk[take..]);
+                    }
+                }
+                crc.update(buf);
+                *len += buf.len() as u64;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    // a one-pass encoder ends the current frame so everything written so far can be decoded, only
+    // the last partial byte is held back
+    fn flush(&mut self) -> Result<(), Error>
+    {
+        let writer = self.writer.as_mut().expect("encoder is not finished");
+        if let Mode::OnePass { model, frame, .. } = &mut self.mode
+        {
+            if !frame.is_empty()
+            {
+                write_frame(writer, model, frame)?;
+            }
+        }
+        writer.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for HuffmanEncoder<W>
+{
+    // best effort, call finish to find out whether it worked
+    fn drop(&mut self)
+    {
+        if let Some(mut writer) = self.writer.take()
+        {
+            if finish_encoding(&mut writer, &mut self.mode).is_ok()
+            {
+                let _ = writer.into_inner();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::uncompress::HuffmanDecoder;
+
+    struct TestFile
+    {
+        path: PathBuf,
+    }
+
+    impl TestFile
+    {
+        pub fn create(path: PathBuf) -> Self { Self { path } }
+    }
+
+    impl Drop for TestFile
+    {
+        fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+    }
+
+    fn decode(compressed: &[u8]) -> Vec<u8>
+    {
+        let mut decoder = HuffmanDecoder::new(compressed).expect("failed reading header");
+        let mut result = Vec::new();
+        decoder.read_to_end(&mut result).expect("failed decoding");
+        result
+    }
+
+    #[test]
+    fn test_two_pass_matches_compress()
+    {
+        let compressed = TestFile::create(PathBuf::from("kmd_CompressTwoPass.compressed"));
+        compress(Path::new("asyoulik.txt"), &compressed.path).expect("failed compressing");
+        let expected = std::fs::read(&compressed.path).expect("failed reading compressed");
+
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut encoder = HuffmanEncoder::new(Vec::new());
+        for chunk in contents.chunks(1000)
+        {
+            encoder.write_all(chunk).expect("failed writing");
+        }
+        let encoded = encoder.finish().expect("failed finishing");
+
+        assert_eq!(encoded, expected);
+        assert_eq!(decode(&encoded), contents);
+    }
+
+    #[test]
+    fn test_static_model()
+    {
+        // big enough to take a few frames
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        assert!(contents.len() > FRAME_LEN);
+
+        let mut encoder = HuffmanEncoder::with_static_model(Vec::new()).unwrap();
+        encoder.write_all(&contents).expect("failed writing");
+        let encoded = encoder.finish().expect("failed finishing");
+
+        assert!(encoded.len() < contents.len());
+        assert_eq!(decode(&encoded), contents);
+    }
+
+    #[test]
+    fn test_supplied_model()
+    {
+        let mut frequencies = FrequencyTable::new();
+        b"abc".iter().for_each(|byte| frequencies.add(*byte as u16));
+        let lengths = HuffmanTree::new(&frequencies).unwrap().code_lengths();
+
+        let mut encoder = HuffmanEncoder::with_model(Vec::new(), &lengths).unwrap();
+        encoder.write_all(b"abcabcaaa").expect("failed writing");
+        // flushing ends a frame part way through
+        encoder.flush().expect("failed flushing");
+        encoder.write_all(b"cba").expect("failed writing");
+        let error = encoder.write_all(b"abcd").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        let encoded = encoder.finish().expect("failed finishing");
+        assert_eq!(decode(&encoded), b"abcabcaaacba");
+    }
+
+    #[test]
+    fn test_empty()
+    {
+        let encoded = HuffmanEncoder::new(Vec::new()).finish().unwrap();
+        assert_eq!(decode(&encoded), b"");
+
+        let encoded = HuffmanEncoder::with_static_model(Vec::new()).unwrap()
+                                                                   .finish()
+                                                                   .unwrap();
+        assert_eq!(decode(&encoded), b"");
+    }
+
+    #[test]
+    fn test_adaptive()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut encoder = HuffmanEncoder::adaptive(Vec::new()).unwrap();
+        for chunk in contents.chunks(1000)
+        {
+            encoder.write_all(chunk).expect("failed writing");
+        }
+        // the tree carries on across a flush
+        encoder.flush().expect("failed flushing");
+        encoder.write_all(b"Hello World!").expect("failed writing");
+        let encoded = encoder.finish().expect("failed finishing");
+
+        assert!(encoded.len() < contents.len() * 2 / 3);
+        let mut expected = contents.clone();
+        expected.extend_from_slice(b"Hello World!");
+        assert_eq!(decode(&encoded), expected);
+
+        let encoded = HuffmanEncoder::adaptive(Vec::new()).unwrap()
+                                                          .finish()
+                                                          .unwrap();
+        assert_eq!(decode(&encoded), b"");
+    }
+
+    #[test]
+    fn test_compress_adaptive()
+    {
+        let compressed = TestFile::create(PathBuf::from("kmd_CompressAdaptive.compressed"));
+        compress_adaptive(Path::new("asyoulik.txt"), &compressed.path).expect("failed compressing");
+
+        let encoded = std::fs::read(&compressed.path).expect("failed reading compressed");
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        assert_eq!(decode(&encoded), contents);
+    }
+
+    #[test]
+    fn test_parallel_matches_single_thread()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let encode = |threads| {
+            let options = CompressOptions { block_len: 4096, threads, ..Default::default() };
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+            encoder.write_all(&contents).expect("failed writing");
+            encoder.finish().expect("failed finishing")
+        };
+
+        let single = encode(1);
+        for threads in [2, 3, 8]
+        {
+            assert_eq!(encode(threads), single);
+        }
+        assert_eq!(decode(&single), contents);
+
+        let compressed = TestFile::create(PathBuf::from("kmd_CompressParallel.compressed"));
+        compress_with(Path::new("asyoulik.txt"),
+                      &compressed.path,
+                      &CompressOptions { block_len: 4096, threads: 4, ..Default::default() }).expect("failed compressing");
+        assert_eq!(std::fs::read(&compressed.path).unwrap(), single);
+    }
+
+    // compressed sizes of order-0 against order-1, run with --nocapture to see the report
+    #[test]
+    fn test_order1_report()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let encode = |contents: &[u8], order1| {
+            let options = CompressOptions { order1, ..Default::default() };
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+            encoder.write_all(contents).expect("failed writing");
+            encoder.finish().expect("failed finishing")
+        };
+
+        println!("{:<16} {:>10} {:>10} {:>10} {:>8}",
+                 "input", "original", "order-0", "order-1", "saved");
+        for (name, contents) in [("asyoulik.txt", &text[..]),
+                                 ("asyoulik x 4", &text.repeat(4)[..]),
+                                 ("first 4 KiB", &text[..4096])]
+        {
+            let order0 = encode(contents, false);
+            let order1 = encode(contents, true);
+            assert_eq!(decode(&order1), contents);
+            println!("{:<16} {:>10} {:>10} {:>10} {:>7.1}%",
+                     name,
+                     contents.len(),
+                     order0.len(),
+                     order1.len(),
+                     100.0 * (1.0 - order1.len() as f64 / order0.len() as f64));
+
+            // tables for every context don't pay off on a small input
+            if contents.len() > 64 * 1024
+            {
+                assert!(order1.len() < order0.len() * 9 / 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_options()
+    {
+        for options in [CompressOptions { block_len: 0, ..Default::default() },
+                        CompressOptions { block_len: MAX_BLOCK_LEN + 1, ..Default::default() },
+                        CompressOptions { threads: 0, ..Default::default() }]
+        {
+            let error = HuffmanEncoder::with_options(Vec::new(), &options).err()
+                                                                          .unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_finish_on_drop()
+    {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = HuffmanEncoder::new(&mut encoded);
+            encoder.write_all(b"Hello World!").expect("failed writing");
+        }
+        assert_eq!(decode(&encoded), b"Hello World!");
+    }
+
+    // takes a few writes, then fails every one after that
+    struct FailingWriter
+    {
+        written: Vec<u8>,
+        writes_left: usize,
+        failed: usize,
+    }
+
+    impl Write for FailingWriter
+    {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Error>
+        {
+            if self.writes_left == 0
+            {
+                self.failed += 1;
+                return Err(Error::other("write failed"));
+            }
+            self.writes_left -= 1;
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+    }
+
+    #[test]
+    fn test_failed_finish_isnt_retried_on_drop()
+    {
+        let clean = {
+            let mut encoder = HuffmanEncoder::new(Vec::new());
+            encoder.write_all(b"Hello World!").expect("failed writing");
+            encoder.finish().expect("failed finishing")
+        };
+
+        let mut failing = FailingWriter { written: Vec::new(), writes_left: 2, failed: 0 };
+        let mut encoder = HuffmanEncoder::new(&mut failing);
+        encoder.write_all(b"Hello World!").expect("failed writing");
+        assert!(encoder.finish().is_err());
+
+        // only what got written before the failure, nothing from encoding again on drop
+        assert_eq!(failing.failed, 1);
+        assert!(clean.starts_with(&failing.written));
+        assert!(failing.written.len() < clean.len());
+    }
 }
@@ -0,0 +1,609 @@
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::transform::Transforms;
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// Every compressed file starts with a fixed size header, multi-byte fields are little endian
+//
+// offset  size  field
+//      0     4  magic, "HUF" followed by 0x1A
+//      4     1  format version
+//      5     1  flags, see the FLAG_ constants
+//      6     8  length of the original file in bytes
+//     14     4  CRC-32 of the original file
+//     18     1  codec the data is coded with, see Codec, from version 2 on
+//
+// the code lengths and the encoded data follow straight after
+pub const MAGIC: [u8; 4] = *b"HUF\x1A";
+pub const FORMAT_VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 19;
+
+// version 1 had no codec, everything was huffman coded
+const V1_HEADER_LEN: usize = 18;
+
+// the data was written in one pass so its length and checksum weren't known up front, both are 0
+// in the header, the data comes in frames of a 32 bit symbol count followed by that many codes, a
+// count of 0 ends the frames and is followed by a 64 bit length and the 32 bit CRC-32
+pub const FLAG_STREAMED: u8 = 0x01;
+
+// the codes come from an adaptive tree that the decoder updates after every symbol like the
+// encoder did, so there are no code lengths after the header
+pub const FLAG_ADAPTIVE: u8 = 0x02;
+
+// the data is split into blocks, see BlockHeader, each with its own code lengths or reusing those
+// of an earlier block
+pub const FLAG_BLOCKS: u8 = 0x04;
+
+// only with FLAG_BLOCKS, every block is coded with order-1 context tables, see ContextTables, in
+// place of a single set of code lengths
+pub const FLAG_ORDER1: u8 = 0x08;
+
+// only with FLAG_BLOCKS, the transforms every block went through before it was coded, see
+// Transforms, each transformed block has the length of its codes after its table
+pub const FLAG_BWT: u8 = 0x10;
+pub const FLAG_MTF: u8 = 0x20;
+pub const FLAG_RLE: u8 = 0x40;
+
+const TRANSFORM_FLAGS: u8 = FLAG_BWT | FLAG_MTF | FLAG_RLE;
+const KNOWN_FLAGS: u8 = FLAG_STREAMED | FLAG_ADAPTIVE | FLAG_BLOCKS | FLAG_ORDER1 | TRANSFORM_FLAGS;
+
+// how the symbols are turned into bits, the uncompressed data and the container around it are the
+// same either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec
+{
+    #[default]
+    Huffman,
+    // only with FLAG_BLOCKS, every block is range coded with scaled frequencies, see RangeModel,
+    // in place of code lengths
+    Range,
+    // only with FLAG_BLOCKS, every block is split into literals and LZ77 matches whose symbols
+    // are coded with two sets of code lengths, see Lz77Tables
+    Lz77,
+}
+
+impl Codec
+{
+    pub fn id(&self) -> u8
+    {
+        match self
+        {
+            Codec::Huffman => 0,
+            Codec::Range => 1,
+            Codec::Lz77 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self>
+    {
+        match id
+        {
+            0 => Some(Codec::Huffman),
+            1 => Some(Codec::Range),
+            2 => Some(Codec::Lz77),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header
+{
+    pub version: u8,
+    pub flags: u8,
+    pub original_len: u64,
+    pub crc32: u32,
+    pub codec: Codec,
+}
+
+impl Header
+{
+    pub fn new(original_len: u64, crc32: u32) -> Self
+    {
+        Self { version: FORMAT_VERSION, flags: 0, original_len, crc32, codec: Codec::Huffman }
+    }
+
+    pub fn blocks(original_len: u64, crc32: u32) -> Self
+    {
+        Self { flags: FLAG_BLOCKS, ..Self::new(original_len, crc32) }
+    }
+
+    pub fn streamed() -> Self { Self { flags: FLAG_STREAMED, ..Self::new(0, 0) } }
+
+    pub fn adaptive() -> Self { Self { flags: FLAG_STREAMED | FLAG_ADAPTIVE, ..Self::streamed() } }
+
+    pub fn is_streamed(&self) -> bool { self.flags & FLAG_STREAMED != 0 }
+
+    pub fn is_adaptive(&self) -> bool { self.flags & FLAG_ADAPTIVE != 0 }
+
+    pub fn has_blocks(&self) -> bool { self.flags & FLAG_BLOCKS != 0 }
+
+    pub fn is_order1(&self) -> bool { self.flags & FLAG_ORDER1 != 0 }
+
+    pub fn transforms(&self) -> Transforms
+    {
+        Transforms { bwt: self.flags & FLAG_BWT != 0,
+                     mtf: self.flags & FLAG_MTF != 0,
+                     rle: self.flags & FLAG_RLE != 0 }
+    }
+
+    pub fn with_transforms(self, transforms: Transforms) -> Self
+    {
+        let mut flags = self.flags & !TRANSFORM_FLAGS;
+        for (set, flag) in [(transforms.bwt, FLAG_BWT),
+                            (transforms.mtf, FLAG_MTF),
+                            (transforms.rle, FLAG_RLE)]
+        {
+            if set
+            {
+                flags |= flag;
+            }
+        }
+        Self { flags, ..self }
+    }
+
+    // bytes taken by the header, which is shorter for version 1
+    pub fn size(&self) -> usize
+    {
+        match self.version
+        {
+            1 => V1_HEADER_LEN,
+            _ => HEADER_LEN,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = vec![0; self.size()];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.flags;
+        bytes[6..14].copy_from_slice(&self.original_len.to_le_bytes());
+        bytes[14..18].copy_from_slice(&self.crc32.to_le_bytes());
+        if self.version > 1
+        {
+            bytes[18] = self.codec.id();
+        }
+        bytes
+    }
+
+    // parse and validate a header, anything after the header is ignored
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError>
+    {
+        // check the magic first so a short foreign file is reported as foreign, not truncated
+        let magic_len = bytes.len().min(MAGIC.len());
+        if bytes[..magic_len] != MAGIC[..magic_len]
+        {
+            return Err(FormatError::BadMagic);
+        }
+        if bytes.len() < V1_HEADER_LEN
+        {
+            return Err(FormatError::Truncated);
+        }
+
+        let version = bytes[4];
+        let codec = match version
+        {
+            1 => Codec::Huffman,
+            FORMAT_VERSION =>
+            {
+                let id = *bytes.get(18).ok_or(FormatError::Truncated)?;
+                Codec::from_id(id).ok_or(FormatError::UnsupportedCodec(id))?
+            }
+            _ => return Err(FormatError::UnsupportedVersion(version)),
+        };
+
+        let header = Self { version,
+                            flags: bytes[5],
+                            original_len: u64::from_le_bytes(bytes[6..14].try_into().unwrap()),
+                            crc32: u32::from_le_bytes(bytes[14..18].try_into().unwrap()),
+                            codec };
+
+        // blocks need the length up front, so can't be streamed, and only blocks can be order-1 or
+        // transformed
+        if header.flags & !KNOWN_FLAGS != 0
+           || (header.has_blocks()
+               && header.flags & !(FLAG_BLOCKS | FLAG_ORDER1 | TRANSFORM_FLAGS) != 0)
+           || (!header.has_blocks() && header.flags & (FLAG_ORDER1 | TRANSFORM_FLAGS) != 0)
+           || (header.codec != Codec::Huffman && (!header.has_blocks() || header.is_order1()))
+        {
+            return Err(FormatError::UnsupportedFlags(header.flags));
+        }
+
+        Ok(header)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), io::Error>
+    {
+        for byte in self.to_bytes()
+        {
+            writer.write_bits(byte as u64, 8)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> Result<Self, io::Error>
+    {
+        // the version says whether there's a codec after the first 18 bytes
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        while bytes.len() < V1_HEADER_LEN || (bytes.len() < HEADER_LEN && bytes[4] > 1)
+        {
+            match reader.read_bits(8)
+            {
+                Ok(byte) => bytes.push(byte as u8),
+                // let parse decide between foreign and truncated
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self::parse(&bytes)?)
+    }
+}
+
+// Every block starts on a byte boundary with a block header, multi-byte fields are little endian
+//
+// offset  size  field
+//      0     4  length of the block's data in bytes
+//      4     4  CRC-32 of the block's data
+//      8     4  length of the rest of the block in bytes, for skipping to the next block
+//     12     1  table, 0 if the block's code lengths follow, 1 if it reuses an earlier block's
+//     13     4  only when the table is reused, the index of the block with the code lengths
+//
+// the code lengths, if any, and the codes follow, padded out to a whole byte
+//
+// the last block is followed by a block header of all zeros, BlockHeader::END, and then the block
+// index so a reader that can seek finds every block without reading them all
+//
+//   size  field
+//  8 * n  the offset of every block from the start of the file, 8 bytes each
+//      4  number of blocks, n
+//      4  magic, "HIDX"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockHeader
+{
+    pub len: u32,
+    pub crc32: u32,
+    pub compressed_len: u32,
+    pub table: BlockTable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockTable
+{
+    Own,
+    // the index of the block the code lengths are in
+    Reuse(u32),
+}
+
+impl BlockHeader
+{
+    // marks the end of the blocks, no block is empty
+    pub const END: BlockHeader =
+        BlockHeader { len: 0, crc32: 0, compressed_len: 0, table: BlockTable::Own };
+
+    pub fn is_end(&self) -> bool { self.len == 0 }
+
+    // bytes taken by the block header itself
+    pub fn size(&self) -> usize
+    {
+        match self.table
+        {
+            BlockTable::Own => 13,
+            BlockTable::Reuse(_) => 17,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), io::Error>
+    {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes.extend_from_slice(&self.crc32.to_le_bytes());
+        bytes.extend_from_slice(&self.compressed_len.to_le_bytes());
+        match self.table
+        {
+            BlockTable::Own => bytes.push(0),
+            BlockTable::Reuse(index) =>
+            {
+                bytes.push(1);
+                bytes.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        for byte in bytes
+        {
+            writer.write_bits(byte as u64, 8)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> Result<Self, io::Error>
+    {
+        let len = read_u32(reader)?;
+        if len as usize > MAX_BLOCK_LEN
+        {
+            return Err(io::Error::new(ErrorKind::InvalidData,
+                                      format!("a block of {} bytes is longer than the longest \
+                                               block of {} bytes",
+                                              len, MAX_BLOCK_LEN)));
+        }
+        let crc32 = read_u32(reader)?;
+        let compressed_len = read_u32(reader)?;
+        let table = match read_byte(reader)?
+        {
+            0 => BlockTable::Own,
+            1 => BlockTable::Reuse(read_u32(reader)?),
+            table =>
+            {
+                return Err(io::Error::new(ErrorKind::InvalidData,
+                                          format!("unknown block table {}", table)))
+            }
+        };
+
+        Ok(Self { len, crc32, compressed_len, table })
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut BitReader<R>) -> Result<u32, io::Error>
+{
+    let mut bytes = [0u8; 4];
+    for byte in bytes.iter_mut()
+    {
+        *byte = read_byte(reader)?;
+    }
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_byte<R: Read>(reader: &mut BitReader<R>) -> Result<u8, io::Error>
+{
+    match reader.read_bits(8)
+    {
+        Ok(byte) => Ok(byte as u8),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(FormatError::Truncated.into()),
+        Err(e) => Err(e),
+    }
+}
+
+// keeps a block's length, and the length of its codes, within the 32 bits of the block header, and
+// what a corrupt block header can have the decoder allocate within reason
+pub const MAX_BLOCK_LEN: usize = 1 << 28;
+
+pub const BLOCK_INDEX_MAGIC: [u8; 4] = *b"HIDX";
+
+// the block count and magic at the very end of the file
+pub const BLOCK_INDEX_FOOTER_LEN: usize = 8;
+
+// write the end of the blocks and the block index
+pub fn write_block_index<W: Write>(writer: &mut BitWriter<W>,
+                                   offsets: &[u64])
+                                   -> Result<(), io::Error>
+{
+    BlockHeader::END.write(writer)?;
+
+    let count = u32::try_from(offsets.len()).map_err(|_| {
+                                                io::Error::new(ErrorKind::InvalidInput,
+                                                               "too many blocks for the index")
+                                            })?;
+    let mut bytes = offsets.iter()
+                           .flat_map(|offset| offset.to_le_bytes())
+                           .collect::<Vec<_>>();
+    bytes.extend_from_slice(&count.to_le_bytes());
+    bytes.extend_from_slice(&BLOCK_INDEX_MAGIC);
+    writer.write_bytes(&bytes)
+}
+
+// reasons a compressed file can be rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError
+{
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnsupportedFlags(u8),
+    UnsupportedCodec(u8),
+    Truncated,
+    LengthMismatch
+    {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch
+    {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl Display for FormatError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            FormatError::BadMagic => write!(f, "not a huffman compressed file"),
+            FormatError::UnsupportedVersion(version) =>
+            {
+                write!(f, "unsupported format version {}", version)
+            }
+            FormatError::UnsupportedFlags(flags) => write!(f, "unsupported flags {:#04x}", flags),
+            FormatError::UnsupportedCodec(codec) => write!(f, "unsupported codec {}", codec),
+            FormatError::Truncated => write!(f, "compressed data is truncated"),
+            FormatError::LengthMismatch { expected, actual } =>
+            {
+                write!(f, "expected {} bytes but decoded {}", expected, actual)
+            }
+            FormatError::ChecksumMismatch { expected, actual } =>
+            {
+                write!(f,
+                       "checksum mismatch, expected {:#010x} got {:#010x}",
+                       expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+// the compress and uncompress apis report io::Error, the FormatError can be recovered with
+// io::Error::get_ref and downcast_ref
+impl From<FormatError> for io::Error
+{
+    fn from(error: FormatError) -> Self
+    {
+        let kind = match error
+        {
+            FormatError::Truncated => ErrorKind::UnexpectedEof,
+            _ => ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_round_trip()
+    {
+        let header = Header::new(125179, 0xDEADBEEF);
+        let bytes = header.to_bytes();
+        assert_eq!(bytes[..4], *b"HUF\x1A");
+        assert_eq!(Header::parse(&bytes), Ok(header));
+    }
+
+    #[test]
+    fn test_bad_magic()
+    {
+        let mut bytes = Header::new(0, 0).to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(Header::parse(&bytes), Err(FormatError::BadMagic));
+        assert_eq!(Header::parse(b"PK"), Err(FormatError::BadMagic));
+    }
+
+    #[test]
+    fn test_unsupported_version()
+    {
+        let mut bytes = Header::new(0, 0).to_bytes();
+        bytes[4] = FORMAT_VERSION + 1;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedVersion(FORMAT_VERSION + 1)));
+    }
+
+    #[test]
+    fn test_unsupported_flags()
+    {
+        let mut bytes = Header::new(0, 0).to_bytes();
+        bytes[5] = 0x80;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(0x80)));
+    }
+
+    #[test]
+    fn test_blocks_only_alone()
+    {
+        let mut bytes = Header::blocks(0, 0).to_bytes();
+        assert!(Header::parse(&bytes).unwrap().has_blocks());
+        bytes[5] |= FLAG_ORDER1;
+        assert!(Header::parse(&bytes).unwrap().is_order1());
+        bytes[5] |= FLAG_STREAMED;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(FLAG_BLOCKS
+                                                     | FLAG_ORDER1
+                                                     | FLAG_STREAMED)));
+
+        bytes[5] = FLAG_ORDER1;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(FLAG_ORDER1)));
+    }
+
+    #[test]
+    fn test_transforms()
+    {
+        let header = Header::blocks(0, 0).with_transforms(Transforms::all());
+        let mut bytes = header.to_bytes();
+        assert_eq!(Header::parse(&bytes).unwrap().transforms(),
+                   Transforms::all());
+
+        let mtf = Transforms { mtf: true, ..Default::default() };
+        assert_eq!(header.with_transforms(mtf).flags, FLAG_BLOCKS | FLAG_MTF);
+
+        bytes[5] &= !FLAG_BLOCKS;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(FLAG_BWT
+                                                     | FLAG_MTF
+                                                     | FLAG_RLE)));
+    }
+
+    #[test]
+    fn test_codec()
+    {
+        let header = Header { codec: Codec::Range, ..Header::blocks(10, 0) };
+        let mut bytes = header.to_bytes();
+        assert_eq!(Header::parse(&bytes), Ok(header));
+
+        bytes[18] = 0xFF;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedCodec(0xFF)));
+
+        // the other codecs only code blocks, and only order-0
+        bytes[18] = Codec::Range.id();
+        bytes[5] |= FLAG_ORDER1;
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(FLAG_BLOCKS | FLAG_ORDER1)));
+        let bytes = Header { codec: Codec::Range, ..Header::adaptive() }.to_bytes();
+        assert_eq!(Header::parse(&bytes),
+                   Err(FormatError::UnsupportedFlags(FLAG_STREAMED | FLAG_ADAPTIVE)));
+    }
+
+    #[test]
+    fn test_version1()
+    {
+        // no codec byte, everything is huffman coded
+        let header = Header { version: 1, ..Header::blocks(125179, 0xDEADBEEF) };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 18);
+        assert_eq!(Header::parse(&bytes), Ok(header));
+
+        let mut reader = BitReader::new(&bytes[..]);
+        assert_eq!(Header::read(&mut reader).unwrap(), header);
+        assert_eq!(Header::read(&mut BitReader::new(&Header::new(3, 4).to_bytes()[..])).unwrap(),
+                   Header::new(3, 4));
+    }
+
+    #[test]
+    fn test_block_header_round_trip()
+    {
+        for table in [BlockTable::Own, BlockTable::Reuse(7)]
+        {
+            let header =
+                BlockHeader { len: 65536, crc32: 0xDEADBEEF, compressed_len: 40000, table };
+            let mut writer = BitWriter::new(Vec::new());
+            header.write(&mut writer).unwrap();
+            let bytes = writer.into_inner().unwrap();
+            assert_eq!(bytes.len(), header.size());
+
+            let mut reader = BitReader::new(&bytes[..]);
+            assert_eq!(BlockHeader::read(&mut reader).unwrap(), header);
+
+            let mut reader = BitReader::new(&bytes[..bytes.len() - 1]);
+            let error = BlockHeader::read(&mut reader).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_truncated()
+    {
+        let bytes = Header::new(0, 0).to_bytes();
+        assert_eq!(Header::parse(&bytes[..HEADER_LEN - 1]),
+                   Err(FormatError::Truncated));
+        assert_eq!(Header::parse(&bytes[..2]), Err(FormatError::Truncated));
+        assert_eq!(Header::parse(&[]), Err(FormatError::Truncated));
+    }
+}
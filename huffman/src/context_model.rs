@@ -0,0 +1,352 @@
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::canonical_codes;
+use crate::huffman_tree::code_lengths_size_bits;
+use crate::huffman_tree::coded_bits;
+use crate::huffman_tree::read_code_lengths;
+use crate::huffman_tree::read_value;
+use crate::huffman_tree::write_code;
+use crate::huffman_tree::write_code_lengths;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::huffman_tree::SymbolDecoder;
+use crate::huffman_tree::ALPHABET_SIZE;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// the context is the byte before, the first byte of a block has context 0
+const CONTEXTS: usize = ALPHABET_SIZE;
+
+// contexts seen fewer times than this always use the shared code lengths, their own would cost
+// more to write out than they could save
+pub const RARE_CONTEXT_COUNT: u64 = 64;
+
+// how often each byte follows each other byte
+pub struct ContextCounts
+{
+    contexts: Vec<FrequencyTable>,
+}
+
+impl ContextCounts
+{
+    pub fn new(block: &[u8]) -> Self
+    {
+        let mut contexts = (0..CONTEXTS).map(|_| FrequencyTable::new())
+                                        .collect::<Vec<_>>();
+        let mut context = 0;
+        for byte in block
+        {
+            contexts[context].add(*byte as u16);
+            context = *byte as usize;
+        }
+        Self { contexts }
+    }
+
+    pub fn context(&self, context: u8) -> &FrequencyTable { &self.contexts[context as usize] }
+}
+
+// Code lengths for order-1 coding, where the code for a byte depends on the byte before it
+//
+// A context seen often enough gets code lengths of its own if they're smaller than using the code
+// for the whole block, every other context shares code lengths built from their counts together.
+//
+// written out as a bit saying whether there are shared lengths and then those lengths, a bit per
+// context saying whether it has its own, and then the lengths for each context that does
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextTables
+{
+    shared: Option<Vec<u8>>,
+    contexts: Vec<Option<Vec<u8>>>,
+}
+
+impl ContextTables
+{
+    pub fn new(counts: &ContextCounts) -> Self
+    {
+        let mut all = FrequencyTable::new();
+        for table in &counts.contexts
+        {
+            table.symbols()
+                 .for_each(|(symbol, count)| all.add_count(symbol, count));
+        }
+
+        let mut contexts = vec![None; CONTEXTS];
+        let Some(all_lengths) = lengths(&all)
+        else
+        {
+            return Self { shared: None, contexts };
+        };
+
+        let mut shared = FrequencyTable::new();
+        for (context, table) in counts.contexts.iter().enumerate()
+        {
+            let total = table.symbols().map(|(_, count)| count).sum::<u64>();
+            let own = lengths(table).filter(|own| {
+                                        let own_bits = code_lengths_size_bits(own)
+                                                       + coded_bits(table, own).unwrap();
+                                        total >= RARE_CONTEXT_COUNT
+                                        && own_bits < coded_bits(table, &all_lengths).unwrap()
+                                    });
+
+            match own
+            {
+                Some(own) => contexts[context] = Some(own),
+                None => table.symbols()
+                             .for_each(|(symbol, count)| shared.add_count(symbol, count)),
+            }
+        }
+
+        Self { shared: lengths(&shared), contexts }
+    }
+
+    // the code lengths used after this byte, None if there aren't any
+    pub fn lengths(&self, context: u8) -> Option<&[u8]>
+    {
+        self.contexts[context as usize].as_deref()
+                                       .or(self.shared.as_deref())
+    }
+
+    // contexts with code lengths of their own
+    pub fn own_contexts(&self) -> usize { self.contexts.iter().flatten().count() }
+
+    // number of bits write will use
+    pub fn size_bits(&self) -> usize
+    {
+        1
+        + self.shared.as_deref().map_or(0, code_lengths_size_bits)
+        + CONTEXTS
+        + self.contexts
+              .iter()
+              .flatten()
+              .map(|lengths| code_lengths_size_bits(lengths))
+              .sum::<usize>()
+    }
+
+    // the bits taken by the codes for everything counted, None if a byte has no code
+    pub fn coded_bits(&self, counts: &ContextCounts) -> Option<usize>
+    {
+        counts.contexts
+              .iter()
+              .enumerate()
+              .filter(|(_, table)| table.symbols().next().is_some())
+              .map(|(context, table)| coded_bits(table, self.lengths(context as u8)?))
+              .sum()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        writer.write_bits(self.shared.is_some() as u64, 1)?;
+        if let Some(shared) = &self.shared
+        {
+            write_code_lengths(writer, shared)?;
+        }
+
+        for lengths in &self.contexts
+        {
+            writer.write_bits(lengths.is_some() as u64, 1)?;
+        }
+        for lengths in self.contexts.iter().flatten()
+        {
+            write_code_lengths(writer, lengths)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> Result<Self, Error>
+    {
+        let shared = match read_value(reader, 1)?
+        {
+            1 => Some(read_code_lengths(reader)?),
+            _ => None,
+        };
+
+        let own = (0..CONTEXTS).map(|_| Ok(read_value(reader, 1)? == 1))
+                               .collect::<Result<Vec<_>, Error>>()?;
+        let contexts = own.into_iter()
+                          .map(|own| own.then(|| read_code_lengths(reader)).transpose())
+                          .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self { shared, contexts })
+    }
+
+    // write a code for every byte of the block, each one from the table for the byte before
+    pub fn encode<W: Write>(&self, writer: &mut BitWriter<W>, block: &[u8]) -> Result<(), Error>
+    {
+        let shared = self.shared.as_deref().map(canonical_codes);
+        let contexts = self.contexts
+                           .iter()
+                           .map(|lengths| lengths.as_deref().map(canonical_codes))
+                           .collect::<Vec<_>>();
+
+        let mut context = 0;
+        for byte in block
+        {
+            let codes = contexts[context].as_ref()
+                                         .or(shared.as_ref())
+                                         .expect("every context seen has code lengths");
+            write_code(writer,
+                       &codes[*byte as usize].expect("every byte has a code"))?;
+            context = *byte as usize;
+        }
+        Ok(())
+    }
+}
+
+fn lengths(frequencies: &FrequencyTable) -> Option<Vec<u8>>
+{
+    HuffmanTree::new(frequencies).map(|tree| tree.code_lengths())
+}
+
+// Decodes what ContextTables::encode wrote, keeping track of the byte before
+pub struct ContextDecoder
+{
+    decoders: Vec<SymbolDecoder>,
+    // the decoder for each context, None when the context has no code lengths
+    contexts: Vec<Option<usize>>,
+    context: u8,
+}
+
+impl ContextDecoder
+{
+    pub fn new(tables: &ContextTables) -> Result<Self, Error>
+    {
+        let mut decoders = Vec::new();
+        let shared = match &tables.shared
+        {
+            Some(lengths) =>
+            {
+                decoders.push(SymbolDecoder::from_lengths(lengths)?);
+                Some(0)
+            }
+            None => None,
+        };
+
+        let mut contexts = Vec::with_capacity(CONTEXTS);
+        for lengths in &tables.contexts
+        {
+            contexts.push(match lengths
+                          {
+                              Some(lengths) =>
+                              {
+                                  decoders.push(SymbolDecoder::from_lengths(lengths)?);
+                                  Some(decoders.len() - 1)
+                              }
+                              None => shared,
+                          });
+        }
+
+        Ok(Self { decoders, contexts, context: 0 })
+    }
+
+    // go back to the context a block starts in
+    pub fn reset(&mut self) { self.context = 0; }
+
+    pub fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<u8, Error>
+    {
+        let Some(decoder) = self.contexts[self.context as usize]
+        else
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("no code lengths for the bytes after {:#04x}",
+                                          self.context)));
+        };
+
+        let byte = self.decoders[decoder].decode(reader)? as u8;
+        self.context = byte;
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn round_trip(block: &[u8]) -> ContextTables
+    {
+        let counts = ContextCounts::new(block);
+        let tables = ContextTables::new(&counts);
+
+        let mut writer = BitWriter::new(Vec::new());
+        tables.write(&mut writer).unwrap();
+        tables.encode(&mut writer, block).unwrap();
+        assert_eq!(writer.bits_written() as usize,
+                   tables.size_bits() + tables.coded_bits(&counts).unwrap());
+        let encoded = writer.into_inner().unwrap();
+
+        let mut reader = BitReader::new(&encoded[..]);
+        let read = ContextTables::read(&mut reader).unwrap();
+        assert_eq!(read, tables);
+        let mut decoder = ContextDecoder::new(&read).unwrap();
+        for byte in block
+        {
+            assert_eq!(decoder.decode(&mut reader).unwrap(), *byte);
+        }
+        tables
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let tables = round_trip(&text);
+        assert!(tables.own_contexts() > 0);
+        assert!(tables.shared.is_some());
+    }
+
+    #[test]
+    fn test_rare_contexts_share()
+    {
+        // every context is seen less than RARE_CONTEXT_COUNT times
+        let tables = round_trip(b"the quick brown fox jumps over the lazy dog");
+        assert_eq!(tables.own_contexts(), 0);
+        assert!(tables.shared.is_some());
+    }
+
+    #[test]
+    fn test_frequent_contexts()
+    {
+        // random lowercase letters each followed by the same letter in uppercase
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut block = Vec::new();
+        for _ in 0..4000
+        {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let letter = b'a' + (state % 16) as u8;
+            block.extend_from_slice(&[letter, letter.to_ascii_uppercase()]);
+        }
+
+        let tables = round_trip(&block);
+        assert_eq!(tables.own_contexts(), 32);
+        // the uppercase letter after a lowercase one costs nothing more than a bit
+        assert_eq!(tables.lengths(b'a').unwrap()[b'A' as usize], 1);
+
+        // order-0 needs about 5 bits for every letter, order-1 about 4 and then 1
+        let mut all = FrequencyTable::new();
+        block.iter().for_each(|byte| all.add(*byte as u16));
+        let order0 = coded_bits(&all, &lengths(&all).unwrap()).unwrap();
+        let order1 = tables.coded_bits(&ContextCounts::new(&block)).unwrap();
+        assert!(order0 >= block.len() * 5 - 100);
+        assert!(order1 <= block.len() / 2 * 5);
+    }
+
+    #[test]
+    fn test_empty()
+    {
+        let tables = round_trip(b"");
+        assert_eq!(tables.lengths(0), None);
+    }
+
+    #[test]
+    fn test_missing_context()
+    {
+        let tables = ContextTables { shared: None, contexts: vec![None; CONTEXTS] };
+        let mut decoder = ContextDecoder::new(&tables).unwrap();
+        let error = decoder.decode(&mut BitReader::new(&[0u8][..])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,87 @@
+// CRC-32 as used by zip, gzip and png (reflected, polynomial 0xEDB88320)
+
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256]
+{
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256
+    {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8
+        {
+            crc = if crc & 1 == 1
+            {
+                crc >> 1 ^ POLYNOMIAL
+            }
+            else
+            {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[derive(Debug, Clone)]
+pub struct Crc32
+{
+    crc: u32,
+}
+
+impl Crc32
+{
+    pub fn new() -> Self { Self { crc: !0 } }
+
+    pub fn update(&mut self, bytes: &[u8])
+    {
+        for byte in bytes
+        {
+            self.crc = TABLE[((self.crc ^ *byte as u32) & 0xFF) as usize] ^ self.crc >> 8;
+        }
+    }
+
+    pub fn finish(&self) -> u32 { !self.crc }
+}
+
+impl Default for Crc32
+{
+    fn default() -> Self { Self::new() }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32
+{
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_check_value()
+    {
+        // the standard check value for CRC-32
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_incremental()
+    {
+        let mut crc = Crc32::new();
+        crc.update(b"Hello ");
+        crc.update(b"World!");
+        assert_eq!(crc.finish(), crc32(b"Hello World!"));
+    }
+}
@@ -0,0 +1,809 @@
+// Raw DEFLATE streams as described in RFC 1951
+//
+// The matches come from the LZ77 match finder and the codes from the same length limited trees
+// the LZ77 codec uses, only written the way DEFLATE lays them out: least significant bit first,
+// with each code word reversed so its first bit comes out first.
+
+use crate::crc32::Crc32;
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::canonical_codes;
+use crate::huffman_tree::coded_bits;
+use crate::huffman_tree::read_value;
+use crate::huffman_tree::Code;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::lz77::distance_base;
+use crate::lz77::distance_symbol;
+use crate::lz77::find_matches_from;
+use crate::lz77::length_symbol;
+use crate::lz77::MatchOptions;
+use crate::lz77::Token;
+use crate::lz77::LENGTH_BASE;
+use crate::lz77::LENGTH_EXTRA_BITS;
+use crate::lz77::MAX_LZ77_CODE_LENGTH;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// how far back a match can reach
+pub const DEFLATE_WINDOW: usize = 32 * 1024;
+// the input for each block, the most a stored block can hold
+const BLOCK_LEN: usize = u16::MAX as usize;
+
+const END_OF_BLOCK: u16 = 256;
+const FIRST_LENGTH_SYMBOL: u16 = 257;
+const LITERAL_LENGTHS: usize = 286;
+const DISTANCES: usize = 30;
+// the fixed codes have room for two more of each that are never used
+const FIXED_LITERAL_LENGTHS: usize = 288;
+const FIXED_DISTANCES: usize = 32;
+
+const STORED: u64 = 0;
+const FIXED: u64 = 1;
+const DYNAMIC: u64 = 2;
+
+// the code lengths of a dynamic block are themselves coded, with three symbols for runs
+const CODE_LENGTH_SYMBOLS: usize = 19;
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+const REPEAT_PREVIOUS: u16 = 16;
+const SHORT_ZERO_RUN: u16 = 17;
+const LONG_ZERO_RUN: u16 = 18;
+// the code length code lengths are written in this order so the ones that are rarely used can be
+// left off the end
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_SYMBOLS] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// decoded output is written out once this much is waiting behind the window
+const OUTPUT_FLUSH: usize = 4 * DEFLATE_WINDOW;
+
+// Compress everything the reader has into the writer as a DEFLATE stream, returning the length
+// and CRC-32 of what was read
+//
+// The writer has to write least significant bit first. The stream ends part way through a byte,
+// anything that follows it starts on the next byte boundary.
+pub fn deflate<R: Read, W: Write>(reader: &mut R,
+                                  writer: &mut BitWriter<W>,
+                                  matching: &MatchOptions)
+                                  -> Result<(u64, u32), Error>
+{
+    let matching = MatchOptions { window: matching.window.min(DEFLATE_WINDOW), ..matching.clone() };
+    let mut crc = Crc32::new();
+    let mut len = 0u64;
+
+    // the window of earlier input followed by the block being compressed, the block after it is
+    // read first so the last block can be marked
+    let mut data = Vec::new();
+    let mut next = read_chunk(reader, BLOCK_LEN)?;
+    loop
+    {
+        let block = std::mem::replace(&mut next, read_chunk(reader, BLOCK_LEN)?);
+        let last = next.is_empty();
+        crc.update(&block);
+        len += block.len() as u64;
+
+        let start = data.len();
+        data.extend_from_slice(&block);
+        let tokens = find_matches_from(&data, start, &matching);
+        write_block(writer, &data[start..], &tokens, last)?;
+
+        if last
+        {
+            return Ok((len, crc.finish()));
+        }
+        data.drain(..data.len().saturating_sub(DEFLATE_WINDOW));
+    }
+}
+
+fn read_chunk<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, Error>
+{
+    let mut chunk = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+// how often each symbol comes up in a block's tokens, end of block included
+struct Symbols
+{
+    literal_lengths: FrequencyTable,
+    distances: FrequencyTable,
+    extra_bits: usize,
+}
+
+impl Symbols
+{
+    fn new(tokens: &[Token]) -> Self
+    {
+        let mut literal_lengths = FrequencyTable::with_alphabet(LITERAL_LENGTHS);
+        let mut distances = FrequencyTable::with_alphabet(DISTANCES);
+        let mut extra_bits = 0;
+        for token in tokens
+        {
+            match *token
+            {
+                Token::Literal(byte) => literal_lengths.add(byte as u16),
+                Token::Match { length, distance } =>
+                {
+                    let (length, length_bits, _) = length_symbol(length);
+                    let (distance, distance_bits, _) = distance_symbol(distance);
+                    literal_lengths.add(length);
+                    distances.add(distance);
+                    extra_bits += length_bits as usize + distance_bits as usize;
+                }
+            }
+        }
+        literal_lengths.add(END_OF_BLOCK);
+        Self { literal_lengths, distances, extra_bits }
+    }
+
+    // the bits the codes with these lengths take, None if a symbol has no code
+    fn coded_bits(&self, literal_lengths: &[u8], distances: &[u8]) -> Option<usize>
+    {
+        Some(coded_bits(&self.literal_lengths, literal_lengths)?
+             + coded_bits(&self.distances, distances)?
+             + self.extra_bits)
+    }
+}
+
+// write whichever of a stored, fixed or dynamic block comes out smallest
+fn write_block<W: Write>(writer: &mut BitWriter<W>,
+                         block: &[u8],
+                         tokens: &[Token],
+                         last: bool)
+                         -> Result<(), Error>
+{
+    let symbols = Symbols::new(tokens);
+    let (fixed_literal_lengths, fixed_distances) = fixed_lengths();
+    let fixed = DynamicHeader::BLOCK_TYPE_BITS
+                + symbols.coded_bits(&fixed_literal_lengths, &fixed_distances)
+                         .expect("the fixed codes cover every symbol");
+    let dynamic = DynamicHeader::new(&symbols);
+    let dynamic_bits = dynamic.as_ref().map(|header| {
+                                           DynamicHeader::BLOCK_TYPE_BITS
+                                           + header.size_bits()
+                                           + symbols.coded_bits(&header.literal_lengths,
+                                                                &header.distances)
+                                                    .expect("the dynamic codes cover every symbol")
+                                       });
+    // the worst case for padding out to the byte boundary
+    let stored = DynamicHeader::BLOCK_TYPE_BITS + 7 + 32 + 8 * block.len();
+
+    writer.write_bits(last as u64, 1)?;
+    match dynamic_bits
+    {
+        _ if stored < fixed.min(dynamic_bits.unwrap_or(usize::MAX)) =>
+        {
+            writer.write_bits(STORED, 2)?;
+            writer.align_to_byte()?;
+            writer.write_bits(block.len() as u64, 16)?;
+            writer.write_bits(!block.len() as u64 & 0xFFFF, 16)?;
+            writer.write_bytes(block)
+        }
+        Some(dynamic_bits) if dynamic_bits < fixed =>
+        {
+            let header = dynamic.expect("there are dynamic codes");
+            writer.write_bits(DYNAMIC, 2)?;
+            header.write(writer)?;
+            write_tokens(writer, tokens, &header.literal_lengths, &header.distances)
+        }
+        _ =>
+        {
+            writer.write_bits(FIXED, 2)?;
+            write_tokens(writer, tokens, &fixed_literal_lengths, &fixed_distances)
+        }
+    }
+}
+
+fn write_tokens<W: Write>(writer: &mut BitWriter<W>,
+                          tokens: &[Token],
+                          literal_lengths: &[u8],
+                          distances: &[u8])
+                          -> Result<(), Error>
+{
+    let literal_lengths = canonical_codes(literal_lengths);
+    let distances = canonical_codes(distances);
+    let code = |codes: &[Option<Code>], symbol: u16| {
+        codes[symbol as usize].expect("every symbol in the block has a code")
+    };
+
+    for token in tokens
+    {
+        match *token
+        {
+            Token::Literal(byte) => write_reversed(writer, &code(&literal_lengths, byte as u16))?,
+            Token::Match { length, distance } =>
+            {
+                let (length, length_bits, length_extra) = length_symbol(length);
+                let (distance, distance_bits, distance_extra) = distance_symbol(distance);
+                write_reversed(writer, &code(&literal_lengths, length))?;
+                writer.write_bits(length_extra as u64, length_bits as u32)?;
+                write_reversed(writer, &code(&distances, distance))?;
+                writer.write_bits(distance_extra as u64, distance_bits as u32)?;
+            }
+        }
+    }
+    write_reversed(writer, &code(&literal_lengths, END_OF_BLOCK))
+}
+
+// a code word goes out first bit first, backwards from how values are written
+fn write_reversed<W: Write>(writer: &mut BitWriter<W>, code: &Code) -> Result<(), Error>
+{
+    writer.write_bits(code.bits.reverse_bits() >> (64 - code.len), code.len as u32)
+}
+
+// the codes a fixed block uses, given in RFC 1951
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>)
+{
+    let literal_lengths = (0..FIXED_LITERAL_LENGTHS).map(|symbol| match symbol
+                                                    {
+                                                        0..=143 => 8,
+                                                        144..=255 => 9,
+                                                        256..=279 => 7,
+                                                        _ => 8,
+                                                    })
+                                                    .collect();
+    (literal_lengths, vec![5; FIXED_DISTANCES])
+}
+
+// one of the code lengths of a dynamic block after runs are collapsed, with its extra bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LengthCode
+{
+    symbol: u16,
+    extra_bits: u8,
+    extra: u8,
+}
+
+impl LengthCode
+{
+    fn length(len: u8) -> Self { Self { symbol: len as u16, extra_bits: 0, extra: 0 } }
+}
+
+// the code lengths for a dynamic block and how they're written
+struct DynamicHeader
+{
+    literal_lengths: Vec<u8>,
+    distances: Vec<u8>,
+    // the literal and length code lengths followed by the distance ones, run length coded
+    length_codes: Vec<LengthCode>,
+    code_length_lengths: Vec<u8>,
+    // how many code length code lengths are written, in CODE_LENGTH_ORDER
+    code_length_count: usize,
+}
+
+impl DynamicHeader
+{
+    // the final block bit and block type
+    const BLOCK_TYPE_BITS: usize = 3;
+
+    // None when a fixed block is as good as it gets, which is when there's no more than end of
+    // block to code
+    fn new(symbols: &Symbols) -> Option<Self>
+    {
+        if symbols.literal_lengths.symbols().count() < 2
+        {
+            return None;
+        }
+        let literal_lengths = HuffmanTree::with_max_length(&symbols.literal_lengths,
+                                                           MAX_LZ77_CODE_LENGTH)?.code_lengths();
+        let distances = match HuffmanTree::with_max_length(&symbols.distances, MAX_LZ77_CODE_LENGTH)
+        {
+            Some(tree) => tree.code_lengths(),
+            None => vec![0; DISTANCES],
+        };
+
+        // at least the bytes and end of block, and one distance code even if it isn't used
+        let used = |lengths: &[u8], min| {
+            lengths.iter()
+                   .rposition(|len| *len > 0)
+                   .map_or(min, |last| (last + 1).max(min))
+        };
+        let mut lengths = literal_lengths[..used(&literal_lengths, 257)].to_vec();
+        let literal_length_count = lengths.len();
+        lengths.extend_from_slice(&distances[..used(&distances, 1)]);
+        let length_codes = length_codes(&lengths);
+
+        let mut frequencies = FrequencyTable::with_alphabet(CODE_LENGTH_SYMBOLS);
+        for code in &length_codes
+        {
+            frequencies.add(code.symbol);
+        }
+        // a lone code length symbol would get a one bit code with the other half of the code
+        // left empty, which decoders are entitled to refuse, so it gets a partner it never uses
+        if frequencies.symbols().count() < 2
+        {
+            frequencies.add(if frequencies.count(0) == 0 { 0 } else { 1 });
+        }
+        let code_length_lengths =
+            HuffmanTree::with_max_length(&frequencies, MAX_CODE_LENGTH_CODE_LENGTH)?.code_lengths();
+        let code_length_count =
+            CODE_LENGTH_ORDER.iter()
+                             .rposition(|symbol| code_length_lengths[*symbol] > 0)
+                             .map_or(4, |last| (last + 1).max(4));
+
+        Some(Self { literal_lengths: lengths[..literal_length_count].to_vec(),
+                    distances: lengths[literal_length_count..].to_vec(),
+                    length_codes,
+                    code_length_lengths,
+                    code_length_count })
+    }
+
+    // the bits write will use, not counting the block type
+    fn size_bits(&self) -> usize
+    {
+        5
+        + 5
+        + 4
+        + 3 * self.code_length_count
+        + self.length_codes
+              .iter()
+              .map(|code| {
+                  self.code_length_lengths[code.symbol as usize] as usize + code.extra_bits as usize
+              })
+              .sum::<usize>()
+    }
+
+    fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        writer.write_bits(self.literal_lengths.len() as u64 - 257, 5)?;
+        writer.write_bits(self.distances.len() as u64 - 1, 5)?;
+        writer.write_bits(self.code_length_count as u64 - 4, 4)?;
+        for symbol in &CODE_LENGTH_ORDER[..self.code_length_count]
+        {
+            writer.write_bits(self.code_length_lengths[*symbol] as u64, 3)?;
+        }
+
+        let codes = canonical_codes(&self.code_length_lengths);
+        for code in &self.length_codes
+        {
+            write_reversed(writer,
+                           &codes[code.symbol as usize].expect("every length symbol has a code"))?;
+            writer.write_bits(code.extra as u64, code.extra_bits as u32)?;
+        }
+        Ok(())
+    }
+}
+
+// collapse runs in the code lengths, zeros into runs of up to 138 and anything else into repeats
+// of up to 6 after the first
+fn length_codes(lengths: &[u8]) -> Vec<LengthCode>
+{
+    let mut codes = Vec::new();
+    let mut index = 0;
+    while index < lengths.len()
+    {
+        let len = lengths[index];
+        let run = lengths[index..].iter()
+                                  .take_while(|other| **other == len)
+                                  .count();
+        index += run;
+
+        if len == 0
+        {
+            let mut left = run;
+            while left >= 3
+            {
+                let take = left.min(138);
+                codes.push(match take
+                           {
+                               3..=10 => LengthCode { symbol: SHORT_ZERO_RUN,
+                                                      extra_bits: 3,
+                                                      extra: (take - 3) as u8 },
+                               _ => LengthCode { symbol: LONG_ZERO_RUN,
+                                                 extra_bits: 7,
+                                                 extra: (take - 11) as u8 },
+                           });
+                left -= take;
+            }
+            codes.extend(std::iter::repeat_n(LengthCode::length(0), left));
+        }
+        else
+        {
+            codes.push(LengthCode::length(len));
+            let mut left = run - 1;
+            while left >= 3
+            {
+                let take = left.min(6);
+                codes.push(LengthCode { symbol: REPEAT_PREVIOUS,
+                                        extra_bits: 2,
+                                        extra: (take - 3) as u8 });
+                left -= take;
+            }
+            codes.extend(std::iter::repeat_n(LengthCode::length(len), left));
+        }
+    }
+    codes
+}
+
+// Decompress a DEFLATE stream from the reader into the writer, returning the length and CRC-32 of
+// what was written
+//
+// The reader has to read least significant bit first. It's left part way through the last byte
+// of the stream, aligning it to a byte gets to whatever follows.
+pub fn inflate<R: Read, W: Write>(reader: &mut BitReader<R>,
+                                  writer: &mut W)
+                                  -> Result<(u64, u32), Error>
+{
+    let mut output = Output::new(writer);
+    loop
+    {
+        let last = read_value(reader, 1)? == 1;
+        match read_value(reader, 2)?
+        {
+            STORED => inflate_stored(reader, &mut output)?,
+            FIXED =>
+            {
+                let (literal_lengths, distances) = fixed_lengths();
+                inflate_codes(reader,
+                              &mut output,
+                              &CanonicalDecoder::new(&literal_lengths)?,
+                              &CanonicalDecoder::new(&distances)?)?;
+            }
+            DYNAMIC =>
+            {
+                let (literal_lengths, distances) = read_dynamic_header(reader)?;
+                inflate_codes(reader, &mut output, &literal_lengths, &distances)?;
+            }
+            _ => return Err(invalid("reserved DEFLATE block type")),
+        }
+        if last
+        {
+            return output.finish();
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error { Error::new(ErrorKind::InvalidData, msg) }
+
+fn inflate_stored<R: Read, W: Write>(reader: &mut BitReader<R>,
+                                     output: &mut Output<W>)
+                                     -> Result<(), Error>
+{
+    reader.align_to_byte();
+    let len = read_value(reader, 16)?;
+    let complement = read_value(reader, 16)?;
+    if len != !complement & 0xFFFF
+    {
+        return Err(invalid("stored block length doesn't match its complement"));
+    }
+    for _ in 0..len
+    {
+        output.push(read_value(reader, 8)? as u8)?;
+    }
+    Ok(())
+}
+
+fn inflate_codes<R: Read, W: Write>(reader: &mut BitReader<R>,
+                                    output: &mut Output<W>,
+                                    literal_lengths: &CanonicalDecoder,
+                                    distances: &CanonicalDecoder)
+                                    -> Result<(), Error>
+{
+    loop
+    {
+        let symbol = literal_lengths.decode(reader)?;
+        if symbol < END_OF_BLOCK
+        {
+            output.push(symbol as u8)?;
+            continue;
+        }
+        if symbol == END_OF_BLOCK
+        {
+            return Ok(());
+        }
+
+        let index = (symbol - FIRST_LENGTH_SYMBOL) as usize;
+        if index >= LENGTH_BASE.len()
+        {
+            return Err(invalid("invalid DEFLATE length symbol"));
+        }
+        let length = LENGTH_BASE[index] as usize
+                     + read_value(reader, LENGTH_EXTRA_BITS[index] as u32)? as usize;
+
+        let symbol = distances.decode(reader)?;
+        if symbol as usize >= DISTANCES
+        {
+            return Err(invalid("invalid DEFLATE distance symbol"));
+        }
+        let (base, extra_bits) = distance_base(symbol);
+        let distance = base as usize + read_value(reader, extra_bits as u32)? as usize;
+        output.copy(distance, length)?;
+    }
+}
+
+// the literal and length, and distance, decoders for a dynamic block
+fn read_dynamic_header<R: Read>(reader: &mut BitReader<R>)
+                                -> Result<(CanonicalDecoder, CanonicalDecoder), Error>
+{
+    let literal_length_count = read_value(reader, 5)? as usize + 257;
+    let distance_count = read_value(reader, 5)? as usize + 1;
+    let code_length_count = read_value(reader, 4)? as usize + 4;
+    if literal_length_count > LITERAL_LENGTHS || distance_count > DISTANCES
+    {
+        return Err(invalid("too many DEFLATE code lengths"));
+    }
+
+    let mut code_length_lengths = [0u8; CODE_LENGTH_SYMBOLS];
+    for symbol in &CODE_LENGTH_ORDER[..code_length_count]
+    {
+        code_length_lengths[*symbol] = read_value(reader, 3)? as u8;
+    }
+    let code_lengths = CanonicalDecoder::new(&code_length_lengths)?;
+
+    let total = literal_length_count + distance_count;
+    let mut lengths = Vec::with_capacity(total);
+    while lengths.len() < total
+    {
+        let (len, run) = match code_lengths.decode(reader)?
+        {
+            REPEAT_PREVIOUS =>
+            {
+                let previous =
+                    *lengths.last()
+                            .ok_or_else(|| invalid("repeat with no code length before it"))?;
+                (previous, 3 + read_value(reader, 2)? as usize)
+            }
+            SHORT_ZERO_RUN => (0, 3 + read_value(reader, 3)? as usize),
+            LONG_ZERO_RUN => (0, 11 + read_value(reader, 7)? as usize),
+            len => (len as u8, 1),
+        };
+        if lengths.len() + run > total
+        {
+            return Err(invalid("code length run is past the end of the code lengths"));
+        }
+        lengths.resize(lengths.len() + run, len);
+    }
+
+    if lengths[END_OF_BLOCK as usize] == 0
+    {
+        return Err(invalid("DEFLATE block has no end of block code"));
+    }
+    Ok((CanonicalDecoder::new(&lengths[..literal_length_count])?,
+        CanonicalDecoder::new(&lengths[literal_length_count..])?))
+}
+
+// Decodes canonical codes a bit at a time, the way zlib's puff does
+//
+// The codes of each length are consecutive, so a code is found by working through the lengths
+// keeping track of the first code of each one and the symbols that came before it. DEFLATE allows
+// codes that don't fill the code space, reading one of the missing codes is an error.
+struct CanonicalDecoder
+{
+    // how many codes there are of each length
+    counts: [u16; MAX_LZ77_CODE_LENGTH as usize + 1],
+    // the symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl CanonicalDecoder
+{
+    fn new(lengths: &[u8]) -> Result<Self, Error>
+    {
+        let mut counts = [0u16; MAX_LZ77_CODE_LENGTH as usize + 1];
+        for len in lengths
+        {
+            counts[*len as usize] += 1;
+        }
+
+        let mut left = 1i32;
+        for count in &counts[1..]
+        {
+            left = left * 2 - *count as i32;
+            if left < 0
+            {
+                return Err(invalid("DEFLATE code lengths are over-subscribed"));
+            }
+        }
+
+        let mut symbols = (0..lengths.len() as u16).filter(|symbol| lengths[*symbol as usize] > 0)
+                                                   .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, Error>
+    {
+        // the code so far, the first code of the current length, and the index of its symbol
+        let mut code = 0usize;
+        let mut first = 0usize;
+        let mut index = 0usize;
+        for count in &self.counts[1..]
+        {
+            code |= read_value(reader, 1)? as usize;
+            let count = *count as usize;
+            if code - first < count
+            {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid DEFLATE code"))
+    }
+}
+
+// the decoded bytes, keeping the window matches copy from
+struct Output<'a, W: Write>
+{
+    writer: &'a mut W,
+    buffer: Vec<u8>,
+    // bytes at the start of the buffer that have already been written
+    written: usize,
+    len: u64,
+    crc: Crc32,
+}
+
+impl<'a, W: Write> Output<'a, W>
+{
+    fn new(writer: &'a mut W) -> Self
+    {
+        Self { writer,
+               buffer: Vec::with_capacity(OUTPUT_FLUSH + DEFLATE_WINDOW),
+               written: 0,
+               len: 0,
+               crc: Crc32::new() }
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Error>
+    {
+        self.buffer.push(byte);
+        if self.buffer.len() - self.written >= OUTPUT_FLUSH
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, distance: usize, length: usize) -> Result<(), Error>
+    {
+        if distance > self.buffer.len()
+        {
+            return Err(invalid("DEFLATE distance is past the start of the output"));
+        }
+        // a match can overlap the bytes it's copying, so they go one at a time
+        for _ in 0..length
+        {
+            self.push(self.buffer[self.buffer.len() - distance])?;
+        }
+        Ok(())
+    }
+
+    // write out everything waiting and drop all but the window from the buffer
+    fn flush(&mut self) -> Result<(), Error>
+    {
+        let pending = &self.buffer[self.written..];
+        self.crc.update(pending);
+        self.len += pending.len() as u64;
+        self.writer.write_all(pending)?;
+        self.buffer
+            .drain(..self.buffer.len().saturating_sub(DEFLATE_WINDOW));
+        self.written = self.buffer.len();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(u64, u32), Error>
+    {
+        self.flush()?;
+        Ok((self.len, self.crc.finish()))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::file_lib::bit::BitOrder;
+
+    fn deflate_bytes(data: &[u8], matching: &MatchOptions) -> Vec<u8>
+    {
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        assert_eq!(deflate(&mut &data[..], &mut writer, matching).unwrap(),
+                   (data.len() as u64, crate::crc32::crc32(data)));
+        writer.into_inner().unwrap()
+    }
+
+    fn inflate_bytes(stream: &[u8]) -> Result<Vec<u8>, Error>
+    {
+        let mut reader = BitReader::with_order(stream, BitOrder::LsbFirst);
+        let mut output = Vec::new();
+        let (len, crc) = inflate(&mut reader, &mut output)?;
+        assert_eq!((len, crc),
+                   (output.len() as u64, crate::crc32::crc32(&output)));
+        Ok(output)
+    }
+
+    #[test]
+    fn test_fixed_block()
+    {
+        // a final fixed block with a literal a, a copy of it, and end of block
+        let stream = deflate_bytes(b"aaaa", &MatchOptions::default());
+        assert_eq!(stream, [0x4b, 0x04, 0x02, 0x00]);
+        assert_eq!(inflate_bytes(&stream).unwrap(), b"aaaa");
+
+        // an empty stream is a fixed block with only end of block in it
+        assert_eq!(deflate_bytes(b"", &MatchOptions::default()), [0x03, 0x00]);
+        assert_eq!(inflate_bytes(&[0x03, 0x00]).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_stored_block()
+    {
+        // bytes that can't be matched or coded any shorter are stored
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let data = (0..100_000).map(|_| {
+                                   state ^= state << 13;
+                                   state ^= state >> 7;
+                                   state ^= state << 17;
+                                   state as u8
+                               })
+                               .collect::<Vec<_>>();
+        let stream = deflate_bytes(&data, &MatchOptions::default());
+        assert_eq!(stream[0] & 0x07, 0);
+        assert!(stream.len() < data.len() + 20);
+        assert_eq!(inflate_bytes(&stream).unwrap(), data);
+    }
+
+    #[test]
+    fn test_dynamic_blocks()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        for level in [1, 6, 9]
+        {
+            let stream = deflate_bytes(&text, &MatchOptions::level(level));
+            assert_eq!(stream[0] >> 1 & 0x03, DYNAMIC as u8);
+            assert_eq!(inflate_bytes(&stream).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_length_codes()
+    {
+        let mut lengths = vec![0; 150];
+        lengths.extend_from_slice(&[3; 2]);
+        lengths.extend_from_slice(&[0; 7]);
+        lengths.extend_from_slice(&[8; 10]);
+        lengths.extend_from_slice(&[0, 0, 5]);
+        let codes = length_codes(&lengths);
+        assert_eq!(codes,
+                   [LengthCode { symbol: LONG_ZERO_RUN, extra_bits: 7, extra: 127 },
+                    LengthCode { symbol: LONG_ZERO_RUN, extra_bits: 7, extra: 1 },
+                    LengthCode::length(3),
+                    LengthCode::length(3),
+                    LengthCode { symbol: SHORT_ZERO_RUN, extra_bits: 3, extra: 4 },
+                    LengthCode::length(8),
+                    LengthCode { symbol: REPEAT_PREVIOUS, extra_bits: 2, extra: 3 },
+                    LengthCode { symbol: REPEAT_PREVIOUS, extra_bits: 2, extra: 0 },
+                    LengthCode::length(0),
+                    LengthCode::length(0),
+                    LengthCode::length(5)]);
+    }
+
+    #[test]
+    fn test_invalid()
+    {
+        // reserved block type
+        assert_eq!(inflate_bytes(&[0x07]).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+        // stored length and complement that don't match
+        assert_eq!(inflate_bytes(&[0x01, 0x01, 0x00, 0x00, 0x00]).unwrap_err()
+                                                                 .kind(),
+                   ErrorKind::InvalidData);
+        // a fixed block whose first code is a copy from before the start
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        writer.write_bits(1, 1).unwrap();
+        writer.write_bits(FIXED, 2).unwrap();
+        let (literal_lengths, _) = fixed_lengths();
+        let codes = canonical_codes(&literal_lengths);
+        write_reversed(&mut writer, &codes[257].unwrap()).unwrap();
+        writer.write_bits(0, 5).unwrap();
+        let stream = writer.into_inner().unwrap();
+        assert_eq!(inflate_bytes(&stream).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+        // cut short
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let stream = deflate_bytes(&text[..5000], &MatchOptions::default());
+        assert!(inflate_bytes(&stream[..stream.len() / 2]).is_err());
+    }
+}
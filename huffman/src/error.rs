@@ -0,0 +1,167 @@
+use crate::container::FormatError;
+use std::error;
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+// an error reading or writing a file, with the file and how many bits into it things went wrong
+#[derive(Debug)]
+pub enum HuffmanError
+{
+    // the file couldn't be opened, read or written
+    Io
+    {
+        path: PathBuf,
+        bit_offset: u64,
+        source: io::Error,
+    },
+    // the file isn't something this library can read
+    Format
+    {
+        path: PathBuf,
+        bit_offset: u64,
+        source: FormatError,
+    },
+    // the file looks right but its contents don't decode
+    Corrupt
+    {
+        path: PathBuf,
+        bit_offset: u64,
+        reason: String,
+    },
+}
+
+impl HuffmanError
+{
+    // sort an io::Error from reading or writing path into the variant it belongs in, a
+    // FormatError carried inside it comes back out as a Format error
+    pub fn new(path: &Path, bit_offset: u64, source: io::Error) -> Self
+    {
+        let path = path.to_path_buf();
+        if let Some(format) = source.get_ref()
+                                    .and_then(|inner| inner.downcast_ref::<FormatError>())
+        {
+            return HuffmanError::Format { path, bit_offset, source: format.clone() };
+        }
+        match source.kind()
+        {
+            ErrorKind::InvalidData =>
+            {
+                HuffmanError::Corrupt { path, bit_offset, reason: source.to_string() }
+            }
+            _ => HuffmanError::Io { path, bit_offset, source },
+        }
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        match self
+        {
+            HuffmanError::Io { path, .. }
+            | HuffmanError::Format { path, .. }
+            | HuffmanError::Corrupt { path, .. } => path,
+        }
+    }
+
+    pub fn bit_offset(&self) -> u64
+    {
+        match self
+        {
+            HuffmanError::Io { bit_offset, .. }
+            | HuffmanError::Format { bit_offset, .. }
+            | HuffmanError::Corrupt { bit_offset, .. } => *bit_offset,
+        }
+    }
+
+    // the kind it has as an io::Error, the same kind a FormatError or io::Error would have had
+    pub fn kind(&self) -> ErrorKind
+    {
+        match self
+        {
+            HuffmanError::Io { source, .. } => source.kind(),
+            HuffmanError::Format { source: FormatError::Truncated, .. } => ErrorKind::UnexpectedEof,
+            HuffmanError::Format { .. } | HuffmanError::Corrupt { .. } => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl Display for HuffmanError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}: ", self.path().display())?;
+        match self
+        {
+            HuffmanError::Io { source, .. } => write!(f, "{}", source)?,
+            HuffmanError::Format { source, .. } => write!(f, "{}", source)?,
+            HuffmanError::Corrupt { reason, .. } => write!(f, "corrupt data, {}", reason)?,
+        }
+        write!(f, " at bit {}", self.bit_offset())
+    }
+}
+
+impl error::Error for HuffmanError
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)>
+    {
+        match self
+        {
+            HuffmanError::Io { source, .. } => Some(source),
+            HuffmanError::Format { source, .. } => Some(source),
+            HuffmanError::Corrupt { .. } => None,
+        }
+    }
+}
+
+// for the apis that report io::Error, the HuffmanError can be recovered with io::Error::get_ref
+// and downcast_ref the same way a FormatError can
+impl From<HuffmanError> for io::Error
+{
+    fn from(error: HuffmanError) -> Self { io::Error::new(error.kind(), error) }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_new()
+    {
+        let path = Path::new("a.compressed");
+
+        let error = HuffmanError::new(path, 12, io::Error::from(ErrorKind::NotFound));
+        assert!(matches!(error, HuffmanError::Io { bit_offset: 12, .. }));
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert_eq!(error.path(), path);
+
+        let error = HuffmanError::new(path, 80, FormatError::Truncated.into());
+        assert!(matches!(error,
+                         HuffmanError::Format { source: FormatError::Truncated,
+                                                bit_offset: 80,
+                                                .. }));
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(),
+                   "a.compressed: compressed data is truncated at bit 80");
+
+        let error = HuffmanError::new(path, 3, io::Error::new(ErrorKind::InvalidData, "bad code"));
+        assert!(matches!(&error, HuffmanError::Corrupt { reason, .. } if reason == "bad code"));
+        assert_eq!(error.to_string(),
+                   "a.compressed: corrupt data, bad code at bit 3");
+    }
+
+    #[test]
+    fn test_into_io_error()
+    {
+        let error =
+            io::Error::from(HuffmanError::new(Path::new("a"), 0, FormatError::BadMagic.into()));
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let inner = error.get_ref()
+                         .and_then(|inner| inner.downcast_ref::<HuffmanError>())
+                         .expect("the HuffmanError is kept");
+        assert_eq!(inner.path(), Path::new("a"));
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Bit
 {
     Zero,
@@ -19,3 +19,15 @@ impl From<bool> for Bit
         }
     }
 }
+
+// the order bits are packed into each byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitOrder
+{
+    // the first bit goes in the top of the byte, multi-bit values are written top bit first
+    #[default]
+    MsbFirst,
+    // the first bit goes in the bottom of the byte, multi-bit values are written bottom bit
+    // first, this is what deflate uses
+    LsbFirst,
+}
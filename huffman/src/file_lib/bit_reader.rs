@@ -0,0 +1,330 @@
+use crate::file_lib::bit::Bit;
+use crate::file_lib::bit::BitOrder;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+// reads bits from anything that implements Read, bytes are pulled one at a time so wrap it in a
+// BufReader if that matters
+//
+// bytes are only pulled from the reader once their bits are needed, so peeking can read up to 4
+// bytes past the bits actually consumed
+pub struct BitReader<R: Read>
+{
+    reader: R,
+    order: BitOrder,
+    // bits pulled from the reader but not consumed yet, for MsbFirst they sit in the low
+    // `available` bits with the next bit at the top, for LsbFirst the next bit is bit 0
+    buffer: u64,
+    available: u32,
+    bits_read: u64,
+}
+
+impl<R: Read> BitReader<R>
+{
+    // most significant bit first
+    pub fn new(reader: R) -> Self { Self::with_order(reader, BitOrder::MsbFirst) }
+
+    pub fn with_order(reader: R, order: BitOrder) -> Self
+    {
+        Self { reader, order, buffer: 0, available: 0, bits_read: 0 }
+    }
+
+    // read `count` bits, up to 64, in the reader's bit order
+    // running out part way is an UnexpectedEof error and the bits that were there are lost
+    pub fn read_bits(&mut self, count: u32) -> Result<u64, Error>
+    {
+        if count > 64
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "can't read more than 64 bits at once"));
+        }
+
+        // the buffer can't hold 64 bits plus a partial byte, so big reads go in two halves
+        if count > 32
+        {
+            let first = self.read_bits(32)?;
+            let second = self.read_bits(count - 32)?;
+            return Ok(match self.order
+            {
+                BitOrder::MsbFirst => first << (count - 32) | second,
+                BitOrder::LsbFirst => first | second << 32,
+            });
+        }
+
+        let value = self.peek_bits(count)?;
+        self.consume(count)?;
+        Ok(value)
+    }
+
+    // look at the next `count` bits, up to 32, without consuming them
+    // past the end of the reader the missing bits read as 0
+    pub fn peek_bits(&mut self, count: u32) -> Result<u64, Error>
+    {
+        if count > 32
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "can't peek more than 32 bits at once"));
+        }
+
+        self.fill(count)?;
+        let have = self.available.min(count);
+        let bits = match self.order
+        {
+            BitOrder::MsbFirst => (self.buffer >> (self.available - have)) & mask(have),
+            BitOrder::LsbFirst => self.buffer & mask(have),
+        };
+        Ok(match self.order
+        {
+            BitOrder::MsbFirst => bits << (count - have),
+            BitOrder::LsbFirst => bits,
+        })
+    }
+
+    // drop `count` bits, up to 32, usually after a peek
+    pub fn consume(&mut self, count: u32) -> Result<(), Error>
+    {
+        self.fill(count)?;
+        if self.available < count
+        {
+            self.bits_read += self.available as u64;
+            self.available = 0;
+            return Err(Error::new(ErrorKind::UnexpectedEof, "ran out of bits"));
+        }
+
+        if self.order == BitOrder::LsbFirst
+        {
+            self.buffer = self.buffer.checked_shr(count).unwrap_or(0);
+        }
+        self.available -= count;
+        self.bits_read += count as u64;
+        Ok(())
+    }
+
+    // skip whatever is left of the current byte
+    pub fn align_to_byte(&mut self)
+    {
+        let partial = self.available % 8;
+        if partial > 0
+        {
+            self.consume(partial)
+                .expect("bits are already in the buffer");
+        }
+    }
+
+    pub fn is_aligned(&self) -> bool { self.available.is_multiple_of(8) }
+
+    // every bit consumed so far, including any skipped by align_to_byte
+    pub fn bits_read(&self) -> u64 { self.bits_read }
+
+    pub fn get_ref(&self) -> &R { &self.reader }
+
+    // hand back the reader, any bits pulled from it but not consumed are lost
+    pub fn into_inner(self) -> R { self.reader }
+
+    // pull bytes until at least `count` bits are buffered or the reader runs out
+    fn fill(&mut self, count: u32) -> Result<(), Error>
+    {
+        while self.available < count
+        {
+            let Some(byte) = self.next_byte()?
+            else
+            {
+                break;
+            };
+
+            match self.order
+            {
+                BitOrder::MsbFirst => self.buffer = self.buffer << 8 | byte as u64,
+                BitOrder::LsbFirst => self.buffer |= (byte as u64) << self.available,
+            }
+            self.available += 8;
+        }
+        Ok(())
+    }
+
+    // returns None at the end of the reader
+    fn next_byte(&mut self) -> Result<Option<u8>, Error>
+    {
+        let mut byte = [0u8];
+        loop
+        {
+            return match self.reader.read(&mut byte)
+            {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+        }
+    }
+}
+
+// positions are in bits from the start of the underlying reader, wherever it was when the
+// BitReader was made
+impl<R: Read + Seek> BitReader<R>
+{
+    // the position of the next bit to be consumed
+    pub fn tell_bits(&mut self) -> Result<u64, Error>
+    {
+        Ok(self.reader.stream_position()? * 8 - self.available as u64)
+    }
+
+    // carry on reading from the bit at `position`, bits_read only counts bits consumed so it
+    // isn't moved
+    pub fn seek_bits(&mut self, position: u64) -> Result<(), Error>
+    {
+        self.reader.seek(SeekFrom::Start(position / 8))?;
+        self.buffer = 0;
+        self.available = 0;
+
+        let bits_read = self.bits_read;
+        let result = self.consume((position % 8) as u32);
+        self.bits_read = bits_read;
+        result
+    }
+}
+
+fn mask(count: u32) -> u64 { (1u64 << count) - 1 }
+
+impl<R: Read> Iterator for BitReader<R>
+{
+    type Item = Result<Bit, Error>;
+
+    fn next(&mut self) -> Option<Result<Bit, Error>>
+    {
+        match self.read_bits(1)
+        {
+            Ok(bit) => Some(Ok((bit == 1).into())),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_read_from_slice()
+    {
+        let bits =
+            BitReader::new(&[0b10110000u8, 0b11000000][..]).map(|bit| bit.unwrap() == Bit::One)
+                                                           .collect::<Vec<_>>();
+        assert_eq!(bits.len(), 16);
+        assert_eq!(bits[..4], [true, false, true, true]);
+        assert_eq!(bits[8..10], [true, true]);
+        assert!(bits[10..].iter().all(|bit| !bit));
+    }
+
+    #[test]
+    fn test_peek_bits()
+    {
+        for (order, expected) in [(BitOrder::MsbFirst, 0b1_0110_0001),
+                                  (BitOrder::LsbFirst, 0b0_1011_0000u64)]
+        {
+            let mut reader = BitReader::with_order(&[0b10110000u8, 0b11000000][..], order);
+            assert_eq!(reader.peek_bits(9).unwrap(), expected);
+            assert_eq!(reader.peek_bits(9).unwrap(), expected);
+            assert_eq!(reader.bits_read(), 0);
+
+            reader.consume(9).unwrap();
+            assert_eq!(reader.bits_read(), 9);
+        }
+    }
+
+    #[test]
+    fn test_peek_past_end()
+    {
+        let mut reader = BitReader::new(&[0b10110001u8][..]);
+        reader.consume(4).unwrap();
+
+        // only 4 bits are left, the rest read as 0
+        assert_eq!(reader.peek_bits(10).unwrap(), 0b0001_000000);
+        assert_eq!(reader.consume(5).unwrap_err().kind(),
+                   ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_bits_msb_first()
+    {
+        let mut reader = BitReader::new(&[0b10110101u8, 0b01111001, 0b10100000][..]);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_bits(16).unwrap(), 0xABCD);
+        assert_eq!(reader.read_bits(0).unwrap(), 0);
+        assert_eq!(reader.bits_read(), 19);
+        assert_eq!(reader.read_bits(5).unwrap(), 0);
+        assert_eq!(reader.read_bits(1).unwrap_err().kind(),
+                   ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_bits_lsb_first()
+    {
+        let mut reader = BitReader::with_order(&[0b01101101u8, 0b01011110, 0b00000101][..],
+                                               BitOrder::LsbFirst);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_bits(16).unwrap(), 0xABCD);
+        assert_eq!(reader.bits_read(), 19);
+    }
+
+    #[test]
+    fn test_read_64_bits()
+    {
+        let bytes = [0x80, 0x91, 0xA2, 0xB3, 0xC4, 0xD5, 0xE6, 0xF7, 0x80];
+        let mut reader = BitReader::new(&bytes[..]);
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+        assert_eq!(reader.read_bits(64).unwrap(), 0x0123_4567_89AB_CDEF);
+        assert!(reader.read_bits(65).is_err());
+    }
+
+    #[test]
+    fn test_seek_bits()
+    {
+        let bytes = [0b10110101u8, 0b01111001, 0b10100000];
+        let mut reader = BitReader::new(std::io::Cursor::new(&bytes[..]));
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.tell_bits().unwrap(), 3);
+        // peeking pulls bytes from the reader without moving the position
+        reader.peek_bits(20).unwrap();
+        assert_eq!(reader.tell_bits().unwrap(), 3);
+
+        reader.seek_bits(11).unwrap();
+        assert_eq!(reader.tell_bits().unwrap(), 11);
+        assert_eq!(reader.read_bits(8).unwrap(), 0b11001101);
+        reader.seek_bits(3).unwrap();
+        assert_eq!(reader.read_bits(16).unwrap(), 0xABCD);
+        assert_eq!(reader.bits_read(), 27);
+
+        reader.seek_bits(23).unwrap();
+        assert_eq!(reader.read_bits(2).unwrap_err().kind(),
+                   ErrorKind::UnexpectedEof);
+        assert_eq!(reader.seek_bits(25).unwrap_err().kind(),
+                   ErrorKind::UnexpectedEof);
+
+        let mut reader = BitReader::with_order(std::io::Cursor::new(&[0b01101101u8, 0b01011110][..]),
+                                               BitOrder::LsbFirst);
+        reader.seek_bits(3).unwrap();
+        assert_eq!(reader.read_bits(8).unwrap(), 0b11001101);
+        assert_eq!(reader.tell_bits().unwrap(), 11);
+    }
+
+    #[test]
+    fn test_align_to_byte()
+    {
+        let mut reader = BitReader::new(&[0b11000000u8, 0xFF][..]);
+        reader.align_to_byte();
+        assert_eq!(reader.bits_read(), 0);
+
+        assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+        assert!(!reader.is_aligned());
+        reader.align_to_byte();
+        assert!(reader.is_aligned());
+        assert_eq!(reader.bits_read(), 8);
+        assert_eq!(reader.read_bits(8).unwrap(), 0xFF);
+    }
+}
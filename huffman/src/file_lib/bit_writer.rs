@@ -0,0 +1,235 @@
+use crate::file_lib::bit::Bit;
+use crate::file_lib::bit::BitOrder;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+
+// writes bits to anything that implements Write, bytes are handed to the writer as soon as they
+// fill up so wrap it in a BufWriter if that matters
+pub struct BitWriter<W: Write>
+{
+    writer: W,
+    order: BitOrder,
+    byte: u8,
+    // bits already in byte
+    filled: u32,
+    bits_written: u64,
+}
+
+impl<W: Write> BitWriter<W>
+{
+    // most significant bit first
+    pub fn new(writer: W) -> Self { Self::with_order(writer, BitOrder::MsbFirst) }
+
+    pub fn with_order(writer: W, order: BitOrder) -> Self
+    {
+        Self { writer, order, byte: 0, filled: 0, bits_written: 0 }
+    }
+
+    pub fn write(&mut self, bit: &Bit) -> Result<(), Error>
+    {
+        self.write_bits((*bit == Bit::One) as u64, 1)
+    }
+
+    // write the low `count` bits of value, up to 64, in the writer's bit order
+    pub fn write_bits(&mut self, value: u64, count: u32) -> Result<(), Error>
+    {
+        if count > 64
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "can't write more than 64 bits at once"));
+        }
+
+        // the bytes filled up by this write, handed to the writer all at once
+        let mut full = [0u8; 9];
+        let mut full_len = 0;
+        let mut left = count;
+        while left > 0
+        {
+            let take = left.min(8 - self.filled);
+            let mask = (1u64 << take) - 1;
+            match self.order
+            {
+                BitOrder::MsbFirst =>
+                {
+                    let bits = (value >> (left - take) & mask) as u8;
+                    self.byte |= bits << (8 - self.filled - take);
+                }
+                BitOrder::LsbFirst =>
+                {
+                    let bits = (value >> (count - left) & mask) as u8;
+                    self.byte |= bits << self.filled;
+                }
+            }
+            self.filled += take;
+            left -= take;
+
+            if self.filled == 8
+            {
+                full[full_len] = self.byte;
+                full_len += 1;
+                self.byte = 0;
+                self.filled = 0;
+            }
+        }
+        if full_len > 0
+        {
+            self.writer.write_all(&full[..full_len])?;
+        }
+
+        self.bits_written += count as u64;
+        Ok(())
+    }
+
+    // write whole bytes, handed to the writer in one go when on a byte boundary, the same as
+    // writing each of them as 8 bits
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), Error>
+    {
+        if !self.is_aligned()
+        {
+            return bytes.iter()
+                        .try_for_each(|byte| self.write_bits(*byte as u64, 8));
+        }
+        self.writer.write_all(bytes)?;
+        self.bits_written += 8 * bytes.len() as u64;
+        Ok(())
+    }
+
+    // pad out a partially filled byte with zero bits, does nothing if already on a byte boundary
+    pub fn align_to_byte(&mut self) -> Result<(), Error>
+    {
+        if self.filled > 0
+        {
+            self.bits_written += (8 - self.filled) as u64;
+            self.write_byte()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_aligned(&self) -> bool { self.filled == 0 }
+
+    // every bit written so far, including padding
+    pub fn bits_written(&self) -> u64 { self.bits_written }
+
+    // write out any partially filled byte, padding it with zero bits, then flush the writer
+    pub fn flush(&mut self) -> Result<(), Error>
+    {
+        self.align_to_byte()?;
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W { &self.writer }
+
+    // writing through this skips any bits still waiting for their byte to fill up
+    pub fn get_mut(&mut self) -> &mut W { &mut self.writer }
+
+    // flush and hand back the writer
+    pub fn into_inner(mut self) -> Result<W, Error>
+    {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_byte(&mut self) -> Result<(), Error>
+    {
+        self.writer.write_all(&[self.byte])?;
+        self.byte = 0;
+        self.filled = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_write_to_vec()
+    {
+        let mut writer = BitWriter::new(Vec::new());
+        for bit in [1, 0, 1, 1, 0, 0, 0, 0, 1, 1]
+        {
+            writer.write(&(bit == 1).into())
+                  .expect("failed writing bit");
+        }
+        assert_eq!(writer.get_ref(), &vec![0b10110000]);
+        assert_eq!(writer.into_inner().unwrap(), vec![0b10110000, 0b11000000]);
+    }
+
+    #[test]
+    fn test_write_bits_msb_first()
+    {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(0xABCD, 16).unwrap();
+        writer.write_bits(0, 0).unwrap();
+        assert_eq!(writer.bits_written(), 19);
+        assert_eq!(writer.into_inner().unwrap(),
+                   vec![0b10110101, 0b01111001, 0b10100000]);
+    }
+
+    #[test]
+    fn test_write_bits_lsb_first()
+    {
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(0xABCD, 16).unwrap();
+        assert_eq!(writer.into_inner().unwrap(),
+                   vec![0b01101101, 0b01011110, 0b00000101]);
+    }
+
+    #[test]
+    fn test_write_64_bits()
+    {
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst]
+        {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            writer.write_bits(1, 1).unwrap();
+            writer.write_bits(0x0123_4567_89AB_CDEF, 64).unwrap();
+            assert!(writer.write_bits(0, 65).is_err());
+            assert_eq!(writer.bits_written(), 65);
+            assert_eq!(writer.into_inner().unwrap().len(), 9);
+        }
+    }
+
+    #[test]
+    fn test_align_to_byte()
+    {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.align_to_byte().unwrap();
+        assert_eq!(writer.bits_written(), 0);
+
+        writer.write_bits(0b11, 2).unwrap();
+        assert!(!writer.is_aligned());
+        writer.align_to_byte().unwrap();
+        assert!(writer.is_aligned());
+        assert_eq!(writer.bits_written(), 8);
+
+        writer.write_bits(0xFF, 8).unwrap();
+        assert_eq!(writer.into_inner().unwrap(), vec![0b11000000, 0xFF]);
+    }
+
+    #[test]
+    fn test_write_bytes()
+    {
+        // on a byte boundary or not, the same as writing each byte as 8 bits
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst]
+        {
+            for lead in [0, 3]
+            {
+                let mut bytes = BitWriter::with_order(Vec::new(), order);
+                let mut bits = BitWriter::with_order(Vec::new(), order);
+                bytes.write_bits(0b101, lead).unwrap();
+                bits.write_bits(0b101, lead).unwrap();
+                bytes.write_bytes(b"Hello World!").unwrap();
+                for byte in b"Hello World!"
+                {
+                    bits.write_bits(*byte as u64, 8).unwrap();
+                }
+                assert_eq!(bytes.bits_written(), bits.bits_written());
+                assert_eq!(bytes.into_inner().unwrap(), bits.into_inner().unwrap());
+            }
+        }
+    }
+}
@@ -1,69 +1,56 @@
+use crate::error::HuffmanError;
 use crate::file_lib::bit::Bit;
+use crate::file_lib::bit_reader::BitReader;
 use std::fs::File;
 use std::io::BufReader;
-use std::io::Bytes;
-use std::io::Error;
-use std::io::Read;
+use std::ops::Deref;
+use std::ops::DerefMut;
 use std::path::Path;
+use std::path::PathBuf;
 
+// a buffered BitReader that opens its own file, its errors say which file and where
 pub struct FileBitReader
 {
-    bytes: Bytes<BufReader<File>>,
-    byte: u8,
-    mask: u8,
+    reader: BitReader<BufReader<File>>,
+    path: PathBuf,
 }
 
 impl FileBitReader
 {
-    pub fn new(path: &Path) -> Self
+    pub fn new(path: &Path) -> Result<Self, HuffmanError>
     {
-        let file = File::open(path).unwrap_or_else(|_| panic!("Couldn't open: {:?}", path));
-        let buff_reader = BufReader::new(file);
-        Self { bytes: buff_reader.bytes(),
-               byte: 0,
-               mask: 0 }
+        let file = File::open(path).map_err(|error| HuffmanError::new(path, 0, error))?;
+        Ok(Self { reader: BitReader::new(BufReader::new(file)), path: path.to_path_buf() })
     }
 
-    pub fn next(&mut self) -> Option<Result<Bit, Error>>
-    {
-        if self.mask != 0
-        {
-            Some(Ok(self.extract_bit()))
-        }
-        else
-        {
-            match self.next_byte()?
-            {
-                Ok(_) => Some(Ok(self.extract_bit())),
-                Err(e) => Some(Err(e)),
-            }
-        }
-    }
+    pub fn path(&self) -> &Path { &self.path }
+}
 
-    fn next_byte(&mut self) -> Option<Result<(), Error>>
-    {
-        const NEW_BYTE_MASK: u8 = 0b10000000;
-        let result = self.bytes.next()?;
-        match result
-        {
-            Ok(byte) =>
-            {
-                self.byte = byte;
-                self.mask = NEW_BYTE_MASK;
-                Some(Ok(()))
-            }
-            Err(e) => Some(Err(e)),
-        }
-    }
+impl Iterator for FileBitReader
+{
+    type Item = Result<Bit, HuffmanError>;
 
-    fn extract_bit(&mut self) -> Bit
+    fn next(&mut self) -> Option<Result<Bit, HuffmanError>>
     {
-        let bit = (self.byte & self.mask > 0).into();
-        self.mask >>= 1;
-        bit
+        let offset = self.reader.bits_read();
+        self.reader
+            .next()
+            .map(|bit| bit.map_err(|error| HuffmanError::new(&self.path, offset, error)))
     }
 }
 
+impl Deref for FileBitReader
+{
+    type Target = BitReader<BufReader<File>>;
+
+    fn deref(&self) -> &BitReader<BufReader<File>> { &self.reader }
+}
+
+impl DerefMut for FileBitReader
+{
+    fn deref_mut(&mut self) -> &mut BitReader<BufReader<File>> { &mut self.reader }
+}
+
 #[cfg(test)]
 mod tests
 {
@@ -83,7 +70,7 @@ mod tests
         {
             let error_msg = format!("Couldn't create: {:?}", path);
             let mut file = File::create_new(path.clone()).expect(error_msg.as_str());
-            file.write(b"Hello World!").expect(error_msg.as_str());
+            file.write_all(b"Hello World!").expect(error_msg.as_str());
             Self { path }
         }
     }
@@ -102,13 +89,12 @@ mod tests
         let mut char_index: usize = 0;
         let mut mask: u8 = 0b10000000;
 
-        let mut reader = FileBitReader::new(path);
-        while let Some(bit) = reader.next()
+        for bit in FileBitReader::new(path).expect("failed opening")
         {
             match bit
             {
                 Ok(bit) => assert_eq!(bit, (test_str[char_index] & mask > 0).into()),
-                Err(_) => assert!(false),
+                Err(e) => panic!("failed reading bit: {}", e),
             }
 
             mask >>= 1;
@@ -121,4 +107,15 @@ mod tests
 
         drop(test_file);
     }
+
+    #[test]
+    fn test_missing_file()
+    {
+        let path = Path::new("kmd_FileBitReaderMissing.txt");
+        let error = FileBitReader::new(path).err()
+                                            .expect("opened a missing file");
+        assert!(matches!(&error, HuffmanError::Io { source, bit_offset: 0, .. }
+                                 if source.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(error.path(), path);
+    }
 }
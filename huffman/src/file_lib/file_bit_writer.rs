@@ -1,76 +1,76 @@
+use crate::error::HuffmanError;
 use crate::file_lib::bit::Bit;
+use crate::file_lib::bit_writer::BitWriter;
 use std::fs::File;
-use std::io;
+use std::io::BufWriter;
 use std::io::Error;
-use std::io::Write;
+use std::ops::Deref;
+use std::ops::DerefMut;
 use std::path::Path;
+use std::path::PathBuf;
 
-const NEW_BYTE_MASK: u8 = 0b10000000;
-
+// a buffered BitWriter that creates its own file, finish writes out any partial byte and what's
+// buffered and reports whether that worked, dropping it without finishing tries the same but can't
+// say if it failed
 pub struct FileBitWriter
 {
-    file: File,
-    byte: u8,
-    mask: u8,
+    writer: BitWriter<BufWriter<File>>,
+    path: PathBuf,
 }
 
 impl FileBitWriter
 {
-    pub fn new(path: &Path) -> Self
+    // the file mustn't already exist
+    pub fn new(path: &Path) -> Result<Self, HuffmanError>
     {
-        let file = File::create_new(path).unwrap_or_else(|_| panic!("Couldn't create: {:?}", path));
-        Self { file,
-               byte: 0,
-               mask: NEW_BYTE_MASK }
+        let file = File::create_new(path).map_err(|error| HuffmanError::new(path, 0, error))?;
+        Ok(Self { writer: BitWriter::new(BufWriter::new(file)), path: path.to_path_buf() })
     }
 
-    pub fn write(&mut self, bit: &Bit) -> Result<(), Error>
-    {
-        self.set_bit(bit);
-
-        if self.mask == 0
-        {
-            self.write_byte()?;
-        }
+    pub fn path(&self) -> &Path { &self.path }
 
-        Ok(())
+    pub fn write(&mut self, bit: &Bit) -> Result<(), HuffmanError>
+    {
+        let offset = self.writer.bits_written();
+        self.writer
+            .write(bit)
+            .map_err(|error| self.error(offset, error))
     }
 
-    pub fn flush(&mut self) -> Result<(), Error>
+    // write out any partially filled byte, padding it with zero bits, and everything buffered
+    pub fn flush(&mut self) -> Result<(), HuffmanError>
     {
-        self.write_byte()?;
-        Ok(())
+        let offset = self.writer.bits_written();
+        self.writer
+            .flush()
+            .map_err(|error| self.error(offset, error))
     }
 
-    fn set_bit(&mut self, bit: &Bit)
-    {
-        if *bit == Bit::One
-        {
-            self.byte |= self.mask;
-        }
+    // flush and close the file
+    pub fn finish(mut self) -> Result<(), HuffmanError> { self.flush() }
 
-        self.mask >>= 1;
+    fn error(&self, offset: u64, error: Error) -> HuffmanError
+    {
+        HuffmanError::new(&self.path, offset, error)
     }
+}
 
-    fn write_byte(&mut self) -> io::Result<usize>
-    {
-        let written = self.file.write(&[self.byte])?;
-        self.mask = NEW_BYTE_MASK;
-        self.byte = 0;
+impl Deref for FileBitWriter
+{
+    type Target = BitWriter<BufWriter<File>>;
 
-        Ok(written)
-    }
+    fn deref(&self) -> &BitWriter<BufWriter<File>> { &self.writer }
+}
+
+impl DerefMut for FileBitWriter
+{
+    fn deref_mut(&mut self) -> &mut BitWriter<BufWriter<File>> { &mut self.writer }
 }
 
 impl Drop for FileBitWriter
 {
-    fn drop(&mut self)
-    {
-        if self.mask != NEW_BYTE_MASK
-        {
-            self.write_byte().expect("failed writing byte in drop");
-        }
-    }
+    // there's nowhere for an error to go, and panicking here could be panicking while unwinding
+    fn drop(&mut self) { let _ = self.writer.flush(); }
 }
 
 #[cfg(test)]
@@ -113,13 +113,15 @@ mod tests
         let test_file = TestFile::create(path.to_path_buf());
         let test_str = "Hello World!";
 
-        let mut writer = FileBitWriter::new(path);
+        let mut writer = FileBitWriter::new(path).expect("failed creating");
 
         for c in test_str.as_bytes()
         {
             write_char(&mut writer, *c);
         }
 
+        // whole bytes sit in the buffer until it's flushed
+        writer.flush().expect("failed flushing");
         let read_str = std::fs::read_to_string(path).expect("failed reading file");
 
         assert_eq!(read_str, test_str);
@@ -134,7 +136,7 @@ mod tests
         let test_file = TestFile::create(path.to_path_buf());
         let test_str = "Hello World!";
 
-        let mut writer = FileBitWriter::new(path);
+        let mut writer = FileBitWriter::new(path).expect("failed creating");
 
         for c in test_str.as_bytes()
         {
@@ -160,4 +162,27 @@ mod tests
 
         drop(test_file);
     }
+
+    #[test]
+    fn test_finish()
+    {
+        let path = Path::new("kmd_FileBitWriterTestFinish.txt");
+        let test_file = TestFile::create(path.to_path_buf());
+
+        let mut writer = FileBitWriter::new(path).expect("failed creating");
+        write_char(&mut writer, b'H');
+        writer.write(&Bit::Zero).expect("failed writing bit");
+        writer.write(&Bit::One).expect("failed writing bit");
+        writer.finish().expect("failed finishing");
+        assert_eq!(std::fs::read(path).expect("failed reading file"), b"H@");
+
+        // the file is there now, so it can't be created again
+        let error = FileBitWriter::new(path).err()
+                                            .expect("created an existing file");
+        assert!(matches!(&error, HuffmanError::Io { source, .. }
+                                 if source.kind() == std::io::ErrorKind::AlreadyExists));
+        assert_eq!(error.path(), path);
+
+        drop(test_file);
+    }
 }
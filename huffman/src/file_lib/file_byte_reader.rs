@@ -1,25 +1,78 @@
+use crate::error::HuffmanError;
 use std::fs::File;
 use std::io::BufReader;
-use std::io::Bytes;
 use std::io::Error;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::path::Path;
+use std::path::PathBuf;
 
+// the bytes of a file one at a time, or a chunk at a time with read_chunk which is a lot quicker,
+// its errors say which file and where
 pub struct FileByteReader
 {
-    bytes: Bytes<BufReader<File>>,
+    reader: BufReader<File>,
+    path: PathBuf,
+    bytes_read: u64,
 }
 
 impl FileByteReader
 {
-    pub fn new(path: &Path) -> Self
+    pub fn new(path: &Path) -> Result<Self, HuffmanError>
     {
-        let file = File::open(path).unwrap_or_else(|_| panic!("Couldn't open: {:?}", path));
-        let buff_reader = BufReader::new(file);
-        Self { bytes: buff_reader.bytes() }
+        let file = File::open(path).map_err(|error| HuffmanError::new(path, 0, error))?;
+        Ok(Self { reader: BufReader::new(file), path: path.to_path_buf(), bytes_read: 0 })
     }
 
-    pub fn next(&mut self) -> Option<Result<u8, Error>> { self.bytes.next() }
+    pub fn path(&self) -> &Path { &self.path }
+
+    // fill as much of buf as the file has left, returns how many bytes that was, 0 at the end
+    pub fn read_chunk(&mut self, buf: &mut [u8]) -> Result<usize, HuffmanError>
+    {
+        let mut filled = 0;
+        while filled < buf.len()
+        {
+            match self.reader.read(&mut buf[filled..])
+            {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(self.error(filled, error)),
+            }
+        }
+        self.bytes_read += filled as u64;
+        Ok(filled)
+    }
+
+    // `pending` bytes were read past bytes_read before the error
+    fn error(&self, pending: usize, error: Error) -> HuffmanError
+    {
+        HuffmanError::new(&self.path, 8 * (self.bytes_read + pending as u64), error)
+    }
+}
+
+impl Iterator for FileByteReader
+{
+    type Item = Result<u8, HuffmanError>;
+
+    fn next(&mut self) -> Option<Result<u8, HuffmanError>>
+    {
+        let mut byte = [0u8];
+        loop
+        {
+            return match self.reader.read(&mut byte)
+            {
+                Ok(0) => None,
+                Ok(_) =>
+                {
+                    self.bytes_read += 1;
+                    Some(Ok(byte[0]))
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => Some(Err(self.error(0, error))),
+            };
+        }
+    }
 }
 
 #[cfg(test)]
@@ -41,7 +94,7 @@ mod tests
         {
             let error_msg = format!("Couldn't create: {:?}", path);
             let mut file = File::create_new(path.clone()).expect(error_msg.as_str());
-            file.write(b"Hello World!").expect(error_msg.as_str());
+            file.write_all(b"Hello World!").expect(error_msg.as_str());
             Self { path }
         }
     }
@@ -57,7 +110,7 @@ mod tests
         let path = Path::new("kmd_FileByteReaderTest.txt");
         let test_file = TestFile::create(path.to_path_buf());
 
-        let mut reader = FileByteReader::new(path);
+        let mut reader = FileByteReader::new(path).expect("failed opening");
         assert_eq!(reader.next().unwrap().unwrap(), b'H');
         assert_eq!(reader.next().unwrap().unwrap(), b'e');
         assert_eq!(reader.next().unwrap().unwrap(), b'l');
@@ -70,7 +123,40 @@ mod tests
         assert_eq!(reader.next().unwrap().unwrap(), b'l');
         assert_eq!(reader.next().unwrap().unwrap(), b'd');
         assert_eq!(reader.next().unwrap().unwrap(), b'!');
+        assert!(reader.next().is_none());
+
+        drop(test_file);
+    }
+
+    #[test]
+    fn test_read_chunk()
+    {
+        let path = Path::new("kmd_FileByteReaderChunk.txt");
+        let test_file = TestFile::create(path.to_path_buf());
+
+        // chunks and single bytes can be mixed
+        let mut reader = FileByteReader::new(path).expect("failed opening");
+        let mut chunk = [0u8; 5];
+        assert_eq!(reader.read_chunk(&mut chunk).unwrap(), 5);
+        assert_eq!(&chunk, b"Hello");
+        assert_eq!(reader.next().unwrap().unwrap(), b' ');
+        let mut chunk = [0u8; 16];
+        assert_eq!(reader.read_chunk(&mut chunk).unwrap(), 6);
+        assert_eq!(&chunk[..6], b"World!");
+        assert_eq!(reader.read_chunk(&mut chunk).unwrap(), 0);
+        assert!(reader.next().is_none());
 
         drop(test_file);
     }
+
+    #[test]
+    fn test_missing_file()
+    {
+        let path = Path::new("kmd_FileByteReaderMissing.txt");
+        let error = FileByteReader::new(path).err()
+                                             .expect("opened a missing file");
+        assert!(matches!(&error, HuffmanError::Io { source, bit_offset: 0, .. }
+                                 if source.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(error.path(), path);
+    }
 }
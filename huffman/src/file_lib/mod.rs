@@ -1,4 +1,6 @@
 pub mod bit;
+pub mod bit_reader;
+pub mod bit_writer;
 pub mod file_bit_reader;
 pub mod file_bit_writer;
 pub mod file_byte_reader;
@@ -0,0 +1,210 @@
+// Malformed compressed data run through every way there is of reading it
+//
+// Only built for tests and with the fuzzing feature, which fuzz/ turns on. The cargo-fuzz target
+// there hands decode whatever the fuzzer comes up with, and the tests here hand it the seed corpus
+// and mutations of it, so the same checks run offline. Whatever the data, nothing may panic, hang
+// or allocate without bound. Errors are expected and thrown away.
+
+use crate::container::Header;
+use crate::uncompress::copy_range;
+use crate::uncompress::read_block;
+use crate::uncompress::read_block_index;
+use crate::uncompress::scan_blocks;
+use crate::uncompress::BlockEntry;
+use crate::uncompress::HuffmanDecoder;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+
+// a valid file can claim any length, so decoding stops after this much
+const MAX_OUTPUT: u64 = 1 << 20;
+// blocks decoded on their own through scan_blocks and the block index
+const MAX_BLOCKS: usize = 8;
+
+pub fn decode(data: &[u8])
+{
+    let _ = Header::parse(data);
+
+    if let Ok(decoder) = HuffmanDecoder::new(data)
+    {
+        let _ = io::copy(&mut decoder.take(MAX_OUTPUT), &mut io::sink());
+    }
+
+    // a block is decoded whole, and a valid one can be MAX_BLOCK_LEN bytes of a single symbol
+    // that takes seconds to decode, so only the short ones are
+    let short = |block: &BlockEntry| block.header.len as u64 <= MAX_OUTPUT;
+    let mut reader = Cursor::new(data);
+    for blocks in [scan_blocks(&mut reader), read_block_index(&mut reader)].into_iter()
+                                                                           .flatten()
+    {
+        for index in (0..blocks.len().min(MAX_BLOCKS)).filter(|index| short(&blocks[*index]))
+        {
+            let _ = read_block(&mut reader, &blocks, index);
+        }
+    }
+    if read_block_index(&mut reader).map_or(true, |blocks| blocks.iter().all(short))
+    {
+        let _ = copy_range(&mut reader, 1000..2000, &mut io::sink());
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::ErrorKind;
+    use std::io::Write;
+    use std::path::Path;
+
+    use super::*;
+    use crate::compress::CompressOptions;
+    use crate::compress::HuffmanEncoder;
+    use crate::container::Codec;
+    use crate::container::HEADER_LEN;
+    use crate::container::MAX_BLOCK_LEN;
+    use crate::lz77::MatchOptions;
+    use crate::transform::Transforms;
+
+    // what cargo-fuzz starts from, small files in every format
+    const CORPUS: &str = "fuzz/corpus/decode";
+
+    fn seeds() -> Vec<Vec<u8>>
+    {
+        let mut seeds = vec![std::fs::read("asyoulik.txt.compressed").expect("failed reading \
+                                                                              asyoulik.txt.\
+                                                                              compressed")];
+        let mut paths = std::fs::read_dir(CORPUS).expect("failed listing the corpus")
+                                                 .map(|entry| entry.unwrap().path())
+                                                 .collect::<Vec<_>>();
+        paths.sort();
+        seeds.extend(paths.iter()
+                          .map(|path| std::fs::read(path).expect("failed reading a seed")));
+        seeds
+    }
+
+    // what the corpus has in it, so it can be made again if the format changes
+    fn encodings(text: &[u8]) -> Vec<(&'static str, Vec<u8>)>
+    {
+        let two_pass = |options: CompressOptions| {
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+            encoder.write_all(text).unwrap();
+            encoder.finish().unwrap()
+        };
+        let one_pass = |mut encoder: HuffmanEncoder<Vec<u8>>| {
+            encoder.write_all(text).unwrap();
+            encoder.finish().unwrap()
+        };
+        let small = CompressOptions { block_len: 256, ..Default::default() };
+
+        vec![("blocks", two_pass(small.clone())),
+             ("order1", two_pass(CompressOptions { order1: true, ..small.clone() })),
+             ("range", two_pass(CompressOptions { codec: Codec::Range, ..small.clone() })),
+             ("lz77",
+              two_pass(CompressOptions { codec: Codec::Lz77,
+                                         matching: MatchOptions::level(6),
+                                         ..small.clone() })),
+             ("transforms",
+              two_pass(CompressOptions { transforms: Transforms::all(), ..small.clone() })),
+             ("static", one_pass(HuffmanEncoder::with_static_model(Vec::new()).unwrap())),
+             ("adaptive", one_pass(HuffmanEncoder::adaptive(Vec::new()).unwrap())),
+             ("empty", HuffmanEncoder::new(Vec::new()).finish().unwrap())]
+    }
+
+    fn corpus_text() -> Vec<u8>
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        text[..1000].to_vec()
+    }
+
+    #[test]
+    fn test_corpus_is_current()
+    {
+        for (name, encoded) in encodings(&corpus_text())
+        {
+            let path = Path::new(CORPUS).join(format!("{}.compressed", name));
+            let seed = std::fs::read(&path).unwrap_or_else(|_| panic!("missing {:?}", path));
+            assert!(seed == encoded, "{:?} isn't what compress gives now", path);
+        }
+    }
+
+    #[test]
+    fn test_seeds()
+    {
+        for seed in seeds()
+        {
+            decode(&seed);
+        }
+    }
+
+    // block lengths the file header can't account for, which were once decoded regardless, a
+    // symbol at a time with a one symbol range coder that needs no bits for them
+    #[test]
+    fn test_block_len_past_original_len()
+    {
+        for (codec, transforms) in [(Codec::Huffman, Transforms::default()),
+                                    (Codec::Range, Transforms::default()),
+                                    (Codec::Range, Transforms::all()),
+                                    (Codec::Lz77, Transforms::default())]
+        {
+            let options = CompressOptions { codec, transforms, ..Default::default() };
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+            encoder.write_all(&[b'a'; 100]).unwrap();
+            let encoded = encoder.finish().unwrap();
+
+            for len in [u32::MAX, MAX_BLOCK_LEN as u32, 101]
+            {
+                let mut data = encoded.clone();
+                data[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&len.to_le_bytes());
+                let mut decoder = HuffmanDecoder::new(&data[..]).unwrap();
+                let error = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?} {}", codec, len);
+
+                let mut reader = Cursor::new(&data);
+                if let Ok(blocks) = scan_blocks(&mut reader)
+                {
+                    assert!(read_block(&mut reader, &blocks, 0).is_err());
+                }
+                decode(&data);
+            }
+        }
+    }
+
+    // bit flips, random bytes, cuts and splices, mostly near the start where the headers are
+    #[test]
+    fn test_mutations()
+    {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for seed in seeds().iter().filter(|seed| seed.len() < 4096)
+        {
+            for _ in 0..300
+            {
+                let mut data = seed.clone();
+                for _ in 0..1 + next() % 3
+                {
+                    let len = data.len().max(1);
+                    let at = match next() % 2
+                    {
+                        0 => next() as usize % len.min(64),
+                        _ => next() as usize % len,
+                    };
+                    match next() % 4
+                    {
+                        0 if at < data.len() => data[at] ^= 1 << (next() % 8),
+                        1 if at < data.len() => data[at] = next() as u8,
+                        2 => data.truncate(at),
+                        _ => data.splice(at.min(data.len())..at.min(data.len()),
+                                         (0..next() % 8).map(|_| next() as u8))
+                                 .for_each(drop),
+                    }
+                }
+                decode(&data);
+            }
+        }
+    }
+}
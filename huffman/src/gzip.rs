@@ -0,0 +1,368 @@
+// gzip files as described in RFC 1952, a DEFLATE stream between a small header and a trailer with
+// the CRC-32 and length of the data
+//
+// Files written here have the plainest header there is, with no name or modification time. Any
+// gzip file can be read, including ones with several members one after the other, which come out
+// as their data joined together.
+
+use crate::container::FormatError;
+use crate::deflate::deflate;
+use crate::deflate::inflate;
+use crate::file_lib::bit::BitOrder;
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::read_value;
+use crate::lz77::MatchOptions;
+use crate::uncompress::write_output;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const DEFLATE_METHOD: u8 = 8;
+
+// header flags
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const RESERVED_FLAGS: u8 = 0xE0;
+
+// extra flags saying how hard the compressor tried
+const XFL_SMALLEST: u8 = 2;
+const XFL_FASTEST: u8 = 4;
+const OS_UNKNOWN: u8 = 255;
+
+pub fn gzip(in_file: &Path, out_file: &Path, matching: &MatchOptions) -> Result<(), Error>
+{
+    let mut reader = BufReader::new(File::open(in_file)?);
+    let mut writer = BitWriter::with_order(BufWriter::new(File::create_new(out_file)?),
+                                           BitOrder::LsbFirst);
+    write_gzip(&mut reader, &mut writer, matching)?;
+    writer.flush()
+}
+
+pub fn gunzip(in_file: &Path, out_file: &Path) -> Result<(), Error>
+{
+    let mut reader =
+        BitReader::with_order(BufReader::new(File::open(in_file)?), BitOrder::LsbFirst);
+    write_output(out_file, |writer| {
+        read_gzip(&mut reader, writer).map(|_| ())
+    })
+}
+
+// whether the file starts like a gzip file
+pub fn is_gzip(in_file: &Path) -> Result<bool, Error>
+{
+    let mut magic = [0u8; 2];
+    match File::open(in_file)?.read_exact(&mut magic)
+    {
+        Ok(()) => Ok(magic == GZIP_MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// write everything the reader has as a single gzip member, the writer has to write least
+// significant bit first
+pub fn write_gzip<R: Read, W: Write>(reader: &mut R,
+                                     writer: &mut BitWriter<W>,
+                                     matching: &MatchOptions)
+                                     -> Result<(), Error>
+{
+    let extra_flags = match matching.max_chain
+    {
+        0..=8 => XFL_FASTEST,
+        1024.. => XFL_SMALLEST,
+        _ => 0,
+    };
+    // magic, method, no flags, no modification time, extra flags and operating system
+    for byte in GZIP_MAGIC.iter()
+                          .chain(&[DEFLATE_METHOD, 0, 0, 0, 0, 0, extra_flags, OS_UNKNOWN])
+    {
+        writer.write_bits(*byte as u64, 8)?;
+    }
+
+    let (len, crc) = deflate(reader, writer, matching)?;
+    writer.align_to_byte()?;
+    writer.write_bits(crc as u64, 32)?;
+    // the length is only kept modulo 2^32
+    writer.write_bits(len & 0xFFFF_FFFF, 32)
+}
+
+// decompress every member of a gzip file into the writer, returning the length written
+//
+// The reader has to read least significant bit first. Each member's CRC-32 and length are checked
+// as it ends.
+pub fn read_gzip<R: Read, W: Write>(reader: &mut BitReader<R>, writer: &mut W)
+                                    -> Result<u64, Error>
+{
+    let mut total = 0;
+    read_gzip_header(reader)?;
+    loop
+    {
+        let (len, crc) = inflate(reader, writer)?;
+        reader.align_to_byte();
+        let expected_crc = read_value(reader, 32)? as u32;
+        let expected_len = read_value(reader, 32)?;
+        if crc != expected_crc
+        {
+            return Err(FormatError::ChecksumMismatch { expected: expected_crc, actual: crc }.into());
+        }
+        if len & 0xFFFF_FFFF != expected_len
+        {
+            return Err(FormatError::LengthMismatch { expected: expected_len,
+                                                     actual: len & 0xFFFF_FFFF }.into());
+        }
+        total += len;
+
+        // another member can follow straight after the trailer
+        match reader.read_bits(8)
+        {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(total),
+            Err(e) => return Err(e),
+            Ok(byte) if byte == GZIP_MAGIC[0] as u64 =>
+            {
+                if read_value(reader, 8)? != GZIP_MAGIC[1] as u64
+                {
+                    return Err(not_gzip());
+                }
+                read_gzip_header_after_magic(reader)?;
+            }
+            Ok(_) =>
+            {
+                return Err(Error::new(ErrorKind::InvalidData, "trailing data after gzip file"))
+            }
+        }
+    }
+}
+
+fn not_gzip() -> Error { Error::new(ErrorKind::InvalidData, "not a gzip file") }
+
+fn read_gzip_header<R: Read>(reader: &mut BitReader<R>) -> Result<(), Error>
+{
+    for magic in GZIP_MAGIC
+    {
+        if reader.read_bits(8).map_err(|_| not_gzip())? != magic as u64
+        {
+            return Err(not_gzip());
+        }
+    }
+    read_gzip_header_after_magic(reader)
+}
+
+// the rest of a member's header, skipping over the optional fields
+fn read_gzip_header_after_magic<R: Read>(reader: &mut BitReader<R>) -> Result<(), Error>
+{
+    let method = read_value(reader, 8)? as u8;
+    if method != DEFLATE_METHOD
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("unsupported gzip compression method {}", method)));
+    }
+    let flags = read_value(reader, 8)? as u8;
+    if flags & RESERVED_FLAGS != 0
+    {
+        return Err(FormatError::UnsupportedFlags(flags).into());
+    }
+    // modification time, extra flags and operating system
+    read_value(reader, 32)?;
+    read_value(reader, 16)?;
+
+    if flags & FEXTRA != 0
+    {
+        let len = read_value(reader, 16)?;
+        for _ in 0..len
+        {
+            read_value(reader, 8)?;
+        }
+    }
+    for field in [FNAME, FCOMMENT]
+    {
+        if flags & field != 0
+        {
+            // zero terminated
+            while read_value(reader, 8)? != 0
+            {}
+        }
+    }
+    if flags & FHCRC != 0
+    {
+        read_value(reader, 16)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+
+    use super::*;
+
+    struct TestFile
+    {
+        path: PathBuf,
+    }
+
+    impl TestFile
+    {
+        pub fn create(path: PathBuf) -> Self { Self { path } }
+    }
+
+    impl Drop for TestFile
+    {
+        fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+    }
+
+    fn gunzip_bytes(file: &[u8]) -> Result<Vec<u8>, Error>
+    {
+        let mut reader = BitReader::with_order(file, BitOrder::LsbFirst);
+        let mut output = Vec::new();
+        let len = read_gzip(&mut reader, &mut output)?;
+        assert_eq!(len, output.len() as u64);
+        Ok(output)
+    }
+
+    fn gzip_bytes(data: &[u8], matching: &MatchOptions) -> Vec<u8>
+    {
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::LsbFirst);
+        write_gzip(&mut &data[..], &mut writer, matching).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    fn format_error(error: &Error) -> Option<&FormatError>
+    {
+        error.get_ref()?.downcast_ref::<FormatError>()
+    }
+
+    fn fixture(name: &str) -> Vec<u8>
+    {
+        std::fs::read(format!("testdata/{}", name)).unwrap_or_else(|_| {
+                                                       panic!("failed reading testdata/{}", name)
+                                                   })
+    }
+
+    #[test]
+    fn test_gzip_fixtures()
+    {
+        // written by gzip -9 -n, and gzip -n for the rest
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        assert_eq!(gunzip_bytes(&fixture("asyoulik.txt.gz")).unwrap(), text);
+        assert_eq!(gunzip_bytes(&fixture("empty.gz")).unwrap(), b"");
+        assert_eq!(gunzip_bytes(&fixture("hello.txt.gz")).unwrap(),
+                   b"hello hello hello\n");
+        // incompressible, so in stored blocks
+        assert_eq!(gunzip_bytes(&fixture("random.bin.gz")).unwrap(),
+                   fixture("random.bin"));
+        // with the file name and modification time in the header
+        assert_eq!(gunzip_bytes(&fixture("named.txt.gz")).unwrap(),
+                   &text[..20000]);
+        // two members
+        assert_eq!(gunzip_bytes(&fixture("multi.gz")).unwrap(),
+                   b"first member\nsecond member\n");
+    }
+
+    #[test]
+    fn test_gzip_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        for data in [&text[..],
+                     b"",
+                     b"hello hello hello\n",
+                     &fixture("random.bin")]
+        {
+            let file = gzip_bytes(data, &MatchOptions::default());
+            assert_eq!(file[..4], [0x1F, 0x8B, 8, 0]);
+            assert_eq!(gunzip_bytes(&file).unwrap(), data);
+        }
+
+        // no bigger than gzip's own output for a short input, and close to it for a long one
+        assert!(gzip_bytes(b"hello hello hello\n", &MatchOptions::default()).len()
+                <= fixture("hello.txt.gz").len());
+        let file = gzip_bytes(&text, &MatchOptions::level(9));
+        assert!(file.len() < fixture("asyoulik.txt.gz").len() * 101 / 100);
+    }
+
+    #[test]
+    fn test_gzip_files()
+    {
+        let compressed = TestFile::create(PathBuf::from("kmd_GzipFiles.gz"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_GzipFiles.uncompressed"));
+        let _ = std::fs::remove_file(&compressed.path);
+        let _ = std::fs::remove_file(&uncompressed.path);
+
+        gzip(Path::new("asyoulik.txt"),
+             &compressed.path,
+             &MatchOptions::default()).expect("failed compressing");
+        assert!(is_gzip(&compressed.path).unwrap());
+        assert!(!is_gzip(Path::new("asyoulik.txt")).unwrap());
+        gunzip(&compressed.path, &uncompressed.path).expect("failed uncompressing");
+        assert_eq!(std::fs::read(&uncompressed.path).unwrap(),
+                   std::fs::read("asyoulik.txt").unwrap());
+    }
+
+    #[test]
+    fn test_gzip_header_fields()
+    {
+        // every optional field, put together by hand around the deflate stream from a fixture
+        let hello = fixture("hello.txt.gz");
+        let mut file = vec![0x1F,
+                            0x8B,
+                            8,
+                            FHCRC | FEXTRA | FNAME | FCOMMENT,
+                            0,
+                            0,
+                            0,
+                            0,
+                            0,
+                            3];
+        file.extend_from_slice(&[4, 0, b'a', b'b', 0, 0]);
+        file.extend_from_slice(b"hello.txt\0a comment\0");
+        file.extend_from_slice(&[0x12, 0x34]);
+        file.extend_from_slice(&hello[10..]);
+        assert_eq!(gunzip_bytes(&file).unwrap(), b"hello hello hello\n");
+
+        file[3] |= 0x80;
+        assert_eq!(gunzip_bytes(&file).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_gzip_invalid()
+    {
+        let hello = fixture("hello.txt.gz");
+        assert_eq!(gunzip_bytes(b"HUF\x1a").unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+        assert_eq!(gunzip_bytes(b"").unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+
+        let mut method = hello.clone();
+        method[2] = 7;
+        assert_eq!(gunzip_bytes(&method).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+
+        // the crc and then the length in the trailer
+        let mut crc = hello.clone();
+        crc[hello.len() - 8] ^= 1;
+        let error = gunzip_bytes(&crc).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::ChecksumMismatch { .. })));
+        let mut len = hello.clone();
+        len[hello.len() - 4] ^= 1;
+        let error = gunzip_bytes(&len).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::LengthMismatch { .. })));
+
+        assert!(gunzip_bytes(&hello[..hello.len() - 3]).is_err());
+        let mut trailing = hello.clone();
+        trailing.push(0);
+        assert_eq!(gunzip_bytes(&trailing).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+}
@@ -1 +1,1273 @@
+use crate::container::FormatError;
+use crate::file_lib::bit::Bit;
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
 
+// every byte value, the end of the data is found from the length in the header
+pub const ALPHABET_SIZE: usize = 256;
+
+// code lengths are stored in LENGTH_BITS bits, so that's as long as a code can get
+pub const MAX_CODE_LENGTH: u8 = (1 << LENGTH_BITS) - 1;
+const LENGTH_BITS: u32 = 6;
+
+// codes up to this long can be decoded with a TableDecoder
+pub const MAX_TABLE_CODE_LENGTH: u8 = 20;
+// trees built from a frequency table keep their codes this short unless asked otherwise
+pub const DEFAULT_MAX_CODE_LENGTH: u8 = MAX_TABLE_CODE_LENGTH;
+// bits looked up at once in a TableDecoder's first level table
+pub const TABLE_BITS: u32 = 10;
+
+const ZERO_RUN_BITS: u32 = 8;
+const MAX_ZERO_RUN: usize = 1 << ZERO_RUN_BITS;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrequencyTable
+{
+    counts: Vec<u64>,
+}
+
+impl FrequencyTable
+{
+    pub fn new() -> Self { Self::with_alphabet(ALPHABET_SIZE) }
+
+    // for symbols other than bytes, like the lengths and distances of LZ77 matches
+    pub fn with_alphabet(symbols: usize) -> Self { Self { counts: vec![0; symbols] } }
+
+    pub fn alphabet_size(&self) -> usize { self.counts.len() }
+
+    pub fn add(&mut self, symbol: u16) { self.counts[symbol as usize] += 1; }
+
+    pub fn add_count(&mut self, symbol: u16, count: u64) { self.counts[symbol as usize] += count; }
+
+    // add every byte of a slice, quicker than adding them one at a time
+    pub fn add_bytes(&mut self, bytes: &[u8])
+    {
+        // a fixed size table needs no bounds checks, and four of them keep runs of the same byte
+        // from waiting on each other's increments
+        let mut counts = [[0u64; 256]; 4];
+        let mut chunks = bytes.chunks_exact(4);
+        for chunk in chunks.by_ref()
+        {
+            counts[0][chunk[0] as usize] += 1;
+            counts[1][chunk[1] as usize] += 1;
+            counts[2][chunk[2] as usize] += 1;
+            counts[3][chunk[3] as usize] += 1;
+        }
+        for byte in chunks.remainder()
+        {
+            counts[0][*byte as usize] += 1;
+        }
+
+        for byte in 0..256
+        {
+            let count = counts.iter().map(|counts| counts[byte]).sum::<u64>();
+            if count > 0
+            {
+                self.counts[byte] += count;
+            }
+        }
+    }
+
+    pub fn count(&self, symbol: u16) -> u64 { self.counts[symbol as usize] }
+
+    // iterate over (symbol, count) for every symbol that has been seen at least once
+    pub fn symbols(&self) -> impl Iterator<Item = (u16, u64)> + '_
+    {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(symbol, count)| (symbol as u16, *count))
+    }
+}
+
+impl Default for FrequencyTable
+{
+    fn default() -> Self { Self::new() }
+}
+
+// a code word, the low `len` bits of `bits` are the code, written most significant bit first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Code
+{
+    pub bits: u64,
+    pub len: u8,
+}
+
+impl Code
+{
+    pub fn bits(&self) -> impl Iterator<Item = Bit> + '_
+    {
+        (0..self.len).rev()
+                     .map(|shift| (self.bits >> shift & 1 == 1).into())
+    }
+}
+
+#[derive(Debug)]
+enum Node
+{
+    Leaf(u16),
+    // children for a 0 bit and a 1 bit
+    Internal(usize, usize),
+}
+
+// nodes live in a vec and refer to each other by index
+#[derive(Debug)]
+pub struct HuffmanTree
+{
+    nodes: Vec<Node>,
+    root: usize,
+    alphabet_size: usize,
+}
+
+impl HuffmanTree
+{
+    // build a tree from the frequency table with codes no longer than DEFAULT_MAX_CODE_LENGTH
+    // returns None if the table is empty
+    pub fn new(frequencies: &FrequencyTable) -> Option<Self>
+    {
+        Self::with_max_length(frequencies, DEFAULT_MAX_CODE_LENGTH)
+    }
+
+    // build the best tree whose codes are no longer than max_length, which is capped at
+    // MAX_CODE_LENGTH
+    // returns None if the table is empty, or has more symbols than codes of max_length bits
+    pub fn with_max_length(frequencies: &FrequencyTable, max_length: u8) -> Option<Self>
+    {
+        let max_length = max_length.clamp(1, MAX_CODE_LENGTH);
+        if frequencies.symbols().count() as u128 > 1u128 << max_length
+        {
+            return None;
+        }
+
+        let tree = Self::unlimited(frequencies)?;
+        if tree.code_lengths().into_iter().max()? <= max_length
+        {
+            return Some(tree);
+        }
+
+        let lengths = package_merge(frequencies, max_length);
+        Some(Self::from_lengths(&lengths).expect("package-merge gives a complete code"))
+    }
+
+    // plain huffman, the codes can get as long as the number of symbols
+    fn unlimited(frequencies: &FrequencyTable) -> Option<Self>
+    {
+        let mut nodes = Vec::new();
+        // ties are broken on node index so the same table always produces the same tree
+        let mut heap = BinaryHeap::new();
+
+        for (symbol, count) in frequencies.symbols()
+        {
+            heap.push(Reverse((count, nodes.len())));
+            nodes.push(Node::Leaf(symbol));
+        }
+
+        while heap.len() > 1
+        {
+            let Reverse((zero_weight, zero)) = heap.pop()?;
+            let Reverse((one_weight, one)) = heap.pop()?;
+            heap.push(Reverse((zero_weight + one_weight, nodes.len())));
+            nodes.push(Node::Internal(zero, one));
+        }
+
+        let Reverse((_, root)) = heap.pop()?;
+        Some(Self { nodes, root, alphabet_size: frequencies.alphabet_size() })
+    }
+
+    // rebuild the tree for the canonical code described by the code lengths
+    // the lengths must describe a complete code, or a single symbol
+    pub fn from_lengths(lengths: &[u8]) -> Result<Self, Error>
+    {
+        validate_lengths(lengths)?;
+        let codes = canonical_codes(lengths);
+
+        let mut symbols = codes.iter().enumerate().filter(|(_, code)| code.is_some());
+        if let (Some((symbol, _)), None) = (symbols.next(), symbols.next())
+        {
+            return Ok(Self { nodes: vec![Node::Leaf(symbol as u16)],
+                             root: 0,
+                             alphabet_size: lengths.len() });
+        }
+
+        // the root is never anyone's child, so a child index of 0 marks a child not yet created
+        let mut nodes = vec![Node::Internal(0, 0)];
+        for (symbol, code) in codes.iter().enumerate()
+        {
+            let Some(code) = code
+            else
+            {
+                continue;
+            };
+
+            let mut index = 0;
+            for (depth, bit) in code.bits().enumerate()
+            {
+                let next = nodes.len();
+                let Node::Internal(zero, one) = &mut nodes[index]
+                else
+                {
+                    unreachable!("canonical codes are prefix free")
+                };
+                let child = match bit
+                {
+                    Bit::Zero => zero,
+                    Bit::One => one,
+                };
+
+                if *child == 0
+                {
+                    *child = next;
+                }
+                index = *child;
+
+                if index == next
+                {
+                    let is_leaf = depth + 1 == code.len as usize;
+                    nodes.push(match is_leaf
+                               {
+                                   true => Node::Leaf(symbol as u16),
+                                   false => Node::Internal(0, 0),
+                               });
+                }
+            }
+        }
+
+        Ok(Self { nodes, root: 0, alphabet_size: lengths.len() })
+    }
+
+    // the depth of every symbol's leaf, indexed by symbol, 0 for symbols not in the tree
+    // a tree with a single leaf gives that symbol a length of 1
+    pub fn code_lengths(&self) -> Vec<u8>
+    {
+        let mut lengths = vec![0; self.alphabet_size];
+        let mut stack = vec![(self.root, 0u8)];
+
+        while let Some((index, depth)) = stack.pop()
+        {
+            match self.nodes[index]
+            {
+                Node::Leaf(symbol) => lengths[symbol as usize] = depth.max(1),
+                Node::Internal(zero, one) =>
+                {
+                    stack.push((zero, depth + 1));
+                    stack.push((one, depth + 1));
+                }
+            }
+        }
+
+        lengths
+    }
+
+    // walk the tree one bit at a time until a leaf is reached
+    pub fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, Error>
+    {
+        let mut index = self.root;
+
+        if let Node::Leaf(symbol) = self.nodes[index]
+        {
+            // single leaf trees still spend one bit per symbol
+            read_bit(reader)?;
+            return Ok(symbol);
+        }
+
+        loop
+        {
+            match self.nodes[index]
+            {
+                Node::Leaf(symbol) => return Ok(symbol),
+                Node::Internal(zero, one) =>
+                {
+                    index = match read_bit(reader)?
+                    {
+                        Bit::Zero => zero,
+                        Bit::One => one,
+                    };
+                }
+            }
+        }
+    }
+
+    // the tree as a graphviz digraph, leaves are labelled with their symbol and count and
+    // internal nodes with the total count under them, the counts come from the table
+    pub fn to_dot(&self, frequencies: &FrequencyTable) -> String
+    {
+        let weights = self.weights(frequencies);
+        let mut dot = String::from("digraph huffman {\n    node [shape=circle];\n");
+        let mut stack = vec![self.root];
+
+        while let Some(index) = stack.pop()
+        {
+            match self.nodes[index]
+            {
+                Node::Leaf(symbol) =>
+                {
+                    let name = symbol_name(symbol).replace('\\', "\\\\")
+                                                  .replace('"', "\\\"");
+                    dot.push_str(&format!("    n{} [shape=box, label=\"{}\\n{}\"];\n",
+                                          index, name, weights[index]));
+                }
+                Node::Internal(zero, one) =>
+                {
+                    dot.push_str(&format!("    n{} [label=\"{}\"];\n", index, weights[index]));
+                    dot.push_str(&format!("    n{} -> n{} [label=\"0\"];\n", index, zero));
+                    dot.push_str(&format!("    n{} -> n{} [label=\"1\"];\n", index, one));
+                    stack.push(one);
+                    stack.push(zero);
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    // the count of every node, a leaf's from the table and an internal node's from its children
+    fn weights(&self, frequencies: &FrequencyTable) -> Vec<u64>
+    {
+        let mut weights = vec![0; self.nodes.len()];
+        // each node is seen once on the way down and again once its children are done
+        let mut stack = vec![(self.root, false)];
+
+        while let Some((index, children_done)) = stack.pop()
+        {
+            match self.nodes[index]
+            {
+                Node::Leaf(symbol) => weights[index] = frequencies.count(symbol),
+                Node::Internal(zero, one) if children_done =>
+                {
+                    weights[index] = weights[zero] + weights[one]
+                }
+                Node::Internal(zero, one) =>
+                {
+                    stack.push((index, true));
+                    stack.push((zero, false));
+                    stack.push((one, false));
+                }
+            }
+        }
+
+        weights
+    }
+}
+
+// a symbol the way a person would want to read it, bytes that are printable ascii as themselves
+// and other bytes in hex, symbols past the bytes as numbers
+pub fn symbol_name(symbol: u16) -> String
+{
+    match symbol
+    {
+        0x20 => "' '".to_string(),
+        0x21..=0x7e => (symbol as u8 as char).to_string(),
+        0..=0xff => format!("{:#04x}", symbol),
+        _ => symbol.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TableEntry
+{
+    // no code starts with these bits
+    Invalid,
+    // the symbol and its code length
+    Symbol(u16, u8),
+    // the code is longer than the first level, look up the next `bits` bits in the second level
+    // starting at `offset`
+    Link(u32, u8),
+}
+
+// Decodes a symbol with a table lookup instead of a bit at a time tree walk
+//
+// The next TABLE_BITS bits index the first level table, every entry whose index starts with a
+// code holds that code's symbol and length. Codes longer than that share their first TABLE_BITS
+// with a link to a second level table sized for the longest code under it.
+#[derive(Debug)]
+pub struct TableDecoder
+{
+    bits: u32,
+    primary: Vec<TableEntry>,
+    secondary: Vec<TableEntry>,
+}
+
+impl TableDecoder
+{
+    pub fn new(lengths: &[u8]) -> Result<Self, Error> { Self::with_table_bits(lengths, TABLE_BITS) }
+
+    pub fn with_table_bits(lengths: &[u8], bits: u32) -> Result<Self, Error>
+    {
+        validate_lengths(lengths)?;
+        if lengths.iter().any(|len| *len > MAX_TABLE_CODE_LENGTH)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  "codes are too long for a decoding table"));
+        }
+
+        let bits = bits.clamp(1, MAX_TABLE_CODE_LENGTH as u32);
+        let codes = canonical_codes(lengths);
+        let mut primary = vec![TableEntry::Invalid; 1 << bits];
+        let mut secondary = Vec::new();
+
+        // the longest code under each first level entry decides the size of its second level
+        let mut longest = vec![0u32; 1 << bits];
+        for code in codes.iter().flatten().filter(|code| code.len as u32 > bits)
+        {
+            let prefix = (code.bits >> (code.len as u32 - bits)) as usize;
+            longest[prefix] = longest[prefix].max(code.len as u32);
+        }
+        for (prefix, len) in longest.iter().enumerate().filter(|(_, len)| **len > 0)
+        {
+            let sub_bits = len - bits;
+            primary[prefix] = TableEntry::Link(secondary.len() as u32, sub_bits as u8);
+            secondary.resize(secondary.len() + (1 << sub_bits), TableEntry::Invalid);
+        }
+
+        for (symbol, code) in codes.iter().enumerate()
+        {
+            let Some(code) = code
+            else
+            {
+                continue;
+            };
+            let entry = TableEntry::Symbol(symbol as u16, code.len);
+            let len = code.len as u32;
+
+            if len <= bits
+            {
+                let start = (code.bits << (bits - len)) as usize;
+                primary[start..start + (1 << (bits - len))].fill(entry);
+            }
+            else
+            {
+                let prefix = (code.bits >> (len - bits)) as usize;
+                let TableEntry::Link(offset, sub_bits) = primary[prefix]
+                else
+                {
+                    unreachable!("every long code has a link")
+                };
+                let spare = sub_bits as u32 - (len - bits);
+                let rest = (code.bits & ((1 << (len - bits)) - 1)) as usize;
+                let start = offset as usize + (rest << spare);
+                secondary[start..start + (1 << spare)].fill(entry);
+            }
+        }
+
+        Ok(Self { bits, primary, secondary })
+    }
+
+    pub fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, Error>
+    {
+        let index = reader.peek_bits(self.bits)? as usize;
+        let entry = match self.primary[index]
+        {
+            TableEntry::Link(offset, sub_bits) =>
+            {
+                skip_bits(reader, self.bits)?;
+                let index = reader.peek_bits(sub_bits as u32)? as usize;
+                match self.secondary[offset as usize + index]
+                {
+                    TableEntry::Symbol(symbol, len) =>
+                    {
+                        TableEntry::Symbol(symbol, len - self.bits as u8)
+                    }
+                    _ => TableEntry::Invalid,
+                }
+            }
+            entry => entry,
+        };
+
+        match entry
+        {
+            TableEntry::Symbol(symbol, len) =>
+            {
+                skip_bits(reader, len as u32)?;
+                Ok(symbol)
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "invalid code")),
+        }
+    }
+}
+
+// decodes with a table when the codes are short enough, falling back to walking the tree
+#[derive(Debug)]
+pub enum SymbolDecoder
+{
+    Table(TableDecoder),
+    Tree(HuffmanTree),
+}
+
+impl SymbolDecoder
+{
+    pub fn from_lengths(lengths: &[u8]) -> Result<Self, Error>
+    {
+        validate_lengths(lengths)?;
+        if lengths.iter().all(|len| *len <= MAX_TABLE_CODE_LENGTH)
+        {
+            Ok(SymbolDecoder::Table(TableDecoder::new(lengths)?))
+        }
+        else
+        {
+            Ok(SymbolDecoder::Tree(HuffmanTree::from_lengths(lengths)?))
+        }
+    }
+
+    pub fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, Error>
+    {
+        match self
+        {
+            SymbolDecoder::Table(table) => table.decode(reader),
+            SymbolDecoder::Tree(tree) => tree.decode(reader),
+        }
+    }
+}
+
+// An item in a package-merge list, either a symbol or a package of two items from the list before
+#[derive(Clone, Copy)]
+enum MergeItem
+{
+    Leaf(u16),
+    Package(usize, usize),
+}
+
+// Optimal code lengths no longer than max_length, by the package-merge algorithm
+//
+// Each symbol is a coin worth 2^-length for every length it could have, the cheapest set of coins
+// adding up to n - 1 gives the lengths, a symbol's length being the number of its coins picked.
+// The list for each length is the symbols merged with the cheapest pairs from the list for the
+// next length down, and the first 2n - 2 items of the final list are the coins picked.
+fn package_merge(frequencies: &FrequencyTable, max_length: u8) -> Vec<u8>
+{
+    let mut leaves = frequencies.symbols()
+                                .map(|(symbol, count)| (count, symbol))
+                                .collect::<Vec<_>>();
+    leaves.sort();
+
+    let mut lists: Vec<Vec<(u64, MergeItem)>> =
+        vec![leaves.iter()
+                   .map(|(count, symbol)| (*count, MergeItem::Leaf(*symbol)))
+                   .collect()];
+    for _ in 1..max_length
+    {
+        let previous = lists.last().expect("starts with the leaves");
+        let mut packages = previous.chunks_exact(2)
+                                   .enumerate()
+                                   .map(|(index, pair)| {
+                                       (pair[0].0 + pair[1].0,
+                                        MergeItem::Package(2 * index, 2 * index + 1))
+                                   })
+                                   .peekable();
+
+        // leaves go first on ties so the result only depends on the counts
+        let mut list = Vec::with_capacity(2 * leaves.len());
+        for (count, symbol) in &leaves
+        {
+            while let Some(package) = packages.next_if(|package| package.0 < *count)
+            {
+                list.push(package);
+            }
+            list.push((*count, MergeItem::Leaf(*symbol)));
+        }
+        list.extend(packages);
+        lists.push(list);
+    }
+
+    let mut lengths = vec![0u8; frequencies.alphabet_size()];
+    let picked = 2 * leaves.len() - 2;
+    let mut stack = (0..picked).map(|index| (lists.len() - 1, index))
+                               .collect::<Vec<_>>();
+    while let Some((level, index)) = stack.pop()
+    {
+        match lists[level][index].1
+        {
+            MergeItem::Leaf(symbol) => lengths[symbol as usize] += 1,
+            MergeItem::Package(zero, one) =>
+            {
+                stack.push((level - 1, zero));
+                stack.push((level - 1, one));
+            }
+        }
+    }
+
+    lengths
+}
+
+// assign canonical codes from code lengths, shorter codes come first and codes of the same length
+// are handed out in symbol order, so the lengths alone are enough to rebuild the codebook
+pub fn canonical_codes(lengths: &[u8]) -> Vec<Option<Code>>
+{
+    let mut order = (0..lengths.len()).filter(|symbol| lengths[*symbol] > 0)
+                                      .collect::<Vec<_>>();
+    order.sort_by_key(|symbol| (lengths[*symbol], *symbol));
+
+    let mut codes = vec![None; lengths.len()];
+    let mut next = Code { bits: 0, len: 0 };
+    for symbol in order
+    {
+        next.bits <<= lengths[symbol] - next.len;
+        next.len = lengths[symbol];
+        codes[symbol] = Some(next);
+        next.bits += 1;
+    }
+
+    codes
+}
+
+// the lengths need to fill the code space exactly, the only exception is a lone symbol which gets
+// a single bit code
+pub fn validate_lengths(lengths: &[u8]) -> Result<(), Error>
+{
+    let invalid = |msg| Err(Error::new(ErrorKind::InvalidData, msg));
+
+    if lengths.iter().any(|len| *len > MAX_CODE_LENGTH)
+    {
+        return invalid("code length is too long");
+    }
+
+    // sum of 2^-len scaled by 2^MAX_CODE_LENGTH, a complete code adds up to exactly 1
+    let kraft = lengths.iter()
+                       .filter(|len| **len > 0)
+                       .map(|len| 1u128 << (MAX_CODE_LENGTH - len))
+                       .sum::<u128>();
+    let used = lengths.iter().filter(|len| **len > 0).count();
+
+    match (used, kraft.cmp(&(1u128 << MAX_CODE_LENGTH)))
+    {
+        (0, _) => invalid("code lengths are empty"),
+        (1, _) if lengths.contains(&1) => Ok(()),
+        (_, Ordering::Equal) => Ok(()),
+        (_, Ordering::Greater) => invalid("code lengths are over-subscribed"),
+        (_, Ordering::Less) => invalid("code lengths are incomplete"),
+    }
+}
+
+// a fixed model for data that can't be counted before it's compressed, every byte gets a code but
+// the short ones go to english text
+pub fn static_code_lengths() -> Vec<u8>
+{
+    // letter frequencies per thousand letters of english text
+    const LETTERS: &[u8; 26] = b"etaoinshrdlcumwfgypbvkjxqz";
+    const LETTER_COUNTS: [u64; 26] = [127, 91, 82, 75, 70, 67, 63, 61, 60, 43, 40, 28, 28, 24, 24,
+                                      22, 20, 20, 19, 15, 10, 8, 2, 2, 1, 1];
+
+    let mut frequencies = FrequencyTable::new();
+    for symbol in 0..ALPHABET_SIZE as u16
+    {
+        let count = match symbol as u8
+        {
+            b' ' => 1800,
+            b'\n' | b'.' | b',' => 200,
+            b'!'..=b'~' => 20,
+            _ => 1,
+        };
+        frequencies.add_count(symbol, count);
+    }
+    for (letter, count) in LETTERS.iter().zip(LETTER_COUNTS)
+    {
+        frequencies.add_count(*letter as u16, count * 10);
+        frequencies.add_count(letter.to_ascii_uppercase() as u16, count);
+    }
+
+    HuffmanTree::new(&frequencies).expect("every symbol has a count")
+                                  .code_lengths()
+}
+
+// the code lengths are written out one token per symbol, except that runs of unused symbols are
+// collapsed, which keeps the header small for files that only use a few distinct bytes
+#[derive(Debug, PartialEq)]
+pub enum LengthToken
+{
+    // 0 then LENGTH_BITS bits of code length
+    Length(u8),
+    // 1 then ZERO_RUN_BITS bits of run length - 1
+    ZeroRun(usize),
+}
+
+impl LengthToken
+{
+    pub fn size_bits(&self) -> usize
+    {
+        match self
+        {
+            LengthToken::Length(_) => 1 + LENGTH_BITS as usize,
+            LengthToken::ZeroRun(_) => 1 + ZERO_RUN_BITS as usize,
+        }
+    }
+}
+
+// split the code lengths into tokens, a zero run only pays off once it covers two symbols
+pub fn length_tokens(lengths: &[u8]) -> Vec<LengthToken>
+{
+    let mut tokens = Vec::new();
+    let mut symbol = 0;
+
+    while symbol < lengths.len()
+    {
+        let run = lengths[symbol..].iter()
+                                   .take(MAX_ZERO_RUN)
+                                   .take_while(|len| **len == 0)
+                                   .count();
+        if run >= 2
+        {
+            tokens.push(LengthToken::ZeroRun(run));
+            symbol += run;
+        }
+        else
+        {
+            tokens.push(LengthToken::Length(lengths[symbol]));
+            symbol += 1;
+        }
+    }
+
+    tokens
+}
+
+// the bits taken by the codes for every symbol counted, None if a symbol has no code
+pub fn coded_bits(frequencies: &FrequencyTable, lengths: &[u8]) -> Option<usize>
+{
+    frequencies.symbols()
+               .map(|(symbol, count)| match lengths[symbol as usize]
+               {
+                   0 => None,
+                   len => Some(count as usize * len as usize),
+               })
+               .sum()
+}
+
+// number of bits write_code_lengths will use for these lengths
+pub fn code_lengths_size_bits(lengths: &[u8]) -> usize
+{
+    length_tokens(lengths).iter()
+                          .map(LengthToken::size_bits)
+                          .sum()
+}
+
+pub fn write_code_lengths<W: Write>(writer: &mut BitWriter<W>, lengths: &[u8])
+                                    -> Result<(), Error>
+{
+    if lengths.iter().any(|len| *len > MAX_CODE_LENGTH)
+    {
+        return Err(Error::new(ErrorKind::InvalidInput, "code length is too long to store"));
+    }
+
+    for token in length_tokens(lengths)
+    {
+        match token
+        {
+            LengthToken::Length(len) =>
+            {
+                writer.write(&Bit::Zero)?;
+                writer.write_bits(len as u64, LENGTH_BITS)?;
+            }
+            LengthToken::ZeroRun(run) =>
+            {
+                writer.write(&Bit::One)?;
+                writer.write_bits(run as u64 - 1, ZERO_RUN_BITS)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// read back the code lengths for every byte
+pub fn read_code_lengths<R: Read>(reader: &mut BitReader<R>) -> Result<Vec<u8>, Error>
+{
+    read_alphabet_code_lengths(reader, ALPHABET_SIZE)
+}
+
+// read back the code lengths for an alphabet of any size
+pub fn read_alphabet_code_lengths<R: Read>(reader: &mut BitReader<R>,
+                                           symbols: usize)
+                                           -> Result<Vec<u8>, Error>
+{
+    let mut lengths = Vec::with_capacity(symbols);
+
+    while lengths.len() < symbols
+    {
+        match read_bit(reader)?
+        {
+            Bit::Zero => lengths.push(read_value(reader, LENGTH_BITS)? as u8),
+            Bit::One =>
+            {
+                let run = read_value(reader, ZERO_RUN_BITS)? as usize + 1;
+                if lengths.len() + run > symbols
+                {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                                          "zero run is past the alphabet"));
+                }
+                lengths.resize(lengths.len() + run, 0);
+            }
+        }
+    }
+
+    Ok(lengths)
+}
+
+// write out a code word, most significant bit first
+pub fn write_code<W: Write>(writer: &mut BitWriter<W>, code: &Code) -> Result<(), Error>
+{
+    writer.write_bits(code.bits, code.len as u32)
+}
+
+// write the code of every byte to a MsbFirst writer, the codes are gathered up into 64 bit words
+// rather than written one at a time
+pub fn write_codes<W: Write>(writer: &mut BitWriter<W>,
+                             codes: &[Option<Code>],
+                             bytes: &[u8])
+                             -> Result<(), Error>
+{
+    let mut word = 0u64;
+    let mut word_len = 0u32;
+    for byte in bytes
+    {
+        let code = codes[*byte as usize].ok_or_else(|| {
+                                            Error::new(ErrorKind::InvalidInput,
+                                                       format!("byte {} has no code", byte))
+                                        })?;
+        let len = code.len as u32;
+        if word_len + len > 64
+        {
+            writer.write_bits(word, word_len)?;
+            word = 0;
+            word_len = 0;
+        }
+        word = word << len | code.bits;
+        word_len += len;
+    }
+    writer.write_bits(word, word_len)
+}
+
+// running out of bits in the middle of the header or a code word means the file was cut short
+pub fn read_value<R: Read>(reader: &mut BitReader<R>, bits: u32) -> Result<u64, Error>
+{
+    reader.read_bits(bits).map_err(truncated)
+}
+
+fn skip_bits<R: Read>(reader: &mut BitReader<R>, bits: u32) -> Result<(), Error>
+{
+    reader.consume(bits).map_err(truncated)
+}
+
+fn truncated(error: Error) -> Error
+{
+    match error.kind()
+    {
+        ErrorKind::UnexpectedEof => FormatError::Truncated.into(),
+        _ => error,
+    }
+}
+
+fn read_bit<R: Read>(reader: &mut BitReader<R>) -> Result<Bit, Error>
+{
+    Ok((read_value(reader, 1)? == 1).into())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn table(text: &[u8]) -> FrequencyTable
+    {
+        let mut frequencies = FrequencyTable::new();
+        for byte in text
+        {
+            frequencies.add(*byte as u16);
+        }
+        frequencies
+    }
+
+    fn lengths(text: &[u8]) -> Vec<u8> { HuffmanTree::new(&table(text)).unwrap().code_lengths() }
+
+    #[test]
+    fn test_empty_table()
+    {
+        assert!(HuffmanTree::new(&FrequencyTable::new()).is_none());
+    }
+
+    #[test]
+    fn test_single_symbol()
+    {
+        let codes = canonical_codes(&lengths(b"aaaa"));
+        assert_eq!(codes[b'a' as usize], Some(Code { bits: 0, len: 1 }));
+        assert_eq!(codes.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn test_code_lengths()
+    {
+        // a: 4, b: 2, c: 1, d: 1 is a fully skewed tree
+        let lengths = lengths(b"aaaabbcd");
+        assert_eq!(lengths[b'a' as usize], 1);
+        assert_eq!(lengths[b'b' as usize], 2);
+        assert_eq!(lengths[b'c' as usize], 3);
+        assert_eq!(lengths[b'd' as usize], 3);
+    }
+
+    #[test]
+    fn test_canonical_codes()
+    {
+        let codes = canonical_codes(&lengths(b"aaaabbcd"));
+        assert_eq!(codes[b'a' as usize], Some(Code { bits: 0b0, len: 1 }));
+        assert_eq!(codes[b'b' as usize], Some(Code { bits: 0b10, len: 2 }));
+        assert_eq!(codes[b'c' as usize], Some(Code { bits: 0b110, len: 3 }));
+        assert_eq!(codes[b'd' as usize], Some(Code { bits: 0b111, len: 3 }));
+    }
+
+    #[test]
+    fn test_prefix_free()
+    {
+        let codes = canonical_codes(&lengths(b"the quick brown fox jumps over the lazy dog"));
+        let codes = codes.into_iter().flatten().collect::<Vec<_>>();
+
+        for (i, a) in codes.iter().enumerate()
+        {
+            for b in codes.iter().skip(i + 1)
+            {
+                let len = a.len.min(b.len);
+                assert_ne!(a.bits >> (a.len - len), b.bits >> (b.len - len));
+            }
+        }
+    }
+
+    // fibonacci counts give the deepest possible tree, one more level for every symbol
+    fn fibonacci(symbols: usize) -> FrequencyTable
+    {
+        let mut frequencies = FrequencyTable::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for symbol in 0..symbols as u16
+        {
+            frequencies.add_count(symbol, a);
+            (a, b) = (b, a + b);
+        }
+        frequencies
+    }
+
+    fn cost(frequencies: &FrequencyTable, lengths: &[u8]) -> u64
+    {
+        frequencies.symbols()
+                   .map(|(symbol, count)| count * lengths[symbol as usize] as u64)
+                   .sum()
+    }
+
+    fn limited(frequencies: &FrequencyTable, max_length: u8) -> Vec<u8>
+    {
+        HuffmanTree::with_max_length(frequencies, max_length).unwrap()
+                                                             .code_lengths()
+    }
+
+    #[test]
+    fn test_fibonacci_unlimited()
+    {
+        let lengths = limited(&fibonacci(30), MAX_CODE_LENGTH);
+        assert_eq!(lengths.iter().max(), Some(&29));
+    }
+
+    #[test]
+    fn test_fibonacci_limited()
+    {
+        let frequencies = fibonacci(30);
+        let unlimited = cost(&frequencies, &limited(&frequencies, MAX_CODE_LENGTH));
+        let mut previous = unlimited;
+        for max_length in [28, 20, 12, 5]
+        {
+            let lengths = limited(&frequencies, max_length);
+            assert_eq!(lengths.iter().max(), Some(&max_length));
+            validate_lengths(&lengths).unwrap();
+
+            // a tighter limit can only cost more
+            let cost = cost(&frequencies, &lengths);
+            assert!(cost >= previous);
+            previous = cost;
+        }
+
+        // the default keeps every code short enough for a table
+        let lengths = HuffmanTree::new(&fibonacci(60)).unwrap().code_lengths();
+        assert_eq!(lengths.iter().max(), Some(&DEFAULT_MAX_CODE_LENGTH));
+        TableDecoder::new(&lengths).unwrap();
+    }
+
+    #[test]
+    fn test_limit_too_short()
+    {
+        // three symbols can't all have codes of one bit
+        let mut frequencies = FrequencyTable::new();
+        for symbol in [b'a', b'b', b'c']
+        {
+            frequencies.add(symbol as u16);
+        }
+        assert!(HuffmanTree::with_max_length(&frequencies, 1).is_none());
+        assert_eq!(limited(&frequencies, 2).iter()
+                                           .filter(|len| **len > 0)
+                                           .count(),
+                   3);
+    }
+
+    #[test]
+    fn test_limited_is_optimal()
+    {
+        // try every complete code with lengths up to the limit
+        let frequencies = fibonacci(7);
+        let max_length = 4;
+
+        let mut best = u64::MAX;
+        let mut candidate = vec![0u8; ALPHABET_SIZE];
+        for mut index in 0..(max_length as usize).pow(7)
+        {
+            for length in candidate.iter_mut().take(7)
+            {
+                *length = (index % max_length as usize) as u8 + 1;
+                index /= max_length as usize;
+            }
+            if validate_lengths(&candidate).is_ok()
+            {
+                best = best.min(cost(&frequencies, &candidate));
+            }
+        }
+
+        assert_eq!(cost(&frequencies, &limited(&frequencies, max_length)), best);
+    }
+
+    #[test]
+    fn test_limit_leaves_short_codes_alone()
+    {
+        let frequencies = table(b"aaaabbcd");
+        assert_eq!(limited(&frequencies, 3),
+                   limited(&frequencies, MAX_CODE_LENGTH));
+
+        // a limit of 2 has to flatten it out
+        let lengths = limited(&frequencies, 2);
+        assert_eq!(lengths.iter().filter(|len| **len == 2).count(), 4);
+    }
+
+    #[test]
+    fn test_from_lengths()
+    {
+        let lengths = lengths(b"the quick brown fox jumps over the lazy dog");
+        let rebuilt = HuffmanTree::from_lengths(&lengths).unwrap();
+        assert_eq!(rebuilt.code_lengths(), lengths);
+    }
+
+    #[test]
+    fn test_add_bytes()
+    {
+        let text = b"the quick brown fox jumps over the lazy dog";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add_bytes(text);
+        frequencies.add_bytes(b"");
+        frequencies.add_bytes(b"zz");
+        let mut expected = table(text);
+        expected.add_count(b'z' as u16, 2);
+        assert_eq!(frequencies, expected);
+    }
+
+    #[test]
+    fn test_write_codes()
+    {
+        // long enough codes that the words fill up part way through a code
+        let text = b"the quick brown fox jumps over the lazy dog".repeat(5);
+        let codes = canonical_codes(&lengths(&text));
+
+        let mut one_at_a_time = BitWriter::new(Vec::new());
+        for byte in &text
+        {
+            write_code(&mut one_at_a_time, &codes[*byte as usize].unwrap()).unwrap();
+        }
+        let mut together = BitWriter::new(Vec::new());
+        write_codes(&mut together, &codes, &text).unwrap();
+        assert_eq!(together.bits_written(), one_at_a_time.bits_written());
+        assert_eq!(together.into_inner().unwrap(),
+                   one_at_a_time.into_inner().unwrap());
+
+        let error = write_codes(&mut BitWriter::new(Vec::new()), &codes, b"Q").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_to_dot()
+    {
+        let frequencies = table(b"aaaabbc\"");
+        let tree = HuffmanTree::new(&frequencies).unwrap();
+        assert_eq!(tree.to_dot(&frequencies),
+                   "digraph huffman {\n    node [shape=circle];\n    n6 [label=\"8\"];\n    n6 -> \
+                    n1 [label=\"0\"];\n    n6 -> n5 [label=\"1\"];\n    n1 [shape=box, \
+                    label=\"a\\n4\"];\n    n5 [label=\"4\"];\n    n5 -> n2 [label=\"0\"];\n    \
+                    n5 -> n4 [label=\"1\"];\n    n2 [shape=box, label=\"b\\n2\"];\n    n4 \
+                    [label=\"2\"];\n    n4 -> n0 [label=\"0\"];\n    n4 -> n3 [label=\"1\"];\n    \
+                    n0 [shape=box, label=\"\\\"\\n1\"];\n    n3 [shape=box, label=\"c\\n1\"];\n}\n");
+
+        // a tree rebuilt from its lengths takes its counts from the table all the same
+        let rebuilt = HuffmanTree::from_lengths(&tree.code_lengths()).unwrap();
+        assert!(rebuilt.to_dot(&frequencies)
+                       .contains("[shape=box, label=\"a\\n4\"]"));
+
+        assert_eq!(symbol_name(b' ' as u16), "' '");
+        assert_eq!(symbol_name(b'\n' as u16), "0x0a");
+        assert_eq!(symbol_name(256), "256");
+    }
+
+    #[test]
+    fn test_invalid_lengths()
+    {
+        let mut lengths = vec![0; ALPHABET_SIZE];
+        assert!(HuffmanTree::from_lengths(&lengths).is_err());
+
+        // three codes of length 1 don't fit
+        lengths[..3].fill(1);
+        assert!(HuffmanTree::from_lengths(&lengths).is_err());
+
+        // two codes of length 2 leave half the code space unused
+        lengths[..3].fill(0);
+        lengths[..2].fill(2);
+        assert!(HuffmanTree::from_lengths(&lengths).is_err());
+    }
+
+    #[test]
+    fn test_static_code_lengths()
+    {
+        let lengths = static_code_lengths();
+        assert!(lengths.iter().all(|len| *len > 0));
+        assert!(validate_lengths(&lengths).is_ok());
+        assert!(lengths[b' ' as usize] < lengths[b'e' as usize]);
+        assert!(lengths[b'e' as usize] < lengths[b'z' as usize]);
+        assert!(lengths[b'z' as usize] < lengths[0x00]);
+    }
+
+    fn encode(lengths: &[u8], text: &[u8]) -> Vec<u8>
+    {
+        let codes = canonical_codes(lengths);
+        let mut writer = BitWriter::new(Vec::new());
+        for byte in text
+        {
+            write_code(&mut writer, &codes[*byte as usize].unwrap()).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_table_decoder()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let lengths = lengths(&text);
+        let encoded = encode(&lengths, &text);
+
+        // small first level tables push most codes into the second level
+        for bits in [1, 4, 10, 20]
+        {
+            let table = TableDecoder::with_table_bits(&lengths, bits).unwrap();
+            let mut reader = BitReader::new(&encoded[..]);
+            for byte in &text
+            {
+                assert_eq!(table.decode(&mut reader).unwrap(), *byte as u16);
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_decoder_single_symbol()
+    {
+        let lengths = lengths(b"aaa");
+        let encoded = encode(&lengths, b"aaa");
+        let table = TableDecoder::new(&lengths).unwrap();
+        let mut reader = BitReader::new(&encoded[..]);
+        for _ in 0..3
+        {
+            assert_eq!(table.decode(&mut reader).unwrap(), b'a' as u16);
+        }
+
+        // only the 0 bit is a code
+        let mut reader = BitReader::new(&[0xFFu8][..]);
+        assert_eq!(table.decode(&mut reader).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_table_decoder_truncated()
+    {
+        let lengths = lengths(b"aaaabbcd");
+        let table = TableDecoder::new(&lengths).unwrap();
+
+        // d is 111, only 8 bits are there for the third one
+        let mut reader = BitReader::new(&[0b11111111u8][..]);
+        assert_eq!(table.decode(&mut reader).unwrap(), b'd' as u16);
+        assert_eq!(table.decode(&mut reader).unwrap(), b'd' as u16);
+        let error = table.decode(&mut reader).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_long_codes_fall_back_to_tree()
+    {
+        // a code per length from 1 to 24, plus a second code of length 24 to complete it
+        let mut lengths = (1..=24).collect::<Vec<u8>>();
+        lengths.push(24);
+        lengths.resize(ALPHABET_SIZE, 0);
+
+        assert!(TableDecoder::new(&lengths).is_err());
+        assert!(matches!(SymbolDecoder::from_lengths(&lengths),
+                         Ok(SymbolDecoder::Tree(_))));
+
+        let text = [0u8, 23, 24, 5];
+        let encoded = encode(&lengths, &text);
+        let decoder = SymbolDecoder::from_lengths(&lengths).unwrap();
+        let mut reader = BitReader::new(&encoded[..]);
+        for byte in text
+        {
+            assert_eq!(decoder.decode(&mut reader).unwrap(), byte as u16);
+        }
+    }
+
+    #[test]
+    fn test_header_size_small_alphabet()
+    {
+        let lengths = lengths(b"aaaabbcd");
+
+        // run of 97 unused, a b c d, run of 155 unused
+        let expected = vec![LengthToken::ZeroRun(97),
+                            LengthToken::Length(1),
+                            LengthToken::Length(2),
+                            LengthToken::Length(3),
+                            LengthToken::Length(3),
+                            LengthToken::ZeroRun(155)];
+        assert_eq!(length_tokens(&lengths), expected);
+        assert_eq!(code_lengths_size_bits(&lengths), 2 * 9 + 4 * 7);
+    }
+
+    #[test]
+    fn test_header_size_single_symbol()
+    {
+        // run of 97 unused, a, run of 158 unused
+        assert_eq!(code_lengths_size_bits(&lengths(b"a")), 9 + 7 + 9);
+    }
+
+    #[test]
+    fn test_header_size_large_alphabet()
+    {
+        let text = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        let lengths = lengths(&text);
+
+        // every symbol is used so there are no runs to collapse
+        assert_eq!(code_lengths_size_bits(&lengths), ALPHABET_SIZE * 7);
+    }
+
+    #[test]
+    fn test_header_size_long_run()
+    {
+        // the run in front of the last symbol is longer than a single zero run can cover
+        let mut lengths = vec![0; 300];
+        lengths[0] = 1;
+        lengths[299] = 1;
+
+        let expected = vec![LengthToken::Length(1),
+                            LengthToken::ZeroRun(MAX_ZERO_RUN),
+                            LengthToken::ZeroRun(298 - MAX_ZERO_RUN),
+                            LengthToken::Length(1)];
+        assert_eq!(length_tokens(&lengths), expected);
+    }
+}
@@ -0,0 +1,21 @@
+pub mod adaptive_tree;
+pub mod analyze;
+pub mod archive;
+pub mod compress;
+pub mod container;
+pub mod context_model;
+pub mod crc32;
+pub mod deflate;
+pub mod error;
+pub mod file_lib;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+pub mod gzip;
+pub mod huffman_tree;
+pub mod lz77;
+mod parallel;
+pub mod range_coder;
+#[cfg(test)]
+mod round_trip;
+pub mod transform;
+pub mod uncompress;
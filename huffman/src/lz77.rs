@@ -0,0 +1,679 @@
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::canonical_codes;
+use crate::huffman_tree::code_lengths_size_bits;
+use crate::huffman_tree::coded_bits;
+use crate::huffman_tree::read_alphabet_code_lengths;
+use crate::huffman_tree::read_value;
+use crate::huffman_tree::write_code;
+use crate::huffman_tree::write_code_lengths;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::huffman_tree::SymbolDecoder;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+// how far back a match can reach, DEFLATE's 32 KiB unless asked otherwise
+pub const DEFAULT_WINDOW: usize = 32 * 1024;
+// the distance codes carry on past DEFLATE's 30 in the same pattern, up to 40 codes
+pub const MAX_WINDOW: usize = 1 << 20;
+
+// the bytes, then 256 which DEFLATE uses to end a block and is never used here because every
+// block knows its length, then the match lengths
+pub const LITERAL_LENGTH_SYMBOLS: usize = 286;
+const FIRST_LENGTH_SYMBOL: usize = 257;
+pub const DISTANCE_SYMBOLS: usize = 40;
+
+// both trees keep to DEFLATE's limit on code lengths
+pub const MAX_LZ77_CODE_LENGTH: u8 = 15;
+
+// the shortest length each length symbol stands for, and the extra bits picking one from the range
+pub const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+                                    43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub const LENGTH_EXTRA_BITS: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// a minimum length match further back than this costs more than the literals
+const TOO_FAR: usize = 4096;
+
+const HASH_BITS: u32 = 15;
+// an empty slot in the hash chains
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token
+{
+    Literal(u8),
+    // copy length bytes from distance bytes back
+    Match
+    {
+        length: u16,
+        distance: u32,
+    },
+}
+
+// how hard the match finder looks for matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchOptions
+{
+    // how far back matches can come from, up to MAX_WINDOW
+    pub window: usize,
+    // earlier positions with the same hash tried for each match
+    pub max_chain: usize,
+    // a match this long is taken without looking for a longer one
+    pub nice_length: usize,
+    // whether a match waits to see if the next position has a longer one
+    pub lazy: bool,
+}
+
+pub const DEFAULT_LEVEL: u32 = 6;
+
+impl MatchOptions
+{
+    // the options for a compression level from 1, fastest, to 9, smallest, along the lines of
+    // zlib's levels
+    pub fn level(level: u32) -> Self
+    {
+        let (max_chain, nice_length, lazy) = match level.clamp(1, 9)
+        {
+            1 => (4, 8, false),
+            2 => (8, 16, false),
+            3 => (32, 32, false),
+            4 => (16, 16, true),
+            5 => (32, 32, true),
+            6 => (128, 128, true),
+            7 => (256, 128, true),
+            8 => (1024, MAX_MATCH, true),
+            _ => (4096, MAX_MATCH, true),
+        };
+        Self { window: DEFAULT_WINDOW, max_chain, nice_length, lazy }
+    }
+}
+
+impl Default for MatchOptions
+{
+    fn default() -> Self { Self::level(DEFAULT_LEVEL) }
+}
+
+// Finds matches through chains of earlier positions whose next MIN_MATCH bytes hash the same
+//
+// head has the latest position for each hash, and prev the position before that with the same
+// hash for every position in the window, as a ring indexed by position.
+struct MatchFinder<'a>
+{
+    data: &'a [u8],
+    options: &'a MatchOptions,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    mask: usize,
+}
+
+impl<'a> MatchFinder<'a>
+{
+    fn new(data: &'a [u8], options: &'a MatchOptions) -> Self
+    {
+        let ring = options.window.next_power_of_two();
+        Self { data,
+               options,
+               head: vec![NONE; 1 << HASH_BITS],
+               prev: vec![NONE; ring],
+               mask: ring - 1 }
+    }
+
+    fn hash(&self, position: usize) -> usize
+    {
+        let bytes = &self.data[position..position + MIN_MATCH];
+        let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, position: usize)
+    {
+        if position + MIN_MATCH <= self.data.len()
+        {
+            let hash = self.hash(position);
+            self.prev[position & self.mask] = self.head[hash];
+            self.head[hash] = position as u32;
+        }
+    }
+
+    // the longest match for the bytes at position, as (length, distance), a length of 0 if there
+    // isn't one worth taking
+    fn longest(&self, position: usize) -> (usize, usize)
+    {
+        let max_length = MAX_MATCH.min(self.data.len() - position);
+        if max_length < MIN_MATCH
+        {
+            return (0, 0);
+        }
+
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(position)];
+        let mut chain = self.options.max_chain;
+        while candidate != NONE && chain > 0
+        {
+            let start = candidate as usize;
+            let distance = position - start;
+            if distance > self.options.window
+            {
+                break;
+            }
+
+            // a longer match has to at least match at the end of the best so far
+            if self.data[start + best.0.min(max_length - 1)]
+               == self.data[position + best.0.min(max_length - 1)]
+            {
+                let length = self.data[start..start + max_length].iter()
+                                                                 .zip(&self.data[position..])
+                                                                 .take_while(|(a, b)| a == b)
+                                                                 .count();
+                if length > best.0
+                {
+                    best = (length, distance);
+                    if length >= self.options.nice_length || length == max_length
+                    {
+                        break;
+                    }
+                }
+            }
+
+            // positions in the chain only go back, anything else is a slot the ring reused
+            let next = self.prev[start & self.mask];
+            if next == NONE || next as usize >= start
+            {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+
+        match best
+        {
+            (length, distance) if length == MIN_MATCH && distance > TOO_FAR => (0, 0),
+            (length, _) if length < MIN_MATCH => (0, 0),
+            best => best,
+        }
+    }
+}
+
+// split the data into literals and matches with earlier data, matches never reach before the
+// start of the data
+pub fn find_matches(data: &[u8], options: &MatchOptions) -> Vec<Token>
+{
+    find_matches_from(data, 0, options)
+}
+
+// split the data from start on into literals and matches, which can reach back into the data
+// before start as far as the window goes
+pub fn find_matches_from(data: &[u8], start: usize, options: &MatchOptions) -> Vec<Token>
+{
+    let mut finder = MatchFinder::new(data, options);
+    let mut tokens = Vec::new();
+    // every position before this is in the hash chains
+    let mut inserted = start.saturating_sub(options.window);
+    let mut position = start;
+
+    while position < data.len()
+    {
+        while inserted < position
+        {
+            finder.insert(inserted);
+            inserted += 1;
+        }
+
+        let (length, distance) = finder.longest(position);
+        if length > 0 && options.lazy && length < options.nice_length
+        {
+            finder.insert(position);
+            inserted = position + 1;
+            if finder.longest(position + 1).0 > length
+            {
+                tokens.push(Token::Literal(data[position]));
+                position += 1;
+                continue;
+            }
+        }
+
+        if length > 0
+        {
+            tokens.push(Token::Match { length: length as u16, distance: distance as u32 });
+            position += length;
+        }
+        else
+        {
+            tokens.push(Token::Literal(data[position]));
+            position += 1;
+        }
+    }
+
+    tokens
+}
+
+// the symbol for a match length, with the number of extra bits and their value
+pub fn length_symbol(length: u16) -> (u16, u8, u16)
+{
+    let index = LENGTH_BASE.partition_point(|base| *base <= length) - 1;
+    ((FIRST_LENGTH_SYMBOL + index) as u16, LENGTH_EXTRA_BITS[index], length - LENGTH_BASE[index])
+}
+
+// the symbol for a match distance, with the number of extra bits and their value
+//
+// the first four distances have a symbol each, after that each pair of symbols covers twice the
+// distances of the pair before with one more extra bit
+pub fn distance_symbol(distance: u32) -> (u16, u8, u32)
+{
+    if distance <= 4
+    {
+        return (distance as u16 - 1, 0, 0);
+    }
+
+    let offset = distance - 1;
+    let top = 31 - offset.leading_zeros();
+    let extra_bits = top - 1;
+    ((2 * top + (offset >> extra_bits & 1)) as u16,
+     extra_bits as u8,
+     offset & ((1 << extra_bits) - 1))
+}
+
+// the shortest distance a distance symbol stands for, and its number of extra bits
+pub fn distance_base(symbol: u16) -> (u32, u8)
+{
+    if symbol < 4
+    {
+        return (symbol as u32 + 1, 0);
+    }
+
+    let extra_bits = symbol as u32 / 2 - 1;
+    (((2 + (symbol as u32 & 1)) << extra_bits) + 1, extra_bits as u8)
+}
+
+// the tokens for a block and how often each symbol comes up in them
+pub struct Lz77Counts
+{
+    pub tokens: Vec<Token>,
+    literal_lengths: FrequencyTable,
+    distances: FrequencyTable,
+    // taken by the lengths and distances on top of their symbols
+    extra_bits: usize,
+}
+
+impl Lz77Counts
+{
+    pub fn new(block: &[u8], options: &MatchOptions) -> Self
+    {
+        let tokens = find_matches(block, options);
+        let mut literal_lengths = FrequencyTable::with_alphabet(LITERAL_LENGTH_SYMBOLS);
+        let mut distances = FrequencyTable::with_alphabet(DISTANCE_SYMBOLS);
+        let mut extra_bits = 0;
+
+        for token in &tokens
+        {
+            match *token
+            {
+                Token::Literal(byte) => literal_lengths.add(byte as u16),
+                Token::Match { length, distance } =>
+                {
+                    let (length_symbol, length_bits, _) = length_symbol(length);
+                    let (distance_symbol, distance_bits, _) = distance_symbol(distance);
+                    literal_lengths.add(length_symbol);
+                    distances.add(distance_symbol);
+                    extra_bits += (length_bits + distance_bits) as usize;
+                }
+            }
+        }
+
+        Self { tokens, literal_lengths, distances, extra_bits }
+    }
+}
+
+// Code lengths for the literal and length symbols and for the distance symbols, DEFLATE style
+//
+// written out as the literal and length code lengths and then the distance code lengths, which are
+// all 0 when there are no matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lz77Tables
+{
+    literal_lengths: Vec<u8>,
+    distances: Vec<u8>,
+}
+
+impl Lz77Tables
+{
+    pub fn new(counts: &Lz77Counts) -> Self
+    {
+        let lengths = |frequencies: &FrequencyTable| {
+            HuffmanTree::with_max_length(frequencies, MAX_LZ77_CODE_LENGTH)
+                .map_or_else(|| vec![0; frequencies.alphabet_size()], |tree| tree.code_lengths())
+        };
+        Self { literal_lengths: lengths(&counts.literal_lengths),
+               distances: lengths(&counts.distances) }
+    }
+
+    // number of bits write will use
+    pub fn size_bits(&self) -> usize
+    {
+        code_lengths_size_bits(&self.literal_lengths) + code_lengths_size_bits(&self.distances)
+    }
+
+    // the bits taken by the tokens counted, None if a symbol has no code
+    pub fn coded_bits(&self, counts: &Lz77Counts) -> Option<usize>
+    {
+        Some(coded_bits(&counts.literal_lengths, &self.literal_lengths)?
+             + coded_bits(&counts.distances, &self.distances)?
+             + counts.extra_bits)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        write_code_lengths(writer, &self.literal_lengths)?;
+        write_code_lengths(writer, &self.distances)
+    }
+
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> Result<Self, Error>
+    {
+        Ok(Self { literal_lengths: read_alphabet_code_lengths(reader, LITERAL_LENGTH_SYMBOLS)?,
+                  distances: read_alphabet_code_lengths(reader, DISTANCE_SYMBOLS)? })
+    }
+
+    // write the code for every token, lengths and distances followed by their extra bits
+    pub fn encode<W: Write>(&self, writer: &mut BitWriter<W>, tokens: &[Token])
+                            -> Result<(), Error>
+    {
+        let literal_lengths = canonical_codes(&self.literal_lengths);
+        let distances = canonical_codes(&self.distances);
+        let code = |codes: &[Option<_>], symbol: u16| {
+            codes[symbol as usize].ok_or_else(|| {
+                                      Error::new(ErrorKind::InvalidInput,
+                                                 format!("symbol {} has no code", symbol))
+                                  })
+        };
+
+        for token in tokens
+        {
+            match *token
+            {
+                Token::Literal(byte) => write_code(writer, &code(&literal_lengths, byte as u16)?)?,
+                Token::Match { length, distance } =>
+                {
+                    let (symbol, bits, extra) = length_symbol(length);
+                    write_code(writer, &code(&literal_lengths, symbol)?)?;
+                    writer.write_bits(extra as u64, bits as u32)?;
+
+                    let (symbol, bits, extra) = distance_symbol(distance);
+                    write_code(writer, &code(&distances, symbol)?)?;
+                    writer.write_bits(extra as u64, bits as u32)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Decodes what Lz77Tables::encode wrote a byte at a time, keeping what's been decoded of the block
+// for matches to copy from
+pub struct Lz77Decoder
+{
+    literal_lengths: SymbolDecoder,
+    // None when the block has no matches
+    distances: Option<SymbolDecoder>,
+    history: Vec<u8>,
+    // the match being copied
+    copy_distance: usize,
+    copy_remaining: usize,
+}
+
+impl Lz77Decoder
+{
+    pub fn new(tables: &Lz77Tables) -> Result<Self, Error>
+    {
+        let distances = match tables.distances.iter().all(|len| *len == 0)
+        {
+            true => None,
+            false => Some(SymbolDecoder::from_lengths(&tables.distances)?),
+        };
+        Ok(Self { literal_lengths: SymbolDecoder::from_lengths(&tables.literal_lengths)?,
+                  distances,
+                  history: Vec::new(),
+                  copy_distance: 0,
+                  copy_remaining: 0 })
+    }
+
+    // matches never reach back into an earlier block
+    pub fn reset(&mut self)
+    {
+        self.history.clear();
+        self.copy_remaining = 0;
+    }
+
+    pub fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<u8, Error>
+    {
+        if self.copy_remaining == 0
+        {
+            let symbol = self.literal_lengths.decode(reader)? as usize;
+            if symbol < 256
+            {
+                self.history.push(symbol as u8);
+                return Ok(symbol as u8);
+            }
+            self.start_match(reader, symbol)?;
+        }
+
+        let byte = self.history[self.history.len() - self.copy_distance];
+        self.history.push(byte);
+        self.copy_remaining -= 1;
+
+        // only the window is needed, keep at least that much and drop the rest now and again
+        if self.history.len() >= 2 * MAX_WINDOW
+        {
+            self.history.drain(..self.history.len() - MAX_WINDOW);
+        }
+        Ok(byte)
+    }
+
+    fn start_match<R: Read>(&mut self,
+                            reader: &mut BitReader<R>,
+                            symbol: usize)
+                            -> Result<(), Error>
+    {
+        let invalid = |message: String| Err(Error::new(ErrorKind::InvalidData, message));
+
+        let Some(index) = symbol.checked_sub(FIRST_LENGTH_SYMBOL)
+                                .filter(|index| *index < LENGTH_BASE.len())
+        else
+        {
+            return invalid(format!("{} isn't a literal or a length", symbol));
+        };
+        let length = LENGTH_BASE[index] as usize
+                     + read_value(reader, LENGTH_EXTRA_BITS[index] as u32)? as usize;
+
+        let Some(distances) = &self.distances
+        else
+        {
+            return invalid("a match in a block without distance codes".to_string());
+        };
+        let (base, bits) = distance_base(distances.decode(reader)?);
+        let distance = base as usize + read_value(reader, bits as u32)? as usize;
+        if distance > self.history.len()
+        {
+            return invalid(format!("a match {} bytes back with only {} decoded",
+                                   distance,
+                                   self.history.len()));
+        }
+
+        self.copy_distance = distance;
+        self.copy_remaining = length;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // rebuild the data from the tokens
+    fn expand(tokens: &[Token]) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        for token in tokens
+        {
+            match *token
+            {
+                Token::Literal(byte) => data.push(byte),
+                Token::Match { length, distance } =>
+                {
+                    for _ in 0..length
+                    {
+                        data.push(data[data.len() - distance as usize]);
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_symbols()
+    {
+        assert_eq!(length_symbol(3), (257, 0, 0));
+        assert_eq!(length_symbol(12), (265, 1, 1));
+        assert_eq!(length_symbol(257), (284, 5, 30));
+        assert_eq!(length_symbol(258), (285, 0, 0));
+
+        assert_eq!(distance_symbol(1), (0, 0, 0));
+        assert_eq!(distance_symbol(5), (4, 1, 0));
+        assert_eq!(distance_symbol(7), (5, 1, 0));
+        assert_eq!(distance_symbol(32768), (29, 13, 8191));
+        assert_eq!(distance_symbol(MAX_WINDOW as u32), (39, 18, (1 << 18) - 1));
+
+        // every distance comes back from its symbol
+        for distance in (1..=MAX_WINDOW as u32).step_by(97).chain([2, 3, 4, 24577])
+        {
+            let (symbol, bits, extra) = distance_symbol(distance);
+            assert!((symbol as usize) < DISTANCE_SYMBOLS);
+            assert_eq!(distance_base(symbol), (distance - extra, bits));
+            assert!(extra < 1 << bits);
+        }
+    }
+
+    #[test]
+    fn test_find_matches()
+    {
+        let tokens = find_matches(b"abcabcabcabcx", &MatchOptions::default());
+        assert_eq!(tokens,
+                   [Token::Literal(b'a'),
+                    Token::Literal(b'b'),
+                    Token::Literal(b'c'),
+                    Token::Match { length: 9, distance: 3 },
+                    Token::Literal(b'x')]);
+    }
+
+    #[test]
+    fn test_levels()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut sizes = Vec::new();
+        for level in [1, 6, 9]
+        {
+            let counts = Lz77Counts::new(&text, &MatchOptions::level(level));
+            assert_eq!(expand(&counts.tokens), text);
+            let tables = Lz77Tables::new(&counts);
+            sizes.push(tables.size_bits() + tables.coded_bits(&counts).unwrap());
+        }
+        assert!(sizes[0] > sizes[1] && sizes[1] >= sizes[2]);
+        // about where gzip gets to
+        assert!(sizes[2] / 8 < 50_000);
+    }
+
+    #[test]
+    fn test_window()
+    {
+        // the repeat is further back than a small window reaches
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut block = (0..5000).map(|_| {
+                                     state ^= state << 13;
+                                     state ^= state >> 7;
+                                     state ^= state << 17;
+                                     state as u8
+                                 })
+                                 .collect::<Vec<_>>();
+        block.extend_from_within(..1000);
+
+        let small = MatchOptions { window: 1024, ..Default::default() };
+        let tokens = find_matches(&block, &small);
+        assert!(tokens.iter().all(|token| match token
+                             {
+                                 Token::Match { distance, .. } => *distance <= 1024,
+                                 Token::Literal(_) => true,
+                             }));
+        assert_eq!(expand(&tokens), block);
+
+        let tokens = find_matches(&block, &MatchOptions::default());
+        assert!(tokens.contains(&Token::Match { length: 258, distance: 5000 }));
+        assert_eq!(expand(&tokens), block);
+    }
+
+    #[test]
+    fn test_find_matches_from()
+    {
+        // the second half matches the first, which comes before where matching starts
+        let tokens = find_matches_from(b"abcdefgh abcdefgh", 9, &MatchOptions::default());
+        assert_eq!(tokens, [Token::Match { length: 8, distance: 9 }]);
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let counts = Lz77Counts::new(&text, &MatchOptions::default());
+        let tables = Lz77Tables::new(&counts);
+
+        let mut writer = BitWriter::new(Vec::new());
+        tables.write(&mut writer).unwrap();
+        tables.encode(&mut writer, &counts.tokens).unwrap();
+        assert_eq!(writer.bits_written() as usize,
+                   tables.size_bits() + tables.coded_bits(&counts).unwrap());
+        let encoded = writer.into_inner().unwrap();
+
+        let mut reader = BitReader::new(&encoded[..]);
+        let read = Lz77Tables::read(&mut reader).unwrap();
+        assert_eq!(read, tables);
+        let mut decoder = Lz77Decoder::new(&read).unwrap();
+        for byte in &text
+        {
+            assert_eq!(decoder.decode(&mut reader).unwrap(), *byte);
+        }
+    }
+
+    #[test]
+    fn test_no_matches()
+    {
+        let counts = Lz77Counts::new(b"abcdefg", &MatchOptions::default());
+        let tables = Lz77Tables::new(&counts);
+        assert!(tables.distances.iter().all(|len| *len == 0));
+        let decoder = Lz77Decoder::new(&tables).unwrap();
+        assert!(decoder.distances.is_none());
+    }
+
+    #[test]
+    fn test_distance_too_far()
+    {
+        // a match straight away has nothing to copy from
+        let counts = Lz77Counts::new(b"aaaaaaaa", &MatchOptions::default());
+        let tables = Lz77Tables::new(&counts);
+        let mut writer = BitWriter::new(Vec::new());
+        tables.encode(&mut writer, &[Token::Match { length: 7, distance: 1 }])
+              .unwrap();
+        let encoded = writer.into_inner().unwrap();
+
+        let mut decoder = Lz77Decoder::new(&tables).unwrap();
+        let error = decoder.decode(&mut BitReader::new(&encoded[..]))
+                           .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,62 +1,1137 @@
 use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use std::time::Instant;
 
-use compress::compress;
-use uncompress::uncompress;
+use huffman::analyze::analyze_file;
+use huffman::archive::create_archive;
+use huffman::archive::extract_archive;
+use huffman::archive::list_archive;
+use huffman::archive::test_archive;
+use huffman::archive::ArchiveEntry;
+use huffman::archive::EntryKind;
+use huffman::compress::compress_adaptive;
+use huffman::compress::compress_with;
+use huffman::compress::CompressOptions;
+use huffman::compress::HuffmanEncoder;
+use huffman::container::Codec;
+use huffman::file_lib::bit::BitOrder;
+use huffman::file_lib::bit_reader::BitReader;
+use huffman::file_lib::bit_writer::BitWriter;
+use huffman::gzip::gunzip;
+use huffman::gzip::gzip;
+use huffman::gzip::is_gzip;
+use huffman::gzip::read_gzip;
+use huffman::gzip::write_gzip;
+use huffman::gzip::GZIP_MAGIC;
+use huffman::lz77::MatchOptions;
+use huffman::transform::Transforms;
+use huffman::uncompress::copy_range;
+use huffman::uncompress::uncompress;
+use huffman::uncompress::HuffmanDecoder;
 
-mod compress;
-mod file_lib;
-mod huffman_tree;
-mod uncompress;
+// added to the input's name to name the output, and taken off again when uncompressing
+const SUFFIX: &str = ".compressed";
+const GZIP_SUFFIX: &str = ".gz";
+// for uncompressing a file that has neither
+const UNCOMPRESSED_SUFFIX: &str = ".uncompressed";
+// stdin as an input, or stdout as the output
+const STDIO: &str = "-";
 
-fn usage(args: &[String])
+// each kind of failure exits with its own code, when several files fail it's the first one's
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Failure
 {
-    println!("{} MODE IN OUT", args[0]);
-    println!("Huffman compress or uncompress a file");
-    println!("MODE is either:");
-    println!("  -c: compress");
-    println!("  -u: uncompress");
-    println!("IN is the input file, it must exist and be a file");
-    println!("OUT is the output file, it must NOT already exist, no overwrite functionality");
+    // the arguments don't make sense
+    Usage = 1,
+    // an input is missing or can't be read
+    Input = 2,
+    // the output already exists and --force wasn't given
+    OutputExists = 3,
+    // an input to uncompress isn't valid compressed data
+    Corrupt = 4,
+    // anything else that went wrong reading or writing
+    Io = 5,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>>
+#[derive(Debug, Clone, PartialEq)]
+enum Mode
 {
-    let args = env::args().collect::<Vec<String>>();
+    Compress(CompressOptions),
+    Adaptive,
+    Gzip(MatchOptions),
+    Uncompress,
+    // print the code table and entropy of each input instead of writing an output
+    Analyze,
+}
+
+// how --analyze prints each analysis
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AnalysisFormat
+{
+    Table,
+    Json,
+    // the huffman tree as graphviz dot
+    Dot,
+}
+
+// what to do with an archive instead of compressing files one at a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveAction
+{
+    // pack the files into the archive given by --output
+    Create,
+    List,
+    // unpack the first file, the rest of the files are the entries to unpack
+    Extract,
+    Test,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Args
+{
+    mode: Mode,
+    // STDIO reads stdin, which is also what no files at all does
+    files: Vec<String>,
+    // where a single input goes instead of a name made from its own
+    output: Option<PathBuf>,
+    // everything goes to stdout, the way it does for stdin
+    stdout: bool,
+    force: bool,
+    // remove each input file once its output is written
+    remove: bool,
+    verbose: bool,
+    // uncompress only these bytes
+    range: Option<Range<u64>>,
+    // None leaves analyses as tables, for checking --json and --dot are only given with --analyze
+    format: Option<AnalysisFormat>,
+    archive: Option<ArchiveAction>,
+}
+
+fn usage(program: &str)
+{
+    println!("Usage: {} [MODE] [OPTIONS] [FILE]...", program);
+    println!("Huffman compress or uncompress files, or stdin to stdout when FILE is - or missing");
+    println!("MODE is one of:");
+    println!("  -c: compress, the default");
+    println!("  -a: compress in a single pass with an adaptive code");
+    println!("  -r: compress with a range coder instead of huffman codes");
+    println!("  -b: compress after a BWT, move to front and zero run length encoding");
+    println!("  -1 to -9: compress with LZ77 matches, -1 is fastest and -9 smallest");
+    println!("  -z: compress to a gzip file");
+    println!("  -u, -d: uncompress, gzip files included");
+    println!("  --analyze: print the byte counts, codes, entropy and header overhead of each file,");
+    println!("    compressed files are uncompressed first");
+    println!("OPTIONS are:");
+    println!("  -o, --output OUT: write to OUT, only for a single input");
+    println!("  --range START-END: uncompress only the bytes from START up to END, or to the");
+    println!("    end for START-, to -o OUT or --stdout, only the blocks the range covers are");
+    println!("    decoded");
+    println!("  --stdout: write everything to stdout");
+    println!("  -f, --force: overwrite outputs that already exist");
+    println!("  -k, --keep: keep the input files, the default");
+    println!("  --rm: remove each input file once its output is written");
+    println!("  -v, --verbose: print the sizes, ratio and throughput for each file to stderr");
+    println!("  --json: print each analysis as a line of json");
+    println!("  --dot: print the huffman tree of each analysis as a graphviz digraph");
+    println!("  -h, --help: print this and exit");
+    println!("ARCHIVES:");
+    println!("  --archive -o OUT FILE...: pack the files and directories into the archive OUT,");
+    println!("    compressed as MODE says");
+    println!("  -l, --list ARCHIVE...: list what's in the archives");
+    println!("  -x, --extract ARCHIVE [ENTRY]...: unpack everything or just the entries given,");
+    println!("    under the current directory or -o DIR");
+    println!("  -t, --test ARCHIVE...: check every file in the archives can be unpacked");
+    println!("The output is named after the input with {} added, or {} for gzip files,",
+             SUFFIX, GZIP_SUFFIX);
+    println!("uncompressing takes the suffix back off, or adds {} if there isn't one",
+             UNCOMPRESSED_SUFFIX);
+    println!("Exits with 0 on success, {} for bad arguments, {} for a missing or unreadable input,",
+             Failure::Usage as u8,
+             Failure::Input as u8);
+    println!("{} for an output that already exists, {} for an input that isn't valid compressed",
+             Failure::OutputExists as u8,
+             Failure::Corrupt as u8);
+    println!("data and {} for any other error reading or writing",
+             Failure::Io as u8);
+}
+
+// None when the help was asked for
+fn parse_args(args: &[String]) -> Result<Option<Args>, String>
+{
+    let mut parsed = Args { mode: Mode::Compress(CompressOptions::default()),
+                            files: Vec::new(),
+                            output: None,
+                            stdout: false,
+                            force: false,
+                            remove: false,
+                            verbose: false,
+                            range: None,
+                            format: None,
+                            archive: None };
+    let mut options_done = false;
 
-    // should be program + 3 args
-    if args.len() != 4
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
     {
-        usage(&args);
-        Ok(())
+        if options_done || !arg.starts_with('-') || arg == STDIO
+        {
+            parsed.files.push(arg.clone());
+            continue;
+        }
+
+        let level = arg.strip_prefix('-')
+                       .and_then(|level| level.parse::<u32>().ok())
+                       .filter(|level| (1..=9).contains(level));
+        match arg.as_str()
+        {
+            "--" => options_done = true,
+            "-h" | "--help" => return Ok(None),
+            "-c" => parsed.mode = Mode::Compress(CompressOptions::default()),
+            "-a" => parsed.mode = Mode::Adaptive,
+            "-r" =>
+            {
+                parsed.mode =
+                    Mode::Compress(CompressOptions { codec: Codec::Range, ..Default::default() })
+            }
+            "-b" =>
+            {
+                parsed.mode = Mode::Compress(CompressOptions { transforms: Transforms::all(),
+                                                               ..Default::default() })
+            }
+            "-z" => parsed.mode = Mode::Gzip(MatchOptions::default()),
+            "-u" | "-d" => parsed.mode = Mode::Uncompress,
+            "--analyze" => parsed.mode = Mode::Analyze,
+            "-o" | "--output" =>
+            {
+                let output = args.next()
+                                 .ok_or_else(|| format!("{} needs a file name", arg))?;
+                parsed.output = Some(PathBuf::from(output));
+            }
+            "--range" =>
+            {
+                let range = args.next()
+                                .ok_or_else(|| format!("{} needs a range", arg))?;
+                parsed.range = Some(parse_range(range)?);
+            }
+            "--stdout" => parsed.stdout = true,
+            "-f" | "--force" => parsed.force = true,
+            "-k" | "--keep" => parsed.remove = false,
+            "--rm" => parsed.remove = true,
+            "-v" | "--verbose" => parsed.verbose = true,
+            "--json" => parsed.format = Some(AnalysisFormat::Json),
+            "--dot" => parsed.format = Some(AnalysisFormat::Dot),
+            "--archive" => parsed.archive = Some(ArchiveAction::Create),
+            "-l" | "--list" => parsed.archive = Some(ArchiveAction::List),
+            "-x" | "--extract" => parsed.archive = Some(ArchiveAction::Extract),
+            "-t" | "--test" => parsed.archive = Some(ArchiveAction::Test),
+            _ => match level
+            {
+                Some(level) => parsed.mode = lz77_mode(level),
+                None => return Err(format!("unknown option {}", arg)),
+            },
+        }
+    }
+
+    match parsed.archive
+    {
+        None => check_file_args(&mut parsed)?,
+        Some(action) => check_archive_args(&parsed, action)?,
+    }
+    Ok(Some(parsed))
+}
+
+fn check_file_args(parsed: &mut Args) -> Result<(), String>
+{
+    if parsed.files.is_empty()
+    {
+        parsed.files.push(STDIO.to_string());
+    }
+    if parsed.output.is_some() && (parsed.files.len() > 1 || parsed.stdout)
+    {
+        return Err("--output only goes with a single input and without --stdout".to_string());
+    }
+    if parsed.range.is_some()
+    {
+        // the range is found by seeking in the input
+        if parsed.mode != Mode::Uncompress || parsed.files.iter().any(|file| file == STDIO)
+        {
+            return Err("--range only goes with -u and files, not stdin".to_string());
+        }
+        // only part of the input comes out, so it can't be named or replace the input as though it
+        // were all of it
+        if parsed.output.is_none() && !parsed.stdout
+        {
+            return Err("--range needs -o or --stdout".to_string());
+        }
+        if parsed.remove
+        {
+            return Err("--rm doesn't go with --range".to_string());
+        }
+    }
+    if parsed.mode == Mode::Analyze
+    {
+        // the analysis goes to stdout, and the input is read twice when it's compressed
+        if parsed.files.iter().any(|file| file == STDIO)
+        {
+            return Err("--analyze needs files, not stdin".to_string());
+        }
+        if parsed.output.is_some() || parsed.remove
+        {
+            return Err("--output and --rm don't go with --analyze".to_string());
+        }
+    }
+    else if parsed.format.is_some()
+    {
+        return Err("--json and --dot only go with --analyze".to_string());
+    }
+    Ok(())
+}
+
+// archives are read from and written to files, which can be gone back over
+fn check_archive_args(parsed: &Args, action: ArchiveAction) -> Result<(), String>
+{
+    if parsed.files.is_empty() || parsed.files.iter().any(|file| file == STDIO)
+    {
+        return Err("archives need files, not stdin or stdout".to_string());
+    }
+    if parsed.stdout || parsed.remove
+    {
+        return Err("--stdout and --rm don't go with archives".to_string());
+    }
+    if parsed.mode == Mode::Analyze || parsed.format.is_some()
+    {
+        return Err("--analyze, --json and --dot don't go with archives".to_string());
+    }
+    match action
+    {
+        ArchiveAction::Create if parsed.output.is_none() =>
+        {
+            Err("--archive needs --output for the archive".to_string())
+        }
+        ArchiveAction::Create if !matches!(parsed.mode, Mode::Compress(_)) =>
+        {
+            Err("archives are compressed with -c, -r, -b or -1 to -9".to_string())
+        }
+        ArchiveAction::List | ArchiveAction::Test if parsed.output.is_some() =>
+        {
+            Err("--output doesn't go with --list or --test".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+// START-END, or START- for everything from START on, END isn't included
+fn parse_range(range: &str) -> Result<Range<u64>, String>
+{
+    let invalid = || format!("{} isn't a range like 100-200 or 100-", range);
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start = start.parse::<u64>().map_err(|_| invalid())?;
+    let end = match end
+    {
+        "" => u64::MAX,
+        end => end.parse::<u64>().map_err(|_| invalid())?,
+    };
+    if end < start
+    {
+        return Err(invalid());
+    }
+    Ok(start..end)
+}
+
+fn lz77_mode(level: u32) -> Mode
+{
+    Mode::Compress(CompressOptions { codec: Codec::Lz77,
+                                     matching: MatchOptions::level(level),
+                                     ..Default::default() })
+}
+
+// the output for an input file when it isn't given
+fn output_name(mode: &Mode, input: &Path) -> PathBuf
+{
+    let suffix = match mode
+    {
+        Mode::Uncompress =>
+        {
+            let stripped =
+                input.to_str().and_then(|name| {
+                                  [SUFFIX, GZIP_SUFFIX].iter()
+                                                       .find_map(|suffix| name.strip_suffix(suffix))
+                                                       .filter(|name| !name.is_empty())
+                              });
+            if let Some(stripped) = stripped
+            {
+                return PathBuf::from(stripped);
+            }
+            UNCOMPRESSED_SUFFIX
+        }
+        Mode::Gzip(_) => GZIP_SUFFIX,
+        _ => SUFFIX,
+    };
+    let mut name = input.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// compress or uncompress a single input as the arguments say
+fn run(args: &Args, file: &str) -> Result<(), (Failure, Error)>
+{
+    if let Some(action) = args.archive
+    {
+        return run_archive(args, action, file);
+    }
+    if args.mode == Mode::Analyze
+    {
+        return run_analyze(args, Path::new(file));
+    }
+
+    let start = Instant::now();
+    let input = (file != STDIO).then(|| Path::new(file));
+    if let Some(input) = input
+    {
+        check_input(input)?;
+    }
+
+    let output = match (&args.output, input)
+    {
+        (Some(output), _) if output.as_os_str() == STDIO => None,
+        (Some(output), _) => Some(output.clone()),
+        (None, Some(input)) if !args.stdout => Some(output_name(&args.mode, input)),
+        _ => None,
+    };
+    if let Some(output) = &output
+    {
+        if input.is_some_and(|input| same_file(input, output))
+        {
+            return Err((Failure::Usage,
+                        Error::new(ErrorKind::InvalidInput, "the output would be the input")));
+        }
+        check_output(args, output)?;
+    }
+
+    let result = match (input, &output, &args.range)
+    {
+        (Some(input), Some(output), Some(range)) =>
+        {
+            replace_output(output, |temp| run_range(input, Some(temp), range.clone()))
+        }
+        (Some(input), None, Some(range)) => run_range(input, None, range.clone()),
+        (Some(input), Some(output), None) =>
+        {
+            replace_output(output, |temp| run_files(&args.mode, input, temp))
+        }
+        (_, Some(output), _) =>
+        {
+            replace_output(output, |temp| run_streams(&args.mode, input, Some(temp)))
+        }
+        (_, None, _) => run_streams(&args.mode, input, None),
+    };
+    let (read, written) = result.map_err(|error| (failure(&error), error))?;
+
+    if args.remove && output.is_some()
+    {
+        if let Some(input) = input
+        {
+            fs::remove_file(input).map_err(|error| (Failure::Io, error))?;
+        }
+    }
+    if args.verbose
+    {
+        report(file, &args.mode, read, written, start.elapsed());
+    }
+    Ok(())
+}
+
+// print what the input looks like to the coder
+fn run_analyze(args: &Args, input: &Path) -> Result<(), (Failure, Error)>
+{
+    check_input(input)?;
+    let analysis = analyze_file(input).map_err(|error| (failure(&error), error))?;
+    match args.format.unwrap_or(AnalysisFormat::Table)
+    {
+        AnalysisFormat::Table =>
+        {
+            println!("{}:", input.display());
+            print!("{}", analysis);
+        }
+        AnalysisFormat::Json => println!("{}", analysis.to_json()),
+        AnalysisFormat::Dot => print!("{}", analysis.to_dot()),
+    }
+    Ok(())
+}
+
+// the input has to be a file that can be opened
+fn check_input(input: &Path) -> Result<(), (Failure, Error)>
+{
+    let input_error = |error| (Failure::Input, error);
+    if !fs::metadata(input).map_err(input_error)?.is_file()
+    {
+        return Err(input_error(Error::new(ErrorKind::InvalidInput, "not a regular file")));
+    }
+    File::open(input).map_err(input_error)?;
+    Ok(())
+}
+
+// the output can't already be there, unless --force says to replace it, which replace_output
+// does once the new one is complete
+fn check_output(args: &Args, output: &Path) -> Result<(), (Failure, Error)>
+{
+    if output.exists() && !args.force
+    {
+        return Err((Failure::OutputExists,
+                    Error::new(ErrorKind::AlreadyExists,
+                               format!("{} already exists, --force overwrites it",
+                                       output.display()))));
+    }
+    Ok(())
+}
+
+// whether two paths are the same file, however they're spelled and whatever links lead there
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool
+{
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b))
+    {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool
+{
+    match (fs::canonicalize(a), fs::canonicalize(b))
+    {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// write the output under a temporary name next to it, and only rename it over the output once
+// it's complete, so a failure leaves whatever was there before
+fn replace_output<T>(output: &Path,
+                     write: impl FnOnce(&Path) -> Result<T, Error>)
+                     -> Result<T, Error>
+{
+    let mut name = OsString::from(".");
+    name.push(output.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    let temp = output.with_file_name(name);
+
+    let result = write(&temp).and_then(|written| {
+                                 fs::rename(&temp, output)?;
+                                 Ok(written)
+                             });
+    if result.is_err()
+    {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// create the archive, or list, extract or test the archive in file
+fn run_archive(args: &Args, action: ArchiveAction, file: &str) -> Result<(), (Failure, Error)>
+{
+    let start = Instant::now();
+    let failed = |error: Error| (failure(&error), error);
+    match action
+    {
+        ArchiveAction::Create =>
+        {
+            let output = args.output
+                             .as_deref()
+                             .expect("checked by check_archive_args");
+            let inputs = args.files.iter().map(PathBuf::from).collect::<Vec<_>>();
+            for input in &inputs
+            {
+                fs::symlink_metadata(input).map_err(|error| (Failure::Input, error))?;
+                if same_file(input, output)
+                {
+                    return Err((Failure::Usage,
+                                Error::new(ErrorKind::InvalidInput,
+                                           "the archive would be one of its inputs")));
+                }
+            }
+            check_output(args, output)?;
+            let options = match &args.mode
+            {
+                Mode::Compress(options) => options,
+                _ => unreachable!("checked by check_archive_args"),
+            };
+            let entries = replace_output(output, |temp| create_archive(temp, &inputs, options))
+                .map_err(failed)?;
+            if args.verbose
+            {
+                let written = fs::metadata(output).map_err(failed)?.len();
+                let read = entries.iter().map(|entry| entry.size).sum();
+                report(file, &args.mode, read, written, start.elapsed());
+            }
+        }
+        ArchiveAction::List =>
+        {
+            check_input(Path::new(file))?;
+            list(&list_archive(Path::new(file)).map_err(failed)?);
+        }
+        ArchiveAction::Extract =>
+        {
+            check_input(Path::new(file))?;
+            let dest = args.output.as_deref().unwrap_or(Path::new("."));
+            let entries = extract_archive(Path::new(file), dest, &args.files[1..], args.force)
+                .map_err(failed)?;
+            if args.verbose
+            {
+                entries.iter().for_each(|entry| eprintln!("{}", entry.path));
+            }
+        }
+        ArchiveAction::Test =>
+        {
+            check_input(Path::new(file))?;
+            let entries = test_archive(Path::new(file)).map_err(failed)?;
+            if args.verbose
+            {
+                eprintln!("{}: {} entries OK", file, entries.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+// one line per entry, the way ls -l does it
+fn list(entries: &[ArchiveEntry])
+{
+    println!("{:<10} {:>10} {:>10} {:<16} path",
+             "mode", "size", "compressed", "modified");
+    for entry in entries
+    {
+        println!("{:<10} {:>10} {:>10} {:<16} {}",
+                 permissions(entry.kind, entry.mode),
+                 entry.size,
+                 entry.compressed_len,
+                 format_time(entry.mtime),
+                 entry.path);
+    }
+    println!("{} entries, {} bytes, {} compressed",
+             entries.len(),
+             entries.iter().map(|entry| entry.size).sum::<u64>(),
+             entries.iter()
+                    .map(|entry| entry.compressed_len)
+                    .sum::<u64>());
+}
+
+fn permissions(kind: EntryKind, mode: u32) -> String
+{
+    let mut permissions = String::from(match kind
+                                       {
+                                           EntryKind::Directory => 'd',
+                                           EntryKind::File => '-',
+                                       });
+    for shift in [6, 3, 0]
+    {
+        for (bit, letter) in [(4, 'r'), (2, 'w'), (1, 'x')]
+        {
+            permissions.push(if mode >> shift & bit != 0
+                             {
+                                 letter
+                             }
+                             else
+                             {
+                                 '-'
+                             });
+        }
+    }
+    permissions
+}
+
+// the UTC date and time for seconds since the unix epoch
+fn format_time(seconds: i64) -> String
+{
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+
+    // days to a date, from Howard Hinnant's civil_from_days, with years starting in March so the
+    // leap day comes last
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10
+    {
+        month_from_march + 3
     }
     else
     {
-        let mode = &args[1];
-        let in_file = Path::new(&args[2]);
-        let out_file = Path::new(&args[3]);
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60)
+}
+
+// what the error says about the input, errors from the files themselves are sorted out before
+fn failure(error: &Error) -> Failure
+{
+    match error.kind()
+    {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Failure::Corrupt,
+        ErrorKind::AlreadyExists => Failure::OutputExists,
+        ErrorKind::NotFound => Failure::Input,
+        _ => Failure::Io,
+    }
+}
+
+// file to file, which can read the input twice rather than holding on to it, returns the bytes
+// read and written
+fn run_files(mode: &Mode, input: &Path, output: &Path) -> Result<(u64, u64), Error>
+{
+    match mode
+    {
+        Mode::Compress(options) => compress_with(input, output, options)?,
+        Mode::Adaptive => compress_adaptive(input, output)?,
+        Mode::Gzip(matching) => gzip(input, output, matching)?,
+        Mode::Uncompress if is_gzip(input)? => gunzip(input, output)?,
+        Mode::Uncompress => uncompress(input, output)?,
+        Mode::Analyze => unreachable!("analyzed by run_analyze"),
+    }
+    Ok((fs::metadata(input)?.len(), fs::metadata(output)?.len()))
+}
+
+// uncompress part of a file, to stdout when output is None
+fn run_range(input: &Path, output: Option<&Path>, range: Range<u64>) -> Result<(u64, u64), Error>
+{
+    let mut reader = BufReader::new(File::open(input)?);
+    if is_gzip(input)?
+    {
+        return Err(Error::new(ErrorKind::InvalidInput,
+                              "--range doesn't work on gzip files"));
+    }
+    HuffmanDecoder::new(&mut reader)?;
+
+    let writer: Box<dyn Write> = match output
+    {
+        Some(output) => Box::new(File::create_new(output)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = BufWriter::new(writer);
+    let written = copy_range(&mut reader, range, &mut writer)?;
+    writer.flush()?;
+    Ok((fs::metadata(input)?.len(), written))
+}
+
+// anything to or from stdin or stdout, None for either end is the standard stream
+fn run_streams(mode: &Mode,
+               input: Option<&Path>,
+               output: Option<&Path>)
+               -> Result<(u64, u64), Error>
+{
+    let reader: Box<dyn Read> = match input
+    {
+        Some(input) => Box::new(File::open(input)?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let writer: Box<dyn Write> = match output
+    {
+        Some(output) => Box::new(File::create_new(output)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut reader = BufReader::new(Counted::new(reader));
+    let mut writer = Counted::new(BufWriter::new(writer));
+
+    match mode
+    {
+        Mode::Compress(options) =>
+        {
+            let mut encoder = HuffmanEncoder::with_options(&mut writer, options)?;
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?;
+        }
+        Mode::Adaptive =>
+        {
+            let mut encoder = HuffmanEncoder::adaptive(&mut writer)?;
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?;
+        }
+        Mode::Gzip(matching) =>
+        {
+            let mut bits = BitWriter::with_order(&mut writer, BitOrder::LsbFirst);
+            write_gzip(&mut reader, &mut bits, matching)?;
+            bits.flush()?;
+        }
+        Mode::Uncompress if reader.fill_buf()?.starts_with(&GZIP_MAGIC) =>
+        {
+            read_gzip(&mut BitReader::with_order(&mut reader, BitOrder::LsbFirst),
+                      &mut writer)?;
+        }
+        Mode::Uncompress =>
+        {
+            io::copy(&mut HuffmanDecoder::new(&mut reader)?, &mut writer)?;
+        }
+        Mode::Analyze => unreachable!("analyzed by run_analyze"),
+    }
+    writer.flush()?;
+    Ok((reader.get_ref().count, writer.count))
+}
+
+// counts the bytes that go through it, for the statistics
+struct Counted<T>
+{
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counted<T>
+{
+    fn new(inner: T) -> Self { Self { inner, count: 0 } }
+}
+
+impl<R: Read> Read for Counted<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Counted<W>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+// goes to stderr so it stays out of the way of output on stdout
+fn report(file: &str, mode: &Mode, read: u64, written: u64, elapsed: Duration)
+{
+    // the ratio is compressed to uncompressed and the throughput is of the uncompressed bytes,
+    // whichever way round the file went
+    let (compressed, uncompressed) = match mode
+    {
+        Mode::Uncompress => (read, written),
+        _ => (written, read),
+    };
+    let ratio = match uncompressed
+    {
+        0 => 0.0,
+        _ => 100.0 * compressed as f64 / uncompressed as f64,
+    };
+    let seconds = elapsed.as_secs_f64();
+    eprintln!("{}: {} -> {} bytes, {:.1}% in {:.3}s, {:.1} MB/s",
+              file,
+              read,
+              written,
+              ratio,
+              seconds,
+              uncompressed as f64 / 1e6 / seconds.max(1e-9));
+}
 
-        let args_incorrect = (mode != "-c" && mode != "-u") // first arg should be -c(compress) or -u(uncompress)
-                           || (!in_file.exists() || !in_file.is_file()) // second arg is input file, it should already exist and be a file
-                           || out_file.exists(); // third arg is output file, it should not exist, we don't do overwrites
+fn main() -> ExitCode
+{
+    let args = env::args().collect::<Vec<String>>();
+    let program = args.first().map_or("huffman", String::as_str);
 
-        if args_incorrect
+    let parsed = match parse_args(args.get(1..).unwrap_or_default())
+    {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) =>
         {
-            usage(&args);
-            Ok(())
+            usage(program);
+            return ExitCode::SUCCESS;
         }
-        // okay safe to proceed
-        else if mode == "-c"
+        Err(msg) =>
         {
-            compress(in_file, out_file)?;
-            println!("File successfully compressed");
-            Ok(())
+            eprintln!("{}: {}", program, msg);
+            eprintln!("try '{} --help' for more information", program);
+            return ExitCode::from(Failure::Usage as u8);
         }
-        else
+    };
+
+    // an archive is created or extracted in one go, everything else is a file at a time
+    let jobs = match parsed.archive
+    {
+        Some(ArchiveAction::Create) =>
+        {
+            vec![parsed.output
+                       .as_ref()
+                       .expect("checked by check_archive_args")
+                       .display()
+                       .to_string()]
+        }
+        Some(ArchiveAction::Extract) => vec![parsed.files[0].clone()],
+        _ => parsed.files.clone(),
+    };
+
+    // carry on with the rest after a file fails, the way gzip does
+    let mut failed = None;
+    for file in &jobs
+    {
+        if let Err((failure, error)) = run(&parsed, file)
+        {
+            eprintln!("{}: {}: {}", program, file, error);
+            failed.get_or_insert(failure);
+        }
+    }
+    failed.map_or(ExitCode::SUCCESS, |failure| ExitCode::from(failure as u8))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    struct TestFile
+    {
+        path: PathBuf,
+    }
+
+    impl TestFile
+    {
+        pub fn create(path: PathBuf) -> Self { Self { path } }
+    }
+
+    impl Drop for TestFile
+    {
+        fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+    }
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String>
+    {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_args()
+    {
+        let args = parse(&[]).unwrap().unwrap();
+        assert_eq!(args.mode, Mode::Compress(CompressOptions::default()));
+        assert_eq!(args.files, [STDIO]);
+
+        let args = parse(&["-u", "-f", "--rm", "-v", "a", "-", "--", "-b"]).unwrap()
+                                                                           .unwrap();
+        assert_eq!(args.mode, Mode::Uncompress);
+        assert!(args.force && args.remove && args.verbose && !args.stdout);
+        assert_eq!(args.files, ["a", "-", "-b"]);
+
+        let args = parse(&["-7", "-o", "out", "in"]).unwrap().unwrap();
+        assert_eq!(args.output, Some(PathBuf::from("out")));
+        assert!(matches!(args.mode,
+                         Mode::Compress(CompressOptions { codec: Codec::Lz77, .. })));
+
+        assert_eq!(parse(&["in", "--help"]).unwrap(), None);
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["-0"]).is_err());
+        assert!(parse(&["-o"]).is_err());
+        assert!(parse(&["-o", "out", "a", "b"]).is_err());
+        assert!(parse(&["-o", "out", "--stdout"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_range()
+    {
+        let args = parse(&["-u", "--range", "100-200", "-o", "a", "a.compressed"]).unwrap()
+                                                                                  .unwrap();
+        assert_eq!(args.range, Some(100..200));
+        assert!(parse(&["-u", "--range", "100-200", "--stdout", "a.compressed"]).is_ok());
+        assert_eq!(parse_range("5-"), Ok(5..u64::MAX));
+        assert_eq!(parse_range("0-0"), Ok(0..0));
+
+        assert!(parse_range("200-100").is_err());
+        assert!(parse_range("100").is_err());
+        assert!(parse_range("-100").is_err());
+        assert!(parse(&["-u", "--range"]).is_err());
+        assert!(parse(&["--range", "0-10", "a"]).is_err());
+        assert!(parse(&["-u", "--range", "0-10", "-"]).is_err());
+
+        // part of a file can't take the whole file's name, or see the input removed
+        assert!(parse(&["-u", "--range", "0-10", "a.compressed"]).is_err());
+        assert!(parse(&["-u", "--range", "0-10", "--rm", "-o", "a", "a.compressed"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_analyze_args()
+    {
+        let args = parse(&["--analyze", "--json", "a", "b.compressed"]).unwrap()
+                                                                       .unwrap();
+        assert_eq!(args.mode, Mode::Analyze);
+        assert_eq!(args.format, Some(AnalysisFormat::Json));
+        assert_eq!(args.files, ["a", "b.compressed"]);
+        let args = parse(&["--analyze", "--dot", "a"]).unwrap().unwrap();
+        assert_eq!(args.format, Some(AnalysisFormat::Dot));
+
+        assert!(parse(&["--analyze"]).is_err());
+        assert!(parse(&["--analyze", "-"]).is_err());
+        assert!(parse(&["--analyze", "-o", "out", "a"]).is_err());
+        assert!(parse(&["--analyze", "--rm", "a"]).is_err());
+        assert!(parse(&["--json", "a"]).is_err());
+        assert!(parse(&["--dot", "a"]).is_err());
+        assert!(parse(&["--analyze", "-l", "a.hufa"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_archive_args()
+    {
+        let args = parse(&["--archive", "-9", "-o", "out.hufa", "a", "dir"]).unwrap()
+                                                                            .unwrap();
+        assert_eq!(args.archive, Some(ArchiveAction::Create));
+        assert_eq!(args.files, ["a", "dir"]);
+
+        let args = parse(&["-x", "a.hufa", "dir/file"]).unwrap().unwrap();
+        assert_eq!(args.archive, Some(ArchiveAction::Extract));
+        assert_eq!(args.files, ["a.hufa", "dir/file"]);
+
+        assert!(parse(&["--archive", "a"]).is_err());
+        assert!(parse(&["--archive", "-z", "-o", "out.hufa", "a"]).is_err());
+        assert!(parse(&["-l"]).is_err());
+        assert!(parse(&["-t", "-"]).is_err());
+        assert!(parse(&["-l", "-o", "out", "a.hufa"]).is_err());
+        assert!(parse(&["-x", "--rm", "a.hufa"]).is_err());
+    }
+
+    #[test]
+    fn test_list_format()
+    {
+        assert_eq!(permissions(EntryKind::Directory, 0o755), "drwxr-xr-x");
+        assert_eq!(permissions(EntryKind::File, 0o640), "-rw-r-----");
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_000_000_000), "2001-09-09 01:46");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_time(-86_400), "1969-12-31 00:00");
+    }
+
+    #[test]
+    fn test_output_name()
+    {
+        let compress = Mode::Compress(CompressOptions::default());
+        assert_eq!(output_name(&compress, Path::new("a.txt")),
+                   Path::new("a.txt.compressed"));
+        assert_eq!(output_name(&Mode::Gzip(MatchOptions::default()), Path::new("a.txt")),
+                   Path::new("a.txt.gz"));
+        assert_eq!(output_name(&Mode::Uncompress, Path::new("a.txt.compressed")),
+                   Path::new("a.txt"));
+        assert_eq!(output_name(&Mode::Uncompress, Path::new("dir/a.txt.gz")),
+                   Path::new("dir/a.txt"));
+        assert_eq!(output_name(&Mode::Uncompress, Path::new("a.bin")),
+                   Path::new("a.bin.uncompressed"));
+        assert_eq!(output_name(&Mode::Uncompress, Path::new(".gz")),
+                   Path::new(".gz.uncompressed"));
+    }
+
+    #[test]
+    fn test_run()
+    {
+        let original = TestFile::create(PathBuf::from("kmd_CliRun.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_CliRun.txt.compressed"));
+        let _ = fs::remove_file(&compressed.path);
+        let contents = fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        fs::write(&original.path, &contents).expect("failed writing original");
+        let file = original.path.to_str().unwrap();
+
+        let args = parse(&["--rm", file]).unwrap().unwrap();
+        run(&args, file).expect("failed compressing");
+        assert!(!original.path.exists());
+
+        // back again, then again without --force which finds the file already there
+        let args = parse(&["-u", "-k", compressed.path.to_str().unwrap()]).unwrap()
+                                                                          .unwrap();
+        run(&args, compressed.path.to_str().unwrap()).expect("failed uncompressing");
+        assert_eq!(fs::read(&original.path).unwrap(), contents);
+        let (failure, _) = run(&args, compressed.path.to_str().unwrap()).unwrap_err();
+        assert_eq!(failure, Failure::OutputExists);
+
+        let args = parse(&["-u", "-f", compressed.path.to_str().unwrap()]).unwrap()
+                                                                          .unwrap();
+        run(&args, compressed.path.to_str().unwrap()).expect("failed overwriting");
+        assert_eq!(fs::read(&original.path).unwrap(), contents);
+        assert!(compressed.path.exists());
+
+        let (failure, _) = run(&args, "kmd_CliRunMissing.compressed").unwrap_err();
+        assert_eq!(failure, Failure::Input);
+    }
+
+    #[test]
+    fn test_run_force_onto_input()
+    {
+        let original = TestFile::create(PathBuf::from("kmd_CliSame.txt"));
+        fs::write(&original.path, b"Hello World!").expect("failed writing original");
+
+        // the same file spelled differently is still the input
+        let (failure, _) =
+            run(&parse(&["-f", "-o", "./kmd_CliSame.txt", "kmd_CliSame.txt"]).unwrap()
+                                                                             .unwrap(),
+                "kmd_CliSame.txt").unwrap_err();
+        assert_eq!(failure, Failure::Usage);
+        assert_eq!(fs::read(&original.path).unwrap(), b"Hello World!");
+
+        #[cfg(unix)]
         {
-            uncompress(in_file, out_file)?;
-            println!("File successfully uncompressed");
-            Ok(())
+            let link = TestFile::create(PathBuf::from("kmd_CliSameLink.txt"));
+            let _ = fs::remove_file(&link.path);
+            std::os::unix::fs::symlink("kmd_CliSame.txt", &link.path).expect("failed linking");
+            let (failure, _) =
+                run(&parse(&["-f", "-o", "kmd_CliSameLink.txt", "kmd_CliSame.txt"]).unwrap()
+                                                                                   .unwrap(),
+                    "kmd_CliSame.txt").unwrap_err();
+            assert_eq!(failure, Failure::Usage);
+            assert_eq!(fs::read(&original.path).unwrap(), b"Hello World!");
         }
     }
+
+    #[test]
+    fn test_run_force_keeps_output_on_failure()
+    {
+        let corrupt = TestFile::create(PathBuf::from("kmd_CliForceCorrupt.compressed"));
+        let output = TestFile::create(PathBuf::from("kmd_CliForceCorrupt"));
+        fs::write(&corrupt.path, b"not compressed at all").expect("failed writing corrupt");
+        fs::write(&output.path, b"already here").expect("failed writing output");
+
+        let file = corrupt.path.to_str().unwrap();
+        let (failure, _) = run(&parse(&["-u", "-f", file]).unwrap().unwrap(), file).unwrap_err();
+        assert_eq!(failure, Failure::Corrupt);
+        assert_eq!(fs::read(&output.path).unwrap(), b"already here");
+        let temp = format!(".kmd_CliForceCorrupt.{}.tmp", std::process::id());
+        assert!(!Path::new(&temp).exists());
+    }
+
+    #[test]
+    fn test_run_corrupt()
+    {
+        let corrupt = TestFile::create(PathBuf::from("kmd_CliCorrupt.compressed"));
+        let output = TestFile::create(PathBuf::from("kmd_CliCorrupt"));
+        let _ = fs::remove_file(&output.path);
+        fs::write(&corrupt.path, b"not compressed at all").expect("failed writing corrupt");
+
+        let file = corrupt.path.to_str().unwrap();
+        let (failure, _) = run(&parse(&["-u", file]).unwrap().unwrap(), file).unwrap_err();
+        assert_eq!(failure, Failure::Corrupt);
+        assert!(!output.path.exists());
+    }
 }
@@ -0,0 +1,71 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+
+// Apply f to every item on a pool of up to `threads` threads, the results come back in the same
+// order as the items
+//
+// Each thread takes the next item nobody has started on, so a slow item doesn't hold up the rest.
+// One thread, or a single item, runs on the calling thread.
+pub fn map<T: Sync, U: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> U + Sync) -> Vec<U>
+{
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1
+    {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<U>>>());
+
+    thread::scope(|scope| {
+        for _ in 0..threads
+        {
+            scope.spawn(|| {
+                     loop
+                     {
+                         let index = next.fetch_add(1, Ordering::Relaxed);
+                         let Some(item) = items.get(index)
+                         else
+                         {
+                             break;
+                         };
+                         let result = f(item);
+                         results.lock().expect("no worker panicked")[index] = Some(result);
+                     }
+                 });
+        }
+    });
+
+    results.into_inner()
+           .expect("no worker panicked")
+           .into_iter()
+           .map(|result| result.expect("every item was mapped"))
+           .collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_order_kept()
+    {
+        let items = (0..1000u64).collect::<Vec<_>>();
+        for threads in [1, 2, 8]
+        {
+            let squares = map(&items, threads, |item| item * item);
+            assert_eq!(squares,
+                       items.iter().map(|item| item * item).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_empty()
+    {
+        let items: [u8; 0] = [];
+        assert!(map(&items, 4, |item| *item).is_empty());
+    }
+}
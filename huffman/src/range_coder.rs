@@ -0,0 +1,383 @@
+use crate::file_lib::bit_reader::BitReader;
+use crate::file_lib::bit_writer::BitWriter;
+use crate::huffman_tree::read_value;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::ALPHABET_SIZE;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+// the scaled frequencies always add up to 1 << TOTAL_BITS, which leaves every symbol at least
+// 1 << 9 of the range once it's been brought back above TOP
+pub const TOTAL_BITS: u32 = 15;
+const TOTAL: u32 = 1 << TOTAL_BITS;
+
+// the range is shifted out a byte at a time whenever it drops below this
+const TOP: u32 = 1 << 24;
+
+// the encoder finishes by shifting out every byte of low, the decoder starts by reading as many
+const FLUSH_BYTES: usize = 5;
+
+// Frequencies for range coding a block, scaled so they add up to 1 << TOTAL_BITS
+//
+// Built from the same counts as the huffman code lengths, a symbol costs close to its share of the
+// counts in bits instead of a whole number of bits.
+//
+// written out as a bit per symbol saying whether it's used, and then the frequency - 1 of each
+// used symbol in TOTAL_BITS bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeModel
+{
+    frequencies: Vec<u32>,
+    // where each symbol's share of the total starts, with the total on the end
+    starts: Vec<u32>,
+}
+
+impl RangeModel
+{
+    // None if nothing was counted
+    pub fn new(counts: &FrequencyTable) -> Option<Self>
+    {
+        let total = counts.symbols().map(|(_, count)| count).sum::<u64>();
+        if total == 0
+        {
+            return None;
+        }
+
+        // every symbol seen keeps at least 1, whatever that adds or rounding down leaves over
+        // comes off or goes on the most frequent symbols
+        let mut frequencies = vec![0u32; ALPHABET_SIZE];
+        for (symbol, count) in counts.symbols()
+        {
+            frequencies[symbol as usize] =
+                ((count as u128 * TOTAL as u128 / total as u128) as u32).max(1);
+        }
+        let mut sum = frequencies.iter().sum::<u32>();
+        while sum != TOTAL
+        {
+            let largest = (0..ALPHABET_SIZE).max_by_key(|symbol| frequencies[*symbol])
+                                            .expect("the alphabet isn't empty");
+            if sum < TOTAL
+            {
+                frequencies[largest] += TOTAL - sum;
+                sum = TOTAL;
+            }
+            else
+            {
+                let take = (sum - TOTAL).min(frequencies[largest] - 1);
+                frequencies[largest] -= take;
+                sum -= take;
+            }
+        }
+
+        Some(Self::from_frequencies(frequencies))
+    }
+
+    fn from_frequencies(frequencies: Vec<u32>) -> Self
+    {
+        let mut starts = Vec::with_capacity(ALPHABET_SIZE + 1);
+        starts.push(0);
+        for frequency in &frequencies
+        {
+            starts.push(starts.last().unwrap() + frequency);
+        }
+        Self { frequencies, starts }
+    }
+
+    pub fn frequency(&self, symbol: u8) -> u32 { self.frequencies[symbol as usize] }
+
+    // number of bits write will use
+    pub fn size_bits(&self) -> usize
+    {
+        ALPHABET_SIZE
+        + self.frequencies
+              .iter()
+              .filter(|frequency| **frequency > 0)
+              .count()
+          * TOTAL_BITS as usize
+    }
+
+    // about how many bits the range coder takes for everything counted, None if a symbol has no
+    // frequency
+    pub fn coded_bits(&self, counts: &FrequencyTable) -> Option<usize>
+    {
+        let bits = counts.symbols()
+                         .map(|(symbol, count)| match self.frequencies[symbol as usize]
+                         {
+                             0 => None,
+                             frequency => Some(count as f64
+                                               * (TOTAL_BITS as f64 - (frequency as f64).log2())),
+                         })
+                         .sum::<Option<f64>>()?;
+        Some(bits.ceil() as usize + FLUSH_BYTES * 8)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        for frequency in &self.frequencies
+        {
+            writer.write_bits((*frequency > 0) as u64, 1)?;
+        }
+        for frequency in self.frequencies.iter().filter(|frequency| **frequency > 0)
+        {
+            writer.write_bits(*frequency as u64 - 1, TOTAL_BITS)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut BitReader<R>) -> Result<Self, Error>
+    {
+        let used = (0..ALPHABET_SIZE).map(|_| Ok(read_value(reader, 1)? == 1))
+                                     .collect::<Result<Vec<_>, Error>>()?;
+        let frequencies = used.into_iter()
+                              .map(|used| match used
+                              {
+                                  true => Ok(read_value(reader, TOTAL_BITS)? as u32 + 1),
+                                  false => Ok(0),
+                              })
+                              .collect::<Result<Vec<_>, Error>>()?;
+
+        if frequencies.iter().sum::<u32>() != TOTAL
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("range coder frequencies don't add up to {}", TOTAL)));
+        }
+        Ok(Self::from_frequencies(frequencies))
+    }
+
+    // range code every byte of the block and flush the coder, always at least FLUSH_BYTES bytes
+    pub fn encode<W: Write>(&self, writer: &mut BitWriter<W>, block: &[u8]) -> Result<(), Error>
+    {
+        let mut encoder = RangeEncoder::new();
+        for byte in block
+        {
+            encoder.encode(writer,
+                           self.starts[*byte as usize],
+                           self.frequencies[*byte as usize])?;
+        }
+        encoder.finish(writer)
+    }
+}
+
+// A range encoder that keeps a carry out of the top of low
+//
+// Bytes of low are held back while they're 0xFF, a carry could still ripple into them, and written
+// out once it's known whether it does.
+struct RangeEncoder
+{
+    low: u64,
+    range: u32,
+    // the byte held back, followed by `pending` - 1 bytes of 0xFF
+    cache: u8,
+    pending: u64,
+}
+
+impl RangeEncoder
+{
+    fn new() -> Self { Self { low: 0, range: u32::MAX, cache: 0, pending: 1 } }
+
+    fn encode<W: Write>(&mut self,
+                        writer: &mut BitWriter<W>,
+                        start: u32,
+                        frequency: u32)
+                        -> Result<(), Error>
+    {
+        self.range >>= TOTAL_BITS;
+        self.low += start as u64 * self.range as u64;
+        self.range *= frequency;
+        while self.range < TOP
+        {
+            self.range <<= 8;
+            self.shift_low(writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish<W: Write>(mut self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        for _ in 0..FLUSH_BYTES
+        {
+            self.shift_low(writer)?;
+        }
+        Ok(())
+    }
+
+    fn shift_low<W: Write>(&mut self, writer: &mut BitWriter<W>) -> Result<(), Error>
+    {
+        if self.low < 0xFF00_0000 || self.low > u32::MAX as u64
+        {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            for _ in 0..self.pending
+            {
+                writer.write_bits(byte.wrapping_add(carry) as u64, 8)?;
+                byte = 0xFF;
+            }
+            self.pending = 0;
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.pending += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+        Ok(())
+    }
+}
+
+// Decodes what RangeModel::encode wrote
+pub struct RangeDecoder
+{
+    model: RangeModel,
+    // the symbol for every value below the total
+    symbols: Vec<u8>,
+    code: u32,
+    range: u32,
+}
+
+impl RangeDecoder
+{
+    pub fn new(model: RangeModel) -> Self
+    {
+        let mut symbols = Vec::with_capacity(TOTAL as usize);
+        for (symbol, frequency) in model.frequencies.iter().enumerate()
+        {
+            symbols.resize(symbols.len() + *frequency as usize, symbol as u8);
+        }
+        Self { model, symbols, code: 0, range: u32::MAX }
+    }
+
+    // get ready for the codes of a block, which start the coder afresh
+    pub fn start<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<(), Error>
+    {
+        self.range = u32::MAX;
+        self.code = 0;
+        for _ in 0..FLUSH_BYTES
+        {
+            self.code = (self.code << 8) | read_value(reader, 8)? as u32;
+        }
+        Ok(())
+    }
+
+    pub fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<u8, Error>
+    {
+        self.range >>= TOTAL_BITS;
+        let value = (self.code / self.range).min(TOTAL - 1);
+        let symbol = self.symbols[value as usize];
+
+        self.code -= self.model.starts[symbol as usize] * self.range;
+        self.range *= self.model.frequencies[symbol as usize];
+        if self.code >= self.range
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "range coded data is corrupt"));
+        }
+
+        while self.range < TOP
+        {
+            self.code = (self.code << 8) | read_value(reader, 8)? as u32;
+            self.range <<= 8;
+        }
+        Ok(symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn counts(block: &[u8]) -> FrequencyTable
+    {
+        let mut counts = FrequencyTable::new();
+        block.iter().for_each(|byte| counts.add(*byte as u16));
+        counts
+    }
+
+    fn round_trip(block: &[u8]) -> Vec<u8>
+    {
+        let model = RangeModel::new(&counts(block)).unwrap();
+        let mut writer = BitWriter::new(Vec::new());
+        model.write(&mut writer).unwrap();
+        assert_eq!(writer.bits_written() as usize, model.size_bits());
+        model.encode(&mut writer, block).unwrap();
+        let encoded = writer.into_inner().unwrap();
+
+        let mut reader = BitReader::new(&encoded[..]);
+        let read = RangeModel::read(&mut reader).unwrap();
+        assert_eq!(read, model);
+        let mut decoder = RangeDecoder::new(read);
+        decoder.start(&mut reader).unwrap();
+        for byte in block
+        {
+            assert_eq!(decoder.decode(&mut reader).unwrap(), *byte);
+        }
+        encoded
+    }
+
+    #[test]
+    fn test_scaling()
+    {
+        // a rare symbol still gets a frequency, taken off the common one
+        let mut block = vec![b'a'; 1_000_000];
+        block.extend_from_slice(b"bc");
+        let model = RangeModel::new(&counts(&block)).unwrap();
+        assert_eq!(model.frequency(b'b'), 1);
+        assert_eq!(model.frequency(b'c'), 1);
+        assert_eq!(model.frequency(b'a'), TOTAL - 2);
+
+        let model = RangeModel::new(&counts(&(0..=255u8).collect::<Vec<_>>())).unwrap();
+        assert!((0..=255u8).all(|symbol| model.frequency(symbol) == TOTAL / 256));
+
+        assert_eq!(RangeModel::new(&FrequencyTable::new()), None);
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let encoded = round_trip(&text);
+        let model = RangeModel::new(&counts(&text)).unwrap();
+        let estimate = (model.size_bits() + model.coded_bits(&counts(&text)).unwrap()) / 8;
+        assert!(encoded.len().abs_diff(estimate) < 16);
+    }
+
+    #[test]
+    fn test_one_symbol()
+    {
+        // a symbol with the whole range costs next to nothing
+        let encoded = round_trip(&[b'x'; 10000]);
+        assert!(encoded.len() < 64);
+    }
+
+    #[test]
+    fn test_carry()
+    {
+        // long runs of the most likely symbol push low up against the top, random bytes after
+        // them make carries ripple through the bytes held back
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut block = Vec::new();
+        for _ in 0..2000
+        {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            block.extend(std::iter::repeat_n(0xFF, (state % 64) as usize));
+            block.push((state >> 8) as u8);
+        }
+        round_trip(&block);
+    }
+
+    #[test]
+    fn test_bad_frequencies()
+    {
+        let mut writer = BitWriter::new(Vec::new());
+        // only symbol 0 is used, and its frequency is short of the total
+        for used in 0..ALPHABET_SIZE
+        {
+            writer.write_bits((used == 0) as u64, 1).unwrap();
+        }
+        writer.write_bits(99, TOTAL_BITS).unwrap();
+        let encoded = writer.into_inner().unwrap();
+
+        let error = RangeModel::read(&mut BitReader::new(&encoded[..])).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,261 @@
+// Properties of compress and uncompress over random input
+//
+// Each property is checked on many inputs from a seeded generator, so a failure is the same on
+// every run and the case that failed is reported. Every input has to come back unchanged, and every
+// table of code lengths stored in the file has to satisfy the Kraft inequality and give a code to
+// every byte of the blocks that use it.
+
+use crate::compress::compress;
+use crate::compress::DEFAULT_BLOCK_LEN;
+use crate::container::BlockTable;
+use crate::file_lib::bit_reader::BitReader;
+use crate::huffman_tree::read_code_lengths;
+use crate::huffman_tree::FrequencyTable;
+use crate::huffman_tree::HuffmanTree;
+use crate::huffman_tree::MAX_CODE_LENGTH;
+use crate::uncompress::scan_blocks;
+use crate::uncompress::uncompress;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+struct TestFile
+{
+    path: PathBuf,
+}
+
+impl TestFile
+{
+    pub fn create(path: PathBuf) -> Self { Self { path } }
+}
+
+impl Drop for TestFile
+{
+    fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+}
+
+struct Rng
+{
+    state: u64,
+}
+
+impl Rng
+{
+    fn new(seed: u64) -> Self { Self { state: seed } }
+
+    fn next(&mut self) -> u64
+    {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // in low..high
+    fn below(&mut self, low: usize, high: usize) -> usize
+    {
+        low + (self.next() % (high - low) as u64) as usize
+    }
+}
+
+// the sum of 2^-len over the codes, scaled by 2^MAX_CODE_LENGTH so it's exact
+fn kraft_sum(lengths: &[u8]) -> u128
+{
+    lengths.iter()
+           .filter(|len| **len > 0)
+           .map(|len| 1u128 << (MAX_CODE_LENGTH - len))
+           .sum()
+}
+
+fn check_kraft(lengths: &[u8], case: &str)
+{
+    assert!(lengths.iter().all(|len| *len <= MAX_CODE_LENGTH),
+            "{}: a code is too long",
+            case);
+    let sum = kraft_sum(lengths);
+    assert!(sum <= 1 << MAX_CODE_LENGTH,
+            "{}: code lengths are over-subscribed",
+            case);
+
+    // the code space is filled exactly, except by a lone symbol with its single bit code
+    let used = lengths.iter().filter(|len| **len > 0).count();
+    if used > 1
+    {
+        assert_eq!(sum,
+                   1 << MAX_CODE_LENGTH,
+                   "{}: code lengths are incomplete",
+                   case);
+    }
+}
+
+// the code lengths each block of a compressed file is decoded with
+fn block_code_lengths(compressed: &[u8]) -> Vec<Vec<u8>>
+{
+    let mut reader = Cursor::new(compressed);
+    let blocks = scan_blocks(&mut reader).expect("failed scanning blocks");
+    let mut tables: Vec<Vec<u8>> = Vec::with_capacity(blocks.len());
+    for (index, block) in blocks.iter().enumerate()
+    {
+        let lengths = match block.header.table
+        {
+            BlockTable::Own =>
+            {
+                let mut reader = BitReader::new(&mut reader);
+                reader.seek_bits(8 * (block.offset + block.header.size() as u64))
+                      .expect("failed seeking to the code lengths");
+                read_code_lengths(&mut reader).expect("failed reading code lengths")
+            }
+            BlockTable::Reuse(table) =>
+            {
+                assert!((table as usize) < index,
+                        "block {} reuses a later block",
+                        index);
+                tables[table as usize].clone()
+            }
+        };
+        tables.push(lengths);
+    }
+    tables
+}
+
+// compress and uncompress contents through files, check it comes back the same and check the
+// code lengths in the compressed file
+fn check_round_trip(name: &str, case: usize, contents: &[u8])
+{
+    let case = format!("{} case {} of {} bytes", name, case, contents.len());
+    let original = TestFile::create(PathBuf::from(format!("kmd_{}.txt", name)));
+    let compressed = TestFile::create(PathBuf::from(format!("kmd_{}.compressed", name)));
+    let uncompressed = TestFile::create(PathBuf::from(format!("kmd_{}.uncompressed", name)));
+    std::fs::write(&original.path, contents).expect("failed writing original");
+
+    compress(&original.path, &compressed.path).expect("failed compressing");
+    uncompress(&compressed.path, &uncompressed.path).expect("failed uncompressing");
+    let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+    assert!(result == contents, "{}: didn't round trip", case);
+
+    let bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+    let tables = block_code_lengths(&bytes);
+    assert_eq!(tables.len(),
+               contents.chunks(DEFAULT_BLOCK_LEN).count(),
+               "{}",
+               case);
+    for (lengths, block) in tables.iter().zip(contents.chunks(DEFAULT_BLOCK_LEN))
+    {
+        check_kraft(lengths, &case);
+        assert!(block.iter().all(|byte| lengths[*byte as usize] > 0),
+                "{}: a byte has no code",
+                case);
+    }
+
+    // and the tree built straight from the counts, before anything is stored
+    let mut frequencies = FrequencyTable::new();
+    frequencies.add_bytes(contents);
+    if let Some(tree) = HuffmanTree::new(&frequencies)
+    {
+        check_kraft(&tree.code_lengths(), &case);
+    }
+}
+
+#[test]
+fn test_empty()
+{
+    check_round_trip("RoundTripEmpty", 0, b"");
+    assert!(HuffmanTree::new(&FrequencyTable::new()).is_none());
+}
+
+#[test]
+fn test_single_symbol()
+{
+    let mut rng = Rng::new(0x2545F4914F6CDD1D);
+    for case in 0..20
+    {
+        let symbol = rng.next() as u8;
+        let len = match case
+        {
+            0 => 1,
+            _ => rng.below(1, 5000),
+        };
+        let contents = vec![symbol; len];
+
+        // the tree is a lone leaf, which still gets a one bit code
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add_bytes(&contents);
+        let lengths = HuffmanTree::new(&frequencies).unwrap().code_lengths();
+        assert_eq!(lengths[symbol as usize], 1);
+        assert_eq!(lengths.iter().filter(|len| **len > 0).count(), 1);
+
+        check_round_trip("RoundTripSingle", case, &contents);
+    }
+}
+
+#[test]
+fn test_all_symbols()
+{
+    let mut rng = Rng::new(0x2545F4914F6CDD1D);
+    for case in 0..20
+    {
+        // every byte value once, shuffled in among random bytes
+        let mut contents = (0..=255).collect::<Vec<u8>>();
+        let len = rng.below(256, 20000);
+        while contents.len() < len
+        {
+            let at = rng.below(0, contents.len() + 1);
+            contents.insert(at, rng.next() as u8);
+        }
+        check_round_trip("RoundTripAllSymbols", case, &contents);
+    }
+}
+
+#[test]
+fn test_skewed()
+{
+    let mut rng = Rng::new(0x2545F4914F6CDD1D);
+    for case in 0..20
+    {
+        // symbol k comes up with probability 2^-(k+1), which gives a code of every length down to
+        // the rarest symbol, and every few cases one symbol has nearly all of the input
+        let dominant = case % 4 == 0;
+        let len = rng.below(2, 50000);
+        let contents = (0..len).map(|_| {
+                                   let random = rng.next();
+                                   match dominant
+                                   {
+                                       true if !random.is_multiple_of(1000) => b'e',
+                                       true => (random >> 32) as u8,
+                                       false => b'a' + random.trailing_zeros() as u8,
+                                   }
+                               })
+                               .collect::<Vec<_>>();
+        check_round_trip("RoundTripSkewed", case, &contents);
+    }
+}
+
+#[test]
+fn test_large()
+{
+    let mut rng = Rng::new(0x2545F4914F6CDD1D);
+    let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+    for case in 0..2
+    {
+        // several blocks of text, random bytes and runs, each stretch long enough to change the
+        // counts of the block it's in
+        let len = rng.below(3 * DEFAULT_BLOCK_LEN, 5 * DEFAULT_BLOCK_LEN);
+        let mut contents = Vec::with_capacity(len);
+        while contents.len() < len
+        {
+            let stretch = rng.below(1000, 100_000);
+            match rng.next() % 3
+            {
+                0 =>
+                {
+                    let start = rng.below(0, text.len() - 1000);
+                    let end = (start + stretch).min(text.len());
+                    contents.extend_from_slice(&text[start..end]);
+                }
+                1 => contents.extend((0..stretch).map(|_| rng.next() as u8)),
+                _ => contents.resize(contents.len() + stretch, rng.next() as u8),
+            }
+        }
+        contents.truncate(len);
+        check_round_trip("RoundTripLarge", case, &contents);
+    }
+}
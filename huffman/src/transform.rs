@@ -0,0 +1,346 @@
+use std::borrow::Cow;
+use std::io::Error;
+use std::io::ErrorKind;
+
+// after this many zeros in a row comes a byte with the number of zeros that follow them
+const ZERO_RUN: usize = 2;
+const MAX_ZERO_RUN: usize = ZERO_RUN + u8::MAX as usize;
+
+// Transforms run over each block before it's coded, and backwards after it's decoded
+//
+// Together they turn the contexts in text into long runs of the same byte, which the move to front
+// turns into runs of zeros and the zero run length encoding shortens, leaving something that codes
+// far better than the bytes did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Transforms
+{
+    // Burrows-Wheeler transform, sorts the bytes by what follows them
+    pub bwt: bool,
+    // each byte becomes its position in a list of the bytes most recently seen
+    pub mtf: bool,
+    // runs of zeros are shortened to ZERO_RUN zeros and a count
+    pub rle: bool,
+}
+
+// a block after the transforms, with where the BWT left the original if it was done
+pub struct TransformedBlock<'a>
+{
+    pub data: Cow<'a, [u8]>,
+    pub primary: u32,
+}
+
+impl Transforms
+{
+    pub fn all() -> Self { Self { bwt: true, mtf: true, rle: true } }
+
+    pub fn is_empty(&self) -> bool { !(self.bwt || self.mtf || self.rle) }
+
+    pub fn apply<'a>(&self, block: &'a [u8]) -> TransformedBlock<'a>
+    {
+        let mut transformed = TransformedBlock { data: Cow::Borrowed(block), primary: 0 };
+        if self.bwt
+        {
+            let (data, primary) = bwt(&transformed.data);
+            transformed = TransformedBlock { data: Cow::Owned(data), primary };
+        }
+        if self.mtf
+        {
+            transformed.data = Cow::Owned(move_to_front(&transformed.data));
+        }
+        if self.rle
+        {
+            transformed.data = Cow::Owned(zero_rle(&transformed.data));
+        }
+        transformed
+    }
+
+    // undo apply on a block of len bytes, InvalidData if the data couldn't have come from it
+    pub fn reverse(&self, mut data: Vec<u8>, primary: u32, len: usize) -> Result<Vec<u8>, Error>
+    {
+        if self.rle
+        {
+            data = undo_zero_rle(&data, len)?;
+        }
+        if self.mtf
+        {
+            data = undo_move_to_front(&data);
+        }
+        if self.bwt
+        {
+            data = undo_bwt(&data, primary)?;
+        }
+        Ok(data)
+    }
+}
+
+// The order of every rotation of the block, found by prefix doubling
+//
+// Rotations are ranked by their first k bytes, and then by their first 2k by pairing each one's
+// rank with the rank of the rotation k further on, until every rank is different or k covers the
+// whole block, in which case the rotations left with the same rank are equal.
+pub fn suffix_array(block: &[u8]) -> Vec<u32>
+{
+    let len = block.len();
+    let mut suffixes = (0..len as u32).collect::<Vec<_>>();
+    let mut ranks = block.iter().map(|byte| *byte as u32).collect::<Vec<_>>();
+    let mut next_ranks = vec![0u32; len];
+
+    if len == 0
+    {
+        return suffixes;
+    }
+
+    let mut k = 1;
+    loop
+    {
+        let key = |suffix: u32| (ranks[suffix as usize], ranks[(suffix as usize + k) % len]);
+        suffixes.sort_unstable_by_key(|suffix| key(*suffix));
+
+        next_ranks[suffixes[0] as usize] = 0;
+        for pair in suffixes.windows(2)
+        {
+            next_ranks[pair[1] as usize] =
+                next_ranks[pair[0] as usize] + (key(pair[0]) != key(pair[1])) as u32;
+        }
+        std::mem::swap(&mut ranks, &mut next_ranks);
+
+        if ranks[suffixes[len - 1] as usize] as usize == len - 1 || k >= len
+        {
+            break;
+        }
+        k *= 2;
+    }
+
+    suffixes
+}
+
+// the last byte of every rotation in sorted order, and the position of the block itself among them
+pub fn bwt(block: &[u8]) -> (Vec<u8>, u32)
+{
+    let suffixes = suffix_array(block);
+    let mut primary = 0;
+    let mut last = Vec::with_capacity(block.len());
+    for (row, suffix) in suffixes.iter().enumerate()
+    {
+        if *suffix == 0
+        {
+            primary = row as u32;
+        }
+        last.push(block[(*suffix as usize + block.len() - 1) % block.len()]);
+    }
+    (last, primary)
+}
+
+// Rebuild the block from the last bytes of its sorted rotations
+//
+// Sorting the last bytes, keeping equal bytes in order, gives the first bytes and where each row
+// goes next, starting from the row after the block itself the bytes come out in order.
+pub fn undo_bwt(last: &[u8], primary: u32) -> Result<Vec<u8>, Error>
+{
+    if last.is_empty()
+    {
+        return Ok(Vec::new());
+    }
+    if primary as usize >= last.len()
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("BWT primary index {} is past the end of the block",
+                                      primary)));
+    }
+
+    let mut starts = [0usize; 256];
+    for byte in last
+    {
+        starts[*byte as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut()
+    {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+
+    let mut next = vec![0u32; last.len()];
+    for (row, byte) in last.iter().enumerate()
+    {
+        next[starts[*byte as usize]] = row as u32;
+        starts[*byte as usize] += 1;
+    }
+
+    let mut block = Vec::with_capacity(last.len());
+    let mut row = next[primary as usize] as usize;
+    for _ in 0..last.len()
+    {
+        block.push(last[row]);
+        row = next[row] as usize;
+    }
+    Ok(block)
+}
+
+pub fn move_to_front(data: &[u8]) -> Vec<u8>
+{
+    let mut recent = (0..=255u8).collect::<Vec<_>>();
+    data.iter()
+        .map(|byte| {
+            let position = recent.iter()
+                                 .position(|recent| recent == byte)
+                                 .expect("every byte is in the list");
+            recent[..=position].rotate_right(1);
+            position as u8
+        })
+        .collect()
+}
+
+pub fn undo_move_to_front(data: &[u8]) -> Vec<u8>
+{
+    let mut recent = (0..=255u8).collect::<Vec<_>>();
+    data.iter()
+        .map(|position| {
+            let byte = recent[*position as usize];
+            recent[..=*position as usize].rotate_right(1);
+            byte
+        })
+        .collect()
+}
+
+pub fn zero_rle(data: &[u8]) -> Vec<u8>
+{
+    let mut encoded = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len()
+    {
+        let run = data[index..].iter()
+                               .take(MAX_ZERO_RUN)
+                               .take_while(|byte| **byte == 0)
+                               .count();
+        if run < ZERO_RUN
+        {
+            encoded.extend_from_slice(&data[index..index + run.max(1)]);
+            index += run.max(1);
+        }
+        else
+        {
+            encoded.extend_from_slice(&[0; ZERO_RUN]);
+            encoded.push((run - ZERO_RUN) as u8);
+            index += run;
+        }
+    }
+    encoded
+}
+
+// InvalidData as well if the runs would make it longer than max_len
+pub fn undo_zero_rle(data: &[u8], max_len: usize) -> Result<Vec<u8>, Error>
+{
+    let mut decoded = Vec::with_capacity((data.len() * 2).min(max_len));
+    let mut zeros = 0;
+    let mut bytes = data.iter();
+    while let Some(byte) = bytes.next()
+    {
+        decoded.push(*byte);
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+        if zeros == ZERO_RUN
+        {
+            let more = bytes.next()
+                            .ok_or_else(|| {
+                                Error::new(ErrorKind::InvalidData, "zero run is missing its length")
+                            })?;
+            decoded.resize(decoded.len() + *more as usize, 0);
+            zeros = 0;
+        }
+        if decoded.len() > max_len
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("zero runs give more than the {} bytes of the block",
+                                          max_len)));
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_bwt()
+    {
+        // the classic example, the rotations of banana sorted are abanan, anaban, ananab, banana,
+        // nabana and nanaba
+        assert_eq!(bwt(b"banana"), (b"nnbaaa".to_vec(), 3));
+        assert_eq!(undo_bwt(b"nnbaaa", 3).unwrap(), b"banana");
+    }
+
+    #[test]
+    fn test_bwt_repeats()
+    {
+        // rotations that are equal can come in any order
+        for block in [&b"abababab"[..], b"aaaaaaa", b"x", b"abcabcabcab"]
+        {
+            let (last, primary) = bwt(block);
+            assert_eq!(undo_bwt(&last, primary).unwrap(), block);
+        }
+        assert_eq!(undo_bwt(b"abc", 3).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_suffix_array()
+    {
+        // checked against sorting every rotation
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let block = &text[..2000];
+        let rotation = |start: u32| [&block[start as usize..], &block[..start as usize]].concat();
+        let mut expected = (0..block.len() as u32).collect::<Vec<_>>();
+        expected.sort_by_key(|start| rotation(*start));
+
+        let suffixes = suffix_array(block);
+        assert_eq!(suffixes.iter()
+                           .map(|start| rotation(*start))
+                           .collect::<Vec<_>>(),
+                   expected.iter()
+                           .map(|start| rotation(*start))
+                           .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_move_to_front()
+    {
+        assert_eq!(move_to_front(b"aaabbba"), [97, 0, 0, 98, 0, 0, 1]);
+        assert_eq!(undo_move_to_front(&[97, 0, 0, 98, 0, 0, 1]), b"aaabbba");
+    }
+
+    #[test]
+    fn test_zero_rle()
+    {
+        let mut data = vec![1, 0, 2, 0, 0, 3];
+        data.extend_from_slice(&[0; 300]);
+        data.push(4);
+        let encoded = zero_rle(&data);
+        assert_eq!(encoded, [1, 0, 2, 0, 0, 0, 3, 0, 0, 255, 0, 0, 41, 4]);
+        assert_eq!(undo_zero_rle(&encoded, data.len()).unwrap(), data);
+
+        assert_eq!(undo_zero_rle(&[1, 0, 0], 10).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+        assert_eq!(undo_zero_rle(&encoded, data.len() - 1).unwrap_err().kind(),
+                   ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        let text = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let transformed = Transforms::all().apply(&text);
+        assert_eq!(Transforms::all().reverse(transformed.data.to_vec(),
+                                             transformed.primary,
+                                             text.len())
+                                    .unwrap(),
+                   text);
+
+        // half of what the move to front gives is zeros, mostly in runs that get shortened
+        let moved = move_to_front(&bwt(&text).0);
+        assert!(moved.iter().filter(|byte| **byte == 0).count() > text.len() / 2);
+        assert!(transformed.data.len() < moved.len() * 4 / 5);
+    }
+}
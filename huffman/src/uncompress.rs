@@ -1,10 +1,1288 @@
+use crate::adaptive_tree::AdaptiveTree;
+use crate::container::BlockHeader;
+use crate::container::BlockTable;
+use crate::container::Codec;
+use crate::container::FormatError;
+use crate::container::Header;
+use crate::container::BLOCK_INDEX_FOOTER_LEN;
+use crate::container::BLOCK_INDEX_MAGIC;
+use crate::context_model::ContextDecoder;
+use crate::context_model::ContextTables;
+use crate::crc32::crc32;
+use crate::crc32::Crc32;
+use crate::file_lib::bit_reader::BitReader;
+use crate::huffman_tree::read_code_lengths;
+use crate::huffman_tree::read_value;
+use crate::huffman_tree::SymbolDecoder;
+use crate::lz77::Lz77Decoder;
+use crate::lz77::Lz77Tables;
+use crate::parallel;
+use crate::range_coder::RangeDecoder;
+use crate::range_coder::RangeModel;
+use crate::transform::Transforms;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct UncompressOptions
+{
+    // blocks are decoded on this many threads, files that aren't split into blocks use one
+    pub threads: usize,
+}
+
+impl Default for UncompressOptions
+{
+    fn default() -> Self { Self { threads: 1 } }
+}
+
 pub fn uncompress(in_file: &Path, out_file: &Path) -> Result<(), Error>
 {
-    // open the compressed in_file for reading
-    // deserialize the huffman tree from it
-    // then decompress the rest of the data and write it to out_file
-    todo!()
+    uncompress_with(in_file, out_file, &UncompressOptions::default())
+}
+
+pub fn uncompress_with(in_file: &Path,
+                       out_file: &Path,
+                       options: &UncompressOptions)
+                       -> Result<(), Error>
+{
+    // open the compressed in_file for reading, the header is checked before anything is written
+    let mut decoder = HuffmanDecoder::new(BufReader::new(File::open(in_file)?))?;
+
+    if options.threads > 1 && decoder.header().has_blocks()
+    {
+        let header = *decoder.header();
+        return write_output(out_file, |writer| {
+            uncompress_parallel(in_file, &header, options.threads, writer)
+        });
+    }
+
+    write_output(out_file, |writer| {
+        io::copy(&mut decoder, writer).map(|_| ())
+    })
+}
+
+// a file that fails to decode shouldn't be left behind looking like a valid result
+pub(crate) fn write_output(out_file: &Path,
+                           uncompress: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>)
+                           -> Result<(), Error>
+{
+    let result = File::create_new(out_file).and_then(|file| {
+                                               let mut writer = BufWriter::new(file);
+                                               uncompress(&mut writer)?;
+                                               writer.flush()
+                                           });
+    if result.is_err() && out_file.exists()
+    {
+        let _ = std::fs::remove_file(out_file);
+    }
+    result
+}
+
+// decode the blocks found through the block index a batch at a time, a few per thread, and write
+// them out in order
+fn uncompress_parallel(in_file: &Path,
+                       header: &Header,
+                       threads: usize,
+                       writer: &mut impl Write)
+                       -> Result<(), Error>
+{
+    let blocks = read_block_index(&mut BufReader::new(File::open(in_file)?))?;
+    let mut crc = Crc32::new();
+    let mut len = 0u64;
+
+    let indexes = (0..blocks.len()).collect::<Vec<_>>();
+    for batch in indexes.chunks(threads * 4)
+    {
+        let decoded = parallel::map(batch, threads, |index| {
+            let mut reader = BufReader::new(File::open(in_file)?);
+            read_block(&mut reader, &blocks, *index)
+        });
+        for data in decoded
+        {
+            let data = data?;
+            crc.update(&data);
+            len += data.len() as u64;
+            writer.write_all(&data)?;
+        }
+    }
+
+    if len != header.original_len
+    {
+        return Err(FormatError::LengthMismatch { expected: header.original_len, actual: len }.into());
+    }
+    if crc.finish() != header.crc32
+    {
+        return Err(FormatError::ChecksumMismatch { expected: header.crc32,
+                                                   actual: crc.finish() }.into());
+    }
+    Ok(())
+}
+
+// Uncompresses anything compress or a HuffmanEncoder wrote, reading it from the wrapped reader
+//
+// The header and code lengths are read when the decoder is created, the data is decoded as it's
+// read. The length and checksum are checked once the end is reached, a mismatch is returned as an
+// error from that read.
+pub struct HuffmanDecoder<R: Read>
+{
+    reader: BitReader<R>,
+    header: Header,
+    // None for an empty file, which has no code lengths
+    model: Option<Model>,
+    // symbols left before the end of the data, or of the current frame if the data is streamed
+    remaining: u64,
+    // where the decoder is up to for data split into blocks
+    blocks: Option<Blocks>,
+    crc: Crc32,
+    len: u64,
+    done: bool,
+}
+
+struct Blocks
+{
+    // the index of the next block
+    next: u32,
+    // the index of the block the current code lengths came from
+    table: Option<u32>,
+    // symbols left in the current block
+    remaining: u64,
+    crc: Crc32,
+    expected_crc: u32,
+    // a transformed block is decoded whole, and then its bytes are handed out from here
+    decoded: Option<std::vec::IntoIter<u8>>,
+}
+
+enum Model
+{
+    Fixed(SymbolDecoder),
+    Adaptive(AdaptiveTree),
+    Context(ContextDecoder),
+    Range(RangeDecoder),
+    Lz77(Lz77Decoder),
+}
+
+impl Model
+{
+    // the code lengths, or frequencies, at the start of a block
+    fn read_block<R: Read>(reader: &mut BitReader<R>, header: &Header) -> Result<Self, Error>
+    {
+        match header.codec
+        {
+            Codec::Range => Ok(Model::Range(RangeDecoder::new(RangeModel::read(reader)?))),
+            Codec::Lz77 => Ok(Model::Lz77(Lz77Decoder::new(&Lz77Tables::read(reader)?)?)),
+            Codec::Huffman if header.is_order1() =>
+            {
+                Ok(Model::Context(ContextDecoder::new(&ContextTables::read(reader)?)?))
+            }
+            Codec::Huffman =>
+            {
+                Ok(Model::Fixed(SymbolDecoder::from_lengths(&read_code_lengths(reader)?)?))
+            }
+        }
+    }
+
+    // get ready for the codes of a block, every block starts afresh whichever table it uses
+    fn start_block<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<(), Error>
+    {
+        match self
+        {
+            Model::Context(decoder) => decoder.reset(),
+            Model::Range(decoder) => decoder.start(reader)?,
+            Model::Lz77(decoder) => decoder.reset(),
+            Model::Fixed(_) | Model::Adaptive(_) => (),
+        }
+        Ok(())
+    }
+
+    fn decode<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<u8, Error>
+    {
+        match self
+        {
+            Model::Fixed(decoder) => Ok(decoder.decode(reader)? as u8),
+            Model::Adaptive(tree) => tree.decode(reader),
+            Model::Context(decoder) => decoder.decode(reader),
+            Model::Range(decoder) => decoder.decode(reader),
+            Model::Lz77(decoder) => decoder.decode(reader),
+        }
+    }
+}
+
+impl<R: Read> HuffmanDecoder<R>
+{
+    pub fn new(reader: R) -> Result<Self, Error>
+    {
+        let mut reader = BitReader::new(reader);
+        let header = Header::read(&mut reader)?;
+
+        // an empty file has nothing after the header, unless it was streamed in which case the
+        // length isn't known yet
+        let model = if header.is_adaptive()
+        {
+            Some(Model::Adaptive(AdaptiveTree::new()))
+        }
+        else if header.has_blocks()
+        {
+            // every block brings its own code lengths or reuses an earlier block's
+            None
+        }
+        else if header.original_len > 0 || header.is_streamed()
+        {
+            // the codes are rebuilt from the canonical code lengths
+            Some(Model::Fixed(SymbolDecoder::from_lengths(&read_code_lengths(&mut reader)?)?))
+        }
+        else
+        {
+            None
+        };
+
+        Ok(Self { reader,
+                  remaining: if header.is_streamed()
+                  {
+                      0
+                  }
+                  else
+                  {
+                      header.original_len
+                  },
+                  blocks: header.has_blocks().then(|| Blocks { next: 0,
+                                                               table: None,
+                                                               remaining: 0,
+                                                               crc: Crc32::new(),
+                                                               expected_crc: 0,
+                                                               decoded: None }),
+                  header,
+                  model,
+                  crc: Crc32::new(),
+                  len: 0,
+                  done: false })
+    }
+
+    pub fn header(&self) -> &Header { &self.header }
+
+    // move on to the next frame, or check the length and checksum when there are no more
+    // returns whether there's more to decode
+    fn next_frame(&mut self) -> Result<bool, Error>
+    {
+        if self.header.is_streamed()
+        {
+            self.remaining = read_value(&mut self.reader, 32)?;
+            if self.remaining > 0
+            {
+                return Ok(true);
+            }
+
+            let len = read_value(&mut self.reader, 64)?;
+            let crc = read_value(&mut self.reader, 32)? as u32;
+            if len != self.len
+            {
+                return Err(FormatError::LengthMismatch { expected: len, actual: self.len }.into());
+            }
+            self.header.original_len = len;
+            self.header.crc32 = crc;
+        }
+
+        self.done = true;
+        if self.crc.finish() != self.header.crc32
+        {
+            return Err(FormatError::ChecksumMismatch { expected: self.header.crc32,
+                                                       actual: self.crc.finish() }.into());
+        }
+        Ok(false)
+    }
+
+    // read the next block header and whatever code lengths come with it
+    fn next_block(&mut self) -> Result<(), Error>
+    {
+        let blocks = self.blocks.as_mut().expect("the data is split into blocks");
+        self.reader.align_to_byte();
+        let header = BlockHeader::read(&mut self.reader)?;
+        if header.is_end()
+        {
+            return Err(FormatError::LengthMismatch { expected: self.header.original_len,
+                                                     actual: self.len }.into());
+        }
+        if header.len as u64 > self.header.original_len - self.len
+        {
+            return Err(FormatError::LengthMismatch { expected: self.header.original_len,
+                                                     actual: self.len + header.len as u64 }.into());
+        }
+
+        match header.table
+        {
+            BlockTable::Own =>
+            {
+                self.model = Some(Model::read_block(&mut self.reader, &self.header)?);
+                blocks.table = Some(blocks.next);
+            }
+            BlockTable::Reuse(table) if blocks.table == Some(table) => (),
+            BlockTable::Reuse(table) =>
+            {
+                return Err(Error::new(ErrorKind::InvalidData,
+                                      format!("block {} reuses the code lengths of block {}, \
+                                               which aren't the last ones read",
+                                              blocks.next, table)));
+            }
+        }
+
+        let model = self.model.as_mut().expect("a block has a table");
+        let transforms = self.header.transforms();
+        blocks.decoded = match transforms.is_empty()
+        {
+            true =>
+            {
+                model.start_block(&mut self.reader)?;
+                None
+            }
+            false =>
+            {
+                let block = decode_block(model, &mut self.reader, transforms, header.len)?;
+                Some(block.into_iter())
+            }
+        };
+        blocks.next += 1;
+        blocks.remaining = header.len as u64;
+        blocks.crc = Crc32::new();
+        blocks.expected_crc = header.crc32;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for HuffmanDecoder<R>
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error>
+    {
+        let mut filled = 0;
+
+        while filled < buf.len() && !self.done
+        {
+            if self.remaining == 0
+            {
+                self.next_frame()?;
+                continue;
+            }
+            if self.blocks
+                   .as_ref()
+                   .is_some_and(|blocks| blocks.remaining == 0)
+            {
+                self.next_block()?;
+            }
+
+            let decoded = self.blocks
+                              .as_mut()
+                              .and_then(|blocks| blocks.decoded.as_mut());
+            let byte = match decoded
+            {
+                Some(decoded) => decoded.next()
+                                        .expect("the block is as long as its header says"),
+                None => self.model
+                            .as_mut()
+                            .expect("there are codes whenever there's data")
+                            .decode(&mut self.reader)?,
+            };
+            self.crc.update(&[byte]);
+            if let Some(blocks) = self.blocks.as_mut()
+            {
+                blocks.crc.update(&[byte]);
+                blocks.remaining -= 1;
+                if blocks.remaining == 0 && blocks.crc.finish() != blocks.expected_crc
+                {
+                    return Err(FormatError::ChecksumMismatch { expected: blocks.expected_crc,
+                                                               actual: blocks.crc.finish() }.into());
+                }
+            }
+            buf[filled] = byte;
+            filled += 1;
+            self.remaining -= 1;
+            self.len += 1;
+        }
+
+        Ok(filled)
+    }
+}
+
+// where a block starts in a compressed file, along with its header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockEntry
+{
+    pub offset: u64,
+    pub header: BlockHeader,
+}
+
+// find every block in data that's split into blocks, skipping from one block header to the next
+// without decoding anything
+pub fn scan_blocks<R: Read + Seek>(reader: &mut R) -> Result<Vec<BlockEntry>, Error>
+{
+    let header = check_blocks(reader)?;
+
+    let mut blocks = Vec::new();
+    let mut offset = header.size() as u64;
+    loop
+    {
+        reader.seek(SeekFrom::Start(offset))?;
+        let block = BlockHeader::read(&mut BitReader::new(&mut *reader))?;
+        if block.is_end()
+        {
+            return Ok(blocks);
+        }
+
+        blocks.push(BlockEntry { offset, header: block });
+        offset += (block.size() + block.compressed_len as usize) as u64;
+    }
+}
+
+// find every block from the block index at the end of the data, only the block headers are read
+pub fn read_block_index<R: Read + Seek>(reader: &mut R) -> Result<Vec<BlockEntry>, Error>
+{
+    check_blocks(reader)?;
+
+    let mut footer = [0u8; BLOCK_INDEX_FOOTER_LEN];
+    reader.seek(SeekFrom::End(-(BLOCK_INDEX_FOOTER_LEN as i64)))?;
+    reader.read_exact(&mut footer)?;
+    if footer[4..] != BLOCK_INDEX_MAGIC
+    {
+        return Err(Error::new(ErrorKind::InvalidData, "the block index is missing"));
+    }
+
+    let count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as i64;
+    reader.seek(SeekFrom::End(-(BLOCK_INDEX_FOOTER_LEN as i64) - 8 * count))?;
+    let mut offsets = vec![0u8; 8 * count as usize];
+    reader.read_exact(&mut offsets)?;
+
+    offsets.chunks_exact(8)
+           .map(|offset| {
+               let offset = u64::from_le_bytes(offset.try_into().unwrap());
+               reader.seek(SeekFrom::Start(offset))?;
+               let header = BlockHeader::read(&mut BitReader::new(&mut *reader))?;
+               if header.is_end()
+               {
+                   return Err(Error::new(ErrorKind::InvalidData,
+                                         format!("the block index points at the end, offset {}",
+                                                 offset)));
+               }
+               Ok(BlockEntry { offset, header })
+           })
+           .collect()
+}
+
+fn check_blocks<R: Read + Seek>(reader: &mut R) -> Result<Header, Error>
+{
+    reader.seek(SeekFrom::Start(0))?;
+    let header = Header::read(&mut BitReader::new(&mut *reader))?;
+    if !header.has_blocks()
+    {
+        return Err(Error::new(ErrorKind::InvalidInput, "the data isn't split into blocks"));
+    }
+    Ok(header)
+}
+
+// decode one block found by scan_blocks or read_block_index, only the block and the one with its
+// code lengths are read
+pub fn read_block<R: Read + Seek>(reader: &mut R,
+                                  blocks: &[BlockEntry],
+                                  index: usize)
+                                  -> Result<Vec<u8>, Error>
+{
+    let header = check_blocks(reader)?;
+    let block = blocks.get(index).ok_or_else(|| {
+                                      Error::new(ErrorKind::InvalidInput,
+                                                 format!("there's no block {}", index))
+                                  })?;
+    let table = match block.header.table
+    {
+        BlockTable::Own => Some(block),
+        BlockTable::Reuse(table) => blocks.get(table as usize)
+                                          .filter(|table| table.header.table == BlockTable::Own),
+    };
+    let table = table.ok_or_else(|| {
+                         Error::new(ErrorKind::InvalidData,
+                                    format!("block {} reuses a block without code lengths", index))
+                     })?;
+    if block.header.len as u64 > header.original_len
+    {
+        return Err(FormatError::LengthMismatch { expected: header.original_len,
+                                                 actual: block.header.len as u64 }.into());
+    }
+
+    // the code lengths come straight after the header of the block they're in, and the codes
+    // straight after them or the block header
+    let mut reader = BitReader::new(reader);
+    reader.seek_bits(8 * (table.offset + table.header.size() as u64))?;
+    let mut model = Model::read_block(&mut reader, &header)?;
+    if block.header.table != BlockTable::Own
+    {
+        reader.seek_bits(8 * (block.offset + block.header.size() as u64))?;
+    }
+    let data = decode_block(&mut model,
+                            &mut reader,
+                            header.transforms(),
+                            block.header.len)?;
+
+    let crc = crc32(&data);
+    if crc != block.header.crc32
+    {
+        return Err(FormatError::ChecksumMismatch { expected: block.header.crc32, actual: crc }.into());
+    }
+    Ok(data)
+}
+
+// Uncompress just the bytes in range into out_file, the range is cut short at the end of the data
+pub fn uncompress_range(in_file: &Path, out_file: &Path, range: Range<u64>) -> Result<(), Error>
+{
+    let mut reader = BufReader::new(File::open(in_file)?);
+    HuffmanDecoder::new(&mut reader)?;
+    write_output(out_file, |writer| {
+        copy_range(&mut reader, range, writer).map(|_| ())
+    })
+}
+
+// Write the bytes in range of what the compressed data uncompresses to, returns how many there were
+//
+// Data split into blocks only has the blocks the range covers decoded, found through the block
+// index. Anything else is decoded from the start with the bytes ahead of the range thrown away.
+// Either way the checksum of the whole data can't be checked, only those of the blocks decoded. A
+// range that ends where it starts, or before, is empty.
+pub fn copy_range<R: Read + Seek, W: Write>(reader: &mut R,
+                                            range: Range<u64>,
+                                            writer: &mut W)
+                                            -> Result<u64, Error>
+{
+    reader.seek(SeekFrom::Start(0))?;
+    let header = Header::read(&mut BitReader::new(&mut *reader))?;
+    if range.start >= range.end
+    {
+        return Ok(0);
+    }
+    if !header.has_blocks()
+    {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut decoder = HuffmanDecoder::new(BufReader::new(reader))?;
+        io::copy(&mut decoder.by_ref().take(range.start), &mut io::sink())?;
+        return io::copy(&mut decoder.take(range.end.saturating_sub(range.start)),
+                        writer);
+    }
+
+    let blocks = read_block_index(reader)?;
+    let mut start = 0u64;
+    let mut written = 0u64;
+    for (index, block) in blocks.iter().enumerate()
+    {
+        let end = start + block.header.len as u64;
+        if start >= range.end
+        {
+            break;
+        }
+        if end > range.start
+        {
+            let data = read_block(reader, &blocks, index)?;
+            let from = range.start.saturating_sub(start) as usize;
+            let to = (range.end.min(end) - start) as usize;
+            writer.write_all(&data[from..to])?;
+            written += (to - from) as u64;
+        }
+        start = end;
+    }
+    Ok(written)
+}
+
+// Decode the codes of a block, straight after its table, and undo its transforms
+//
+// A transformed block has the length of its codes and the BWT primary index first, which can't be
+// much more than the length of the block.
+fn decode_block<R: Read>(model: &mut Model,
+                         reader: &mut BitReader<R>,
+                         transforms: Transforms,
+                         len: u32)
+                         -> Result<Vec<u8>, Error>
+{
+    if transforms.is_empty()
+    {
+        model.start_block(reader)?;
+        return (0..len).map(|_| model.decode(reader)).collect();
+    }
+
+    let coded_len = read_value(reader, 32)?;
+    let primary = match transforms.bwt
+    {
+        true => read_value(reader, 32)? as u32,
+        false => 0,
+    };
+    if coded_len > 2 * len as u64 + 16
+    {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("a block of {} bytes can't have {} transformed bytes",
+                                      len, coded_len)));
+    }
+
+    model.start_block(reader)?;
+    let coded = (0..coded_len).map(|_| model.decode(reader))
+                              .collect::<Result<Vec<_>, Error>>()?;
+    let data = transforms.reverse(coded, primary, len as usize)?;
+    if data.len() != len as usize
+    {
+        return Err(FormatError::LengthMismatch { expected: len as u64,
+                                                 actual: data.len() as u64 }.into());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::compress::compress;
+    use crate::compress::compress_with;
+    use crate::compress::CompressOptions;
+    use crate::compress::HuffmanEncoder;
+    use crate::container::BLOCK_INDEX_FOOTER_LEN;
+    use crate::container::HEADER_LEN;
+    use crate::huffman_tree::code_lengths_size_bits;
+    use crate::huffman_tree::FrequencyTable;
+    use crate::huffman_tree::HuffmanTree;
+    use crate::lz77::MatchOptions;
+
+    struct TestFile
+    {
+        path: PathBuf,
+    }
+
+    impl TestFile
+    {
+        pub fn create(path: PathBuf) -> Self { Self { path } }
+    }
+
+    impl Drop for TestFile
+    {
+        fn drop(&mut self) { let _ = std::fs::remove_file(self.path.as_path()); }
+    }
+
+    fn round_trip(name: &str, contents: &[u8])
+    {
+        let original = TestFile::create(PathBuf::from(format!("kmd_{}.txt", name)));
+        let compressed = TestFile::create(PathBuf::from(format!("kmd_{}.compressed", name)));
+        let uncompressed = TestFile::create(PathBuf::from(format!("kmd_{}.uncompressed", name)));
+        std::fs::write(&original.path, contents).expect("failed writing original");
+
+        compress(&original.path, &compressed.path).expect("failed compressing");
+        uncompress(&compressed.path, &uncompressed.path).expect("failed uncompressing");
+
+        let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+        assert_eq!(result, contents);
+    }
+
+    #[test]
+    fn test_round_trip_asyoulik()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        round_trip("UncompressAsYouLik", &contents);
+    }
+
+    #[test]
+    fn test_round_trip_empty() { round_trip("UncompressEmpty", b""); }
+
+    #[test]
+    fn test_round_trip_single_symbol() { round_trip("UncompressSingle", b"aaaaaaaa"); }
+
+    #[test]
+    fn test_truncated()
+    {
+        let original = TestFile::create(PathBuf::from("kmd_UncompressTruncated.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressTruncated.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressTruncated.uncompressed"));
+        std::fs::write(&original.path, b"Hello World!").expect("failed writing original");
+        compress(&original.path, &compressed.path).expect("failed compressing");
+
+        let bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+        std::fs::write(&compressed.path, &bytes[..bytes.len() / 2]).expect("failed truncating");
+
+        let error = uncompress(&compressed.path, &uncompressed.path).unwrap_err();
+        assert_eq!(format_error(&error), Some(&FormatError::Truncated));
+        assert!(!uncompressed.path.exists());
+    }
+
+    fn format_error(error: &Error) -> Option<&FormatError>
+    {
+        error.get_ref()?.downcast_ref::<FormatError>()
+    }
+
+    #[test]
+    fn test_foreign_file()
+    {
+        // the checked in file predates the container format
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressForeign.uncompressed"));
+        let error =
+            uncompress(Path::new("asyoulik.txt.compressed"), &uncompressed.path).unwrap_err();
+        assert_eq!(format_error(&error), Some(&FormatError::BadMagic));
+        assert!(!uncompressed.path.exists());
+    }
+
+    #[test]
+    fn test_checksum_mismatch()
+    {
+        let original = TestFile::create(PathBuf::from("kmd_UncompressChecksum.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressChecksum.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressChecksum.uncompressed"));
+        std::fs::write(&original.path, b"Hello World!").expect("failed writing original");
+        compress(&original.path, &compressed.path).expect("failed compressing");
+
+        // corrupt the stored checksum
+        let mut bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+        bytes[14] ^= 0xFF;
+        std::fs::write(&compressed.path, &bytes).expect("failed corrupting");
+
+        let error = uncompress(&compressed.path, &uncompressed.path).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::ChecksumMismatch { .. })));
+        assert!(!uncompressed.path.exists());
+    }
+
+    #[test]
+    fn test_length_mismatch()
+    {
+        let original = TestFile::create(PathBuf::from("kmd_UncompressLength.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressLength.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressLength.uncompressed"));
+        std::fs::write(&original.path, b"Hello World!").expect("failed writing original");
+        compress(&original.path, &compressed.path).expect("failed compressing");
+        let bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+
+        // claiming the original was shorter is caught at the first block that's longer than it
+        let mut shorter = bytes.clone();
+        shorter[6] -= 1;
+        std::fs::write(&compressed.path, &shorter).expect("failed corrupting");
+        let error = uncompress(&compressed.path, &uncompressed.path).unwrap_err();
+        assert_eq!(format_error(&error),
+                   Some(&FormatError::LengthMismatch { expected: 11, actual: 12 }));
+
+        // claiming it was longer runs into the end of the blocks
+        let mut longer = bytes.clone();
+        longer[6] += 100;
+        std::fs::write(&compressed.path, &longer).expect("failed corrupting");
+        let error = uncompress(&compressed.path, &uncompressed.path).unwrap_err();
+        assert_eq!(format_error(&error),
+                   Some(&FormatError::LengthMismatch { expected: 112, actual: 12 }));
+    }
+
+    // a block header with its own code lengths
+    const BLOCK_HEADER_LEN: usize = 13;
+    // the end of the blocks and an index of one block
+    const TRAILER_LEN: usize = BLOCK_HEADER_LEN + 8 + BLOCK_INDEX_FOOTER_LEN;
+
+    // bits written for the contents: header, block header, code lengths and one code per byte
+    fn compressed_bits(contents: &[u8]) -> usize
+    {
+        let mut frequencies = FrequencyTable::new();
+        contents.iter()
+                .for_each(|byte| frequencies.add(*byte as u16));
+        let lengths = HuffmanTree::new(&frequencies).unwrap().code_lengths();
+        let data_bits = contents.iter()
+                                .map(|byte| lengths[*byte as usize] as usize)
+                                .sum::<usize>();
+        (HEADER_LEN + BLOCK_HEADER_LEN) * 8 + code_lengths_size_bits(&lengths) + data_bits
+    }
+
+    #[test]
+    fn test_every_pad_length()
+    {
+        let mut pads_seen = Vec::new();
+
+        // each extra 'a' adds exactly one bit, so eight lengths in a row cover every pad length
+        for a_count in 1..=8
+        {
+            let mut contents = vec![b'a'; a_count];
+            contents.push(b'b');
+            let pad = (8 - compressed_bits(&contents) % 8) % 8;
+            pads_seen.push(pad);
+
+            let name = format!("UncompressPad{}", pad);
+            let original = TestFile::create(PathBuf::from(format!("kmd_{}.txt", name)));
+            let compressed = TestFile::create(PathBuf::from(format!("kmd_{}.compressed", name)));
+            let uncompressed =
+                TestFile::create(PathBuf::from(format!("kmd_{}.uncompressed", name)));
+            std::fs::write(&original.path, &contents).expect("failed writing original");
+            compress(&original.path, &compressed.path).expect("failed compressing");
+
+            let mut bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+            assert_eq!((bytes.len() - TRAILER_LEN) * 8,
+                       compressed_bits(&contents) + pad);
+
+            // set the pad bits so they'd decode as extra symbols if they weren't ignored
+            let last = bytes.len() - TRAILER_LEN - 1;
+            bytes[last] |= ((1u16 << pad) - 1) as u8;
+            std::fs::write(&compressed.path, &bytes).expect("failed writing pad bits");
+
+            uncompress(&compressed.path, &uncompressed.path).expect("failed uncompressing");
+            let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+            assert_eq!(result, contents);
+        }
+
+        pads_seen.sort();
+        assert_eq!(pads_seen, (0..8).collect::<Vec<_>>());
+    }
+
+    fn compress_blocks(name: &str, contents: &[u8], block_len: usize) -> Vec<u8>
+    {
+        let original = TestFile::create(PathBuf::from(format!("kmd_{}.txt", name)));
+        let compressed = TestFile::create(PathBuf::from(format!("kmd_{}.compressed", name)));
+        std::fs::write(&original.path, contents).expect("failed writing original");
+        compress_with(&original.path,
+                      &compressed.path,
+                      &CompressOptions { block_len, ..Default::default() }).expect("failed compressing");
+        std::fs::read(&compressed.path).expect("failed reading compressed")
+    }
+
+    fn decode(compressed: &[u8]) -> Result<Vec<u8>, Error>
+    {
+        let mut result = Vec::new();
+        HuffmanDecoder::new(compressed)?.read_to_end(&mut result)?;
+        Ok(result)
+    }
+
+    #[test]
+    fn test_blocks()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let compressed = compress_blocks("UncompressBlocks", &contents, 4096);
+        assert_eq!(decode(&compressed).unwrap(), contents);
+
+        let mut reader = std::io::Cursor::new(&compressed);
+        let blocks = scan_blocks(&mut reader).unwrap();
+        assert_eq!(blocks.len(), contents.len().div_ceil(4096));
+        // blocks of the same text are close enough for some to share a table
+        assert!(blocks.iter()
+                      .any(|block| matches!(block.header.table, BlockTable::Reuse(_))));
+
+        // any block can be decoded on its own, last first so nothing before it has been read
+        for index in (0..blocks.len()).rev()
+        {
+            let block = read_block(&mut reader, &blocks, index).unwrap();
+            assert_eq!(block, contents.chunks(4096).nth(index).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_blocks_mixed_content()
+    {
+        // text followed by bytes that only use the low 3 bits
+        let mut contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut state = 0x2545F4914F6CDD1Du64;
+        for _ in 0..contents.len()
+        {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            contents.push(state as u8 & 0x07);
+        }
+
+        let blocks = compress_blocks("UncompressMixed", &contents, 64 * 1024);
+        let single = compress_blocks("UncompressMixedSingle", &contents, contents.len());
+        assert!(blocks.len() < single.len() * 9 / 10);
+        assert_eq!(decode(&blocks).unwrap(), contents);
+        assert_eq!(decode(&single).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_block_index()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let compressed = compress_blocks("UncompressBlockIndex", &contents, 4096);
+        let mut reader = std::io::Cursor::new(&compressed);
+        assert_eq!(read_block_index(&mut reader).unwrap(),
+                   scan_blocks(&mut reader).unwrap());
+
+        let empty = compress_blocks("UncompressBlockIndexEmpty", b"", 4096);
+        assert!(read_block_index(&mut std::io::Cursor::new(&empty)).unwrap()
+                                                                   .is_empty());
+
+        // a streamed file has no index
+        let streamed = encode_streamed(b"Hello World!");
+        let error = read_block_index(&mut std::io::Cursor::new(&streamed)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        let mut missing = compressed.clone();
+        missing.truncate(missing.len() - 1);
+        let error = read_block_index(&mut std::io::Cursor::new(&missing)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_copy_range()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut compressed = compress_blocks("UncompressCopyRange", &contents, 4096);
+        let range = |compressed: &[u8], range: Range<u64>| {
+            let mut result = Vec::new();
+            copy_range(&mut std::io::Cursor::new(compressed), range, &mut result).map(|_| result)
+        };
+
+        for (start, end) in [(0, 10),
+                             (4000, 9000),
+                             (8192, 8192),
+                             (4096, 8192),
+                             (120000, 200000)]
+        {
+            let expected = &contents[start.min(contents.len())..end.min(contents.len())];
+            assert_eq!(range(&compressed, start as u64..end as u64).unwrap(),
+                       expected);
+        }
+        assert_eq!(range(&compressed, 0..u64::MAX).unwrap(), contents);
+        assert!(range(&compressed, 200000..300000).unwrap().is_empty());
+        // a range ending before it starts is as empty as one ending where it starts
+        assert!(range(&compressed, 5000..5000).unwrap().is_empty());
+        assert!(range(&compressed, Range { start: 100, end: 50 }).unwrap()
+                                                                 .is_empty());
+        assert!(range(b"not compressed", Range { start: 100, end: 50 }).is_err());
+
+        // the first block is broken, only ranges that touch it notice
+        let blocks = read_block_index(&mut std::io::Cursor::new(&compressed)).unwrap();
+        compressed[blocks[1].offset as usize - 2] ^= 0xFF;
+        assert!(range(&compressed, 100..200).is_err());
+        assert_eq!(range(&compressed, 100000..100100).unwrap(),
+                   &contents[100000..100100]);
+
+        // without blocks everything ahead of the range is decoded
+        let streamed = encode_streamed(&contents);
+        assert_eq!(range(&streamed, 4000..9000).unwrap(), &contents[4000..9000]);
+        assert_eq!(range(&streamed, 125000..u64::MAX).unwrap(),
+                   &contents[125000..]);
+    }
+
+    #[test]
+    fn test_uncompress_range()
+    {
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressRangeFile.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressRangeFile.uncompressed"));
+        let _ = std::fs::remove_file(&compressed.path);
+        let _ = std::fs::remove_file(&uncompressed.path);
+        compress(Path::new("asyoulik.txt"), &compressed.path).expect("failed compressing");
+
+        uncompress_range(&compressed.path, &uncompressed.path, 500..1500).expect("failed range");
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        assert_eq!(std::fs::read(&uncompressed.path).expect("failed reading uncompressed"),
+                   &contents[500..1500]);
+    }
+
+    #[test]
+    fn test_parallel_uncompress()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressParallel.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressParallel.uncompressed"));
+        std::fs::write(&compressed.path,
+                       compress_blocks("UncompressParallel", &contents, 4096)).expect("failed writing");
+
+        uncompress_with(&compressed.path,
+                        &uncompressed.path,
+                        &UncompressOptions { threads: 4 }).expect("failed uncompressing");
+        let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+        assert_eq!(result, contents);
+    }
+
+    #[test]
+    fn test_range_codec()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let original = TestFile::create(PathBuf::from("kmd_UncompressRange.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressRange.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressRange.uncompressed"));
+        std::fs::write(&original.path, &contents).expect("failed writing original");
+        let options =
+            CompressOptions { block_len: 16384, codec: Codec::Range, ..Default::default() };
+        compress_with(&original.path, &compressed.path, &options).expect("failed compressing");
+
+        // picked from the header, whether decoding in order or a block at a time
+        for threads in [1, 4]
+        {
+            uncompress_with(&compressed.path,
+                            &uncompressed.path,
+                            &UncompressOptions { threads }).expect("failed uncompressing");
+            let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+            assert_eq!(result, contents);
+            std::fs::remove_file(&uncompressed.path).expect("failed removing uncompressed");
+        }
+
+        let range = std::fs::read(&compressed.path).expect("failed reading compressed");
+        let huffman = compress_blocks("UncompressRangeHuffman", &contents, 16384);
+        assert!(range.len() < huffman.len());
+    }
+
+    #[test]
+    fn test_transforms()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let original = TestFile::create(PathBuf::from("kmd_UncompressTransforms.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressTransforms.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressTransforms.uncompressed"));
+        std::fs::write(&original.path, &contents).expect("failed writing original");
+        let plain = compress_blocks("UncompressTransformsPlain", &contents, 65536);
+
+        for codec in [Codec::Huffman, Codec::Range]
+        {
+            let options = CompressOptions { block_len: 65536,
+                                            codec,
+                                            transforms: Transforms::all(),
+                                            ..Default::default() };
+            compress_with(&original.path, &compressed.path, &options).expect("failed compressing");
+            let bytes = std::fs::read(&compressed.path).expect("failed reading compressed");
+            assert!(bytes.len() < plain.len() * 2 / 3);
+
+            for threads in [1, 4]
+            {
+                uncompress_with(&compressed.path,
+                                &uncompressed.path,
+                                &UncompressOptions { threads }).expect("failed uncompressing");
+                let result =
+                    std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+                assert_eq!(result, contents);
+                std::fs::remove_file(&uncompressed.path).expect("failed removing uncompressed");
+            }
+            std::fs::remove_file(&compressed.path).expect("failed removing compressed");
+        }
+
+        // each transform can be done without the others
+        for transforms in [Transforms { bwt: true, ..Default::default() },
+                           Transforms { mtf: true, ..Default::default() },
+                           Transforms { rle: true, ..Default::default() }]
+        {
+            let options = CompressOptions { block_len: 65536, transforms, ..Default::default() };
+            let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+            encoder.write_all(&contents).expect("failed writing");
+            assert_eq!(decode(&encoder.finish().unwrap()).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn test_transformed_primary_corrupt()
+    {
+        let contents = b"abracadabra, abracadabra".repeat(10);
+        let options = CompressOptions { transforms: Transforms::all(), ..Default::default() };
+        let mut encoder = HuffmanEncoder::with_options(Vec::new(), &options).unwrap();
+        encoder.write_all(&contents).expect("failed writing");
+        let mut compressed = encoder.finish().unwrap();
+
+        // the primary index comes after the code lengths and the length of the codes
+        let lengths =
+            read_code_lengths(&mut BitReader::new(&compressed[HEADER_LEN + 13..])).unwrap();
+        let primary = HEADER_LEN * 8 + 13 * 8 + code_lengths_size_bits(&lengths) + 32;
+        for bit in primary..primary + 32
+        {
+            compressed[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        let error = decode(&compressed).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_lz77()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let original = TestFile::create(PathBuf::from("kmd_UncompressLz77.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressLz77.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressLz77.uncompressed"));
+        std::fs::write(&original.path, &contents).expect("failed writing original");
+        let plain = compress_blocks("UncompressLz77Plain", &contents, 65536);
+
+        let mut sizes = Vec::new();
+        for matching in [MatchOptions::level(1),
+                         MatchOptions::level(9),
+                         MatchOptions { window: 256, ..Default::default() }]
+        {
+            let options = CompressOptions { block_len: 65536,
+                                            codec: Codec::Lz77,
+                                            matching,
+                                            ..Default::default() };
+            compress_with(&original.path, &compressed.path, &options).expect("failed compressing");
+            sizes.push(std::fs::read(&compressed.path).expect("failed reading compressed")
+                                                      .len());
+
+            for threads in [1, 4]
+            {
+                uncompress_with(&compressed.path,
+                                &uncompressed.path,
+                                &UncompressOptions { threads }).expect("failed uncompressing");
+                let result =
+                    std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+                assert_eq!(result, contents);
+                std::fs::remove_file(&uncompressed.path).expect("failed removing uncompressed");
+            }
+            std::fs::remove_file(&compressed.path).expect("failed removing compressed");
+        }
+
+        assert!(sizes[0] < plain.len() * 3 / 4);
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] > sizes[0]);
+    }
+
+    #[test]
+    fn test_version1()
+    {
+        // a version 1 file is the same without the codec byte
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut compressed = compress_blocks("UncompressVersion1", &contents, 16384);
+        compressed[4] = 1;
+        compressed.remove(HEADER_LEN - 1);
+        assert_eq!(decode(&compressed).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_parallel_uncompress_order1()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let original = TestFile::create(PathBuf::from("kmd_UncompressOrder1.txt"));
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressOrder1.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressOrder1.uncompressed"));
+        std::fs::write(&original.path, &contents).expect("failed writing original");
+        let options = CompressOptions { block_len: 16384, order1: true, ..Default::default() };
+        compress_with(&original.path, &compressed.path, &options).expect("failed compressing");
+
+        for threads in [1, 4]
+        {
+            uncompress_with(&compressed.path,
+                            &uncompressed.path,
+                            &UncompressOptions { threads }).expect("failed uncompressing");
+            let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+            assert_eq!(result, contents);
+            std::fs::remove_file(&uncompressed.path).expect("failed removing uncompressed");
+        }
+    }
+
+    #[test]
+    fn test_parallel_uncompress_corrupt()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressParallelBad.compressed"));
+        let uncompressed =
+            TestFile::create(PathBuf::from("kmd_UncompressParallelBad.uncompressed"));
+        let options = UncompressOptions { threads: 4 };
+
+        // a block's checksum
+        let mut bytes = compress_blocks("UncompressParallelBad", &contents, 4096);
+        bytes[HEADER_LEN + 4] ^= 0xFF;
+        std::fs::write(&compressed.path, &bytes).expect("failed writing");
+        let error = uncompress_with(&compressed.path, &uncompressed.path, &options).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::ChecksumMismatch { .. })));
+        assert!(!uncompressed.path.exists());
+
+        // the length in the header
+        bytes[HEADER_LEN + 4] ^= 0xFF;
+        bytes[6] += 1;
+        std::fs::write(&compressed.path, &bytes).expect("failed writing");
+        let error = uncompress_with(&compressed.path, &uncompressed.path, &options).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::LengthMismatch { .. })));
+        assert!(!uncompressed.path.exists());
+    }
+
+    #[test]
+    fn test_block_checksum_mismatch()
+    {
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        let mut compressed = compress_blocks("UncompressBlockChecksum", &contents, 4096);
+
+        // the first block's checksum
+        compressed[HEADER_LEN + 4] ^= 0xFF;
+        let error = decode(&compressed).unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::ChecksumMismatch { .. })));
+
+        let mut reader = std::io::Cursor::new(&compressed);
+        let blocks = scan_blocks(&mut reader).unwrap();
+        assert!(read_block(&mut reader, &blocks, 0).is_err());
+        assert_eq!(read_block(&mut reader, &blocks, 1).unwrap(),
+                   &contents[4096..8192]);
+    }
+
+    #[test]
+    fn test_block_reuses_missing_table()
+    {
+        let mut compressed = compress_blocks("UncompressBlockMissing", b"Hello World!", 4096);
+        // point the only block at a table that was never read
+        compressed[HEADER_LEN + 12] = 1;
+        compressed.splice(HEADER_LEN + 13..HEADER_LEN + 13, [5, 0, 0, 0]);
+
+        let error = decode(&compressed).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    fn encode_streamed(contents: &[u8]) -> Vec<u8>
+    {
+        let mut encoder = HuffmanEncoder::with_static_model(Vec::new()).unwrap();
+        encoder.write_all(contents).expect("failed writing");
+        encoder.finish().expect("failed finishing")
+    }
+
+    #[test]
+    fn test_decoder_small_reads()
+    {
+        let encoded = encode_streamed(b"Hello World!");
+        let mut decoder = HuffmanDecoder::new(&encoded[..]).unwrap();
+
+        let mut result = Vec::new();
+        let mut buf = [0u8; 5];
+        loop
+        {
+            let read = decoder.read(&mut buf).expect("failed reading");
+            if read == 0
+            {
+                break;
+            }
+            result.extend_from_slice(&buf[..read]);
+        }
+        assert_eq!(result, b"Hello World!");
+        assert_eq!(decoder.header().original_len, 12);
+    }
+
+    #[test]
+    fn test_streamed_checksum_mismatch()
+    {
+        let mut encoded = encode_streamed(b"Hello World!");
+        // the checksum is the last thing written, flip a bit in it
+        let last = encoded.len() - 2;
+        encoded[last] ^= 0x01;
+
+        let mut result = Vec::new();
+        let error = HuffmanDecoder::new(&encoded[..]).unwrap()
+                                                     .read_to_end(&mut result)
+                                                     .unwrap_err();
+        assert!(matches!(format_error(&error),
+                         Some(FormatError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_streamed_truncated()
+    {
+        let encoded = encode_streamed(b"Hello World!");
+        let truncated = &encoded[..encoded.len() - 4];
+
+        let mut result = Vec::new();
+        let error = HuffmanDecoder::new(truncated).unwrap()
+                                                  .read_to_end(&mut result)
+                                                  .unwrap_err();
+        assert_eq!(format_error(&error), Some(&FormatError::Truncated));
+    }
+
+    #[test]
+    fn test_uncompress_streamed_file()
+    {
+        let compressed = TestFile::create(PathBuf::from("kmd_UncompressStreamed.compressed"));
+        let uncompressed = TestFile::create(PathBuf::from("kmd_UncompressStreamed.uncompressed"));
+        let contents = std::fs::read("asyoulik.txt").expect("failed reading asyoulik.txt");
+        std::fs::write(&compressed.path, encode_streamed(&contents)).expect("failed writing");
+
+        uncompress(&compressed.path, &uncompressed.path).expect("failed uncompressing");
+        let result = std::fs::read(&uncompressed.path).expect("failed reading uncompressed");
+        assert_eq!(result, contents);
+    }
 }